@@ -1,10 +1,18 @@
 use std::collections::HashMap;
+use std::fmt;
+
+use crate::types::errors::LinkError;
+use crate::utils::find_seg_start;
 
 pub const MAP_FILE_NAME: &str = "MAP";
+pub const WEAK_SYMBOL_PREFIX: &str = "weak:"; // on DirLib MAP symbols
 pub const MAGIC_NUMBER_LIB: &str = "LIBRARY";
 pub const STUB_MAGIC_NUMBER: &str = "STUB";
 pub const LIB_NAME_FILE: &str = "LIBRARY NAME";
 pub const SHARED_LIBS_SYMBOL: &str = "_SHARED_LIBRARIES";
+pub const DEFSYM_OBJECT_ID: &str = "_DEFSYM";
+pub const COMMON_BLOCK_OBJECT_ID: &str = "_COMMON";
+pub const GOT_SYMBOL: &str = "_GLOBAL_OFFSET_TABLE_";
 
 pub type LibName = String;
 pub type StubMemberName = String;
@@ -12,10 +20,60 @@ pub type ObjectID = String;
 
 pub type Address = i32;
 
-#[derive(Debug, Clone)]
+// Boundary a segment start is rounded up to. None packs the segment right
+// after the previous one; Bytes(n) is only valid for a power of two n (so
+// not for 0). Numeric boundaries of 0 come in as None, see TryFrom<i32>.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Alignment {
+    None,
+    Bytes(u32),
+}
+
+impl Alignment {
+    pub fn is_valid(&self) -> bool {
+        match self {
+            Alignment::None => true,
+            Alignment::Bytes(n) => n.is_power_of_two(),
+        }
+    }
+
+    pub fn align(&self, addr: Address) -> Address {
+        match self {
+            Alignment::None => addr,
+            Alignment::Bytes(n) => find_seg_start(addr, *n as i32),
+        }
+    }
+}
+
+impl TryFrom<i32> for Alignment {
+    type Error = LinkError;
+
+    fn try_from(n: i32) -> Result<Self, Self::Error> {
+        match n {
+            0 => Ok(Alignment::None),
+            n if n > 0 && (n as u32).is_power_of_two() => Ok(Alignment::Bytes(n as u32)),
+            n => Err(LinkError::InvalidAlignment(n)),
+        }
+    }
+}
+
+impl fmt::Display for Alignment {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Alignment::None => write!(f, "none"),
+            Alignment::Bytes(n) => write!(f, "{n:X}"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
 pub enum DefnProvenance {
     FromObjectIn,
     FromSharedLib(LibName),
+    FromDefSym,      // injected at link time via LinkerEditor::define_symbol
+    FromCommonBlock, // allocated in BSS by common block allocation
+    FromGot,         // GOT_SYMBOL, the start of the GOT
+    FromLibSegment,  // SHARED_LIBS_SYMBOL, the start of the .lib segment
 }
 #[derive(Debug, Clone)]
 pub struct Defn {
@@ -43,5 +101,88 @@ impl Defn {
             defn_prov: DefnProvenance::FromSharedLib(libname),
         }
     }
+
+    pub fn defsym_defn(addr: i32) -> Self {
+        Defn {
+            defn_mod_id: DEFSYM_OBJECT_ID.to_string(),
+            defn_ste_ix: None,
+            defn_addr: Some(addr),
+            defn_prov: DefnProvenance::FromDefSym,
+        }
+    }
+
+    pub fn got_defn(addr: i32) -> Self {
+        Defn {
+            defn_mod_id: GOT_SYMBOL.to_string(),
+            defn_ste_ix: None,
+            defn_addr: Some(addr),
+            defn_prov: DefnProvenance::FromGot,
+        }
+    }
+
+    pub fn shared_libs_defn(addr: i32) -> Self {
+        Defn {
+            defn_mod_id: SHARED_LIBS_SYMBOL.to_string(),
+            defn_ste_ix: None,
+            defn_addr: Some(addr),
+            defn_prov: DefnProvenance::FromLibSegment,
+        }
+    }
+
+    pub fn common_block_defn(addr: i32) -> Self {
+        Defn {
+            defn_mod_id: COMMON_BLOCK_OBJECT_ID.to_string(),
+            defn_ste_ix: None,
+            defn_addr: Some(addr),
+            defn_prov: DefnProvenance::FromCommonBlock,
+        }
+    }
+}
+impl fmt::Display for DefnProvenance {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DefnProvenance::FromObjectIn => write!(f, "object"),
+            DefnProvenance::FromSharedLib(libname) => write!(f, "shared lib {libname}"),
+            DefnProvenance::FromDefSym => write!(f, "defsym"),
+            DefnProvenance::FromCommonBlock => write!(f, "common block"),
+            DefnProvenance::FromGot => write!(f, "GOT"),
+            DefnProvenance::FromLibSegment => write!(f, "shared libraries list"),
+        }
+    }
 }
+
 pub type Refs = HashMap<ObjectID, usize>;
+
+// Entry of the global symbol table: the definition, if spotted yet, and the
+// modules referring to the symbol, with its index in their symbol tables
+#[derive(Debug, Clone, Default)]
+pub struct GlobalSymbol {
+    pub defn: Option<Defn>,
+    pub refs: Refs,
+}
+
+impl GlobalSymbol {
+    pub fn new(defn: Option<Defn>, refs: Refs) -> Self {
+        GlobalSymbol { defn, refs }
+    }
+
+    pub fn defined(defn: Defn) -> Self {
+        GlobalSymbol::new(Some(defn), HashMap::new())
+    }
+
+    pub fn is_defined(&self) -> bool {
+        self.defn.is_some()
+    }
+
+    // None if undefined or not resolved yet
+    pub fn address(&self) -> Option<i32> {
+        self.defn.as_ref().and_then(|defn| defn.defn_addr)
+    }
+
+    // in name order
+    pub fn referencing_modules(&self) -> Vec<&ObjectID> {
+        let mut mods: Vec<_> = self.refs.keys().collect();
+        mods.sort();
+        mods
+    }
+}
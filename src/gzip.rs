@@ -0,0 +1,284 @@
+// Just enough gzip (RFC 1952) to read compressed objects: a single member,
+// DEFLATE (RFC 1951) compressed, checked against its CRC-32 and length.
+use crate::types::errors::ParseError;
+use crate::utils::crc32;
+
+pub const GZIP_MAGIC: [u8; 2] = [0x1F, 0x8B];
+
+// header flags
+const FHCRC: u8 = 0x02;
+const FEXTRA: u8 = 0x04;
+const FNAME: u8 = 0x08;
+const FCOMMENT: u8 = 0x10;
+
+const MAX_BITS: usize = 15;
+
+// base values and extra bits of length codes 257..285 and distance codes 0..29
+const LEN_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+const LEN_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+const DIST_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DIST_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+// order the code length code lengths come in
+const CL_ORDER: [usize; 19] = [
+    16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
+];
+
+pub fn is_gzip(bytes: &[u8]) -> bool {
+    bytes.starts_with(&GZIP_MAGIC)
+}
+
+pub fn gunzip(bytes: &[u8]) -> Result<Vec<u8>, ParseError> {
+    // magic, method (8 is deflate), flags, mtime, xfl, os
+    if bytes.len() < 18 || !is_gzip(bytes) || bytes[2] != 8 {
+        return Err(ParseError::InvalidGzip);
+    }
+    let flags = bytes[3];
+    let mut pos = 10;
+    if flags & FEXTRA != 0 {
+        let xlen = u16::from_le_bytes([
+            *bytes.get(pos).ok_or(ParseError::InvalidGzip)?,
+            *bytes.get(pos + 1).ok_or(ParseError::InvalidGzip)?,
+        ]);
+        pos += 2 + xlen as usize;
+    }
+    // zero terminated file name and comment
+    for flag in [FNAME, FCOMMENT] {
+        if flags & flag != 0 {
+            let rest = bytes.get(pos..).ok_or(ParseError::InvalidGzip)?;
+            pos += rest
+                .iter()
+                .position(|&b| b == 0)
+                .ok_or(ParseError::InvalidGzip)?
+                + 1;
+        }
+    }
+    if flags & FHCRC != 0 {
+        pos += 2;
+    }
+    if pos + 8 > bytes.len() {
+        return Err(ParseError::InvalidGzip);
+    }
+    let mut input = BitReader::new(&bytes[pos..bytes.len() - 8]);
+    let data = inflate(&mut input)?;
+    let trailer = &bytes[bytes.len() - 8..];
+    let crc = u32::from_le_bytes([trailer[0], trailer[1], trailer[2], trailer[3]]);
+    let isize = u32::from_le_bytes([trailer[4], trailer[5], trailer[6], trailer[7]]);
+    if crc != crc32(&data) || isize != data.len() as u32 {
+        return Err(ParseError::InvalidGzip);
+    }
+    Ok(data)
+}
+
+// DEFLATE bits come least significant first
+struct BitReader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+    bit: u8,
+}
+
+impl<'a> BitReader<'a> {
+    fn new(bytes: &'a [u8]) -> Self {
+        BitReader {
+            bytes,
+            pos: 0,
+            bit: 0,
+        }
+    }
+
+    fn bits(&mut self, n: u8) -> Result<u32, ParseError> {
+        let mut v = 0;
+        for i in 0..n {
+            let byte = *self.bytes.get(self.pos).ok_or(ParseError::InvalidGzip)?;
+            v |= (((byte >> self.bit) & 1) as u32) << i;
+            self.bit += 1;
+            if self.bit == 8 {
+                self.bit = 0;
+                self.pos += 1;
+            }
+        }
+        Ok(v)
+    }
+
+    // stored blocks start on a byte boundary
+    fn align(&mut self) {
+        if self.bit != 0 {
+            self.bit = 0;
+            self.pos += 1;
+        }
+    }
+
+    fn take(&mut self, n: usize) -> Result<&'a [u8], ParseError> {
+        let bytes = self
+            .bytes
+            .get(self.pos..self.pos + n)
+            .ok_or(ParseError::InvalidGzip)?;
+        self.pos += n;
+        Ok(bytes)
+    }
+}
+
+// Canonical Huffman code, as the number of codes of each length and the
+// symbols in code order
+struct Huffman {
+    counts: [u16; MAX_BITS + 1],
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> Self {
+        let mut counts = [0u16; MAX_BITS + 1];
+        for &len in lengths {
+            counts[len as usize] += 1;
+        }
+        counts[0] = 0;
+        let mut offsets = [0u16; MAX_BITS + 1];
+        for len in 1..MAX_BITS {
+            offsets[len + 1] = offsets[len] + counts[len];
+        }
+        let mut symbols = vec![0; lengths.len()];
+        for (sym, &len) in lengths.iter().enumerate() {
+            if len != 0 {
+                symbols[offsets[len as usize] as usize] = sym as u16;
+                offsets[len as usize] += 1;
+            }
+        }
+        Huffman { counts, symbols }
+    }
+
+    fn decode(&self, input: &mut BitReader) -> Result<u16, ParseError> {
+        // first code and index into symbols of the current length
+        let mut code = 0;
+        let mut first = 0;
+        let mut index = 0;
+        for len in 1..=MAX_BITS {
+            code |= input.bits(1)? as i32;
+            let count = self.counts[len] as i32;
+            if code - first < count {
+                return Ok(self.symbols[(index + code - first) as usize]);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        Err(ParseError::InvalidGzip)
+    }
+}
+
+fn inflate(input: &mut BitReader) -> Result<Vec<u8>, ParseError> {
+    let mut out = vec![];
+    loop {
+        let last = input.bits(1)? == 1;
+        match input.bits(2)? {
+            0 => {
+                input.align();
+                let header = input.take(4)?;
+                let len = u16::from_le_bytes([header[0], header[1]]);
+                let nlen = u16::from_le_bytes([header[2], header[3]]);
+                if len != !nlen {
+                    return Err(ParseError::InvalidGzip);
+                }
+                out.extend_from_slice(input.take(len as usize)?);
+            }
+            1 => {
+                let (lit, dist) = fixed_codes();
+                inflate_block(input, &mut out, &lit, &dist)?;
+            }
+            2 => {
+                let (lit, dist) = dynamic_codes(input)?;
+                inflate_block(input, &mut out, &lit, &dist)?;
+            }
+            _ => return Err(ParseError::InvalidGzip),
+        }
+        if last {
+            return Ok(out);
+        }
+    }
+}
+
+fn fixed_codes() -> (Huffman, Huffman) {
+    let mut lengths = [0u8; 288];
+    lengths[..144].fill(8);
+    lengths[144..256].fill(9);
+    lengths[256..280].fill(7);
+    lengths[280..].fill(8);
+    (Huffman::new(&lengths), Huffman::new(&[5; 30]))
+}
+
+fn dynamic_codes(input: &mut BitReader) -> Result<(Huffman, Huffman), ParseError> {
+    let nlit = input.bits(5)? as usize + 257;
+    let ndist = input.bits(5)? as usize + 1;
+    let ncode = input.bits(4)? as usize + 4;
+    let mut cl_lengths = [0u8; 19];
+    for &i in CL_ORDER.iter().take(ncode) {
+        cl_lengths[i] = input.bits(3)? as u8;
+    }
+    let cl = Huffman::new(&cl_lengths);
+
+    // literal/length and distance code lengths, run length encoded together
+    let mut lengths = vec![];
+    while lengths.len() < nlit + ndist {
+        let (len, repeat) = match cl.decode(input)? {
+            sym @ 0..=15 => (sym as u8, 1),
+            16 => (
+                *lengths.last().ok_or(ParseError::InvalidGzip)?,
+                3 + input.bits(2)?,
+            ),
+            17 => (0, 3 + input.bits(3)?),
+            18 => (0, 11 + input.bits(7)?),
+            _ => return Err(ParseError::InvalidGzip),
+        };
+        lengths.extend(std::iter::repeat_n(len, repeat as usize));
+    }
+    if lengths.len() != nlit + ndist {
+        return Err(ParseError::InvalidGzip);
+    }
+    Ok((
+        Huffman::new(&lengths[..nlit]),
+        Huffman::new(&lengths[nlit..]),
+    ))
+}
+
+fn inflate_block(
+    input: &mut BitReader,
+    out: &mut Vec<u8>,
+    lit: &Huffman,
+    dist: &Huffman,
+) -> Result<(), ParseError> {
+    loop {
+        match lit.decode(input)? {
+            sym @ 0..=255 => out.push(sym as u8),
+            256 => return Ok(()),
+            sym => {
+                let i = sym as usize - 257;
+                if i >= LEN_BASE.len() {
+                    return Err(ParseError::InvalidGzip);
+                }
+                let len = LEN_BASE[i] as usize + input.bits(LEN_EXTRA[i])? as usize;
+                let j = dist.decode(input)? as usize;
+                if j >= DIST_BASE.len() {
+                    return Err(ParseError::InvalidGzip);
+                }
+                let d = DIST_BASE[j] as usize + input.bits(DIST_EXTRA[j])? as usize;
+                if d > out.len() {
+                    return Err(ParseError::InvalidGzip);
+                }
+                // the copy may overlap what it produces
+                let start = out.len() - d;
+                for k in 0..len {
+                    out.push(out[start + k]);
+                }
+            }
+        }
+    }
+}
@@ -1,7 +1,10 @@
 pub mod common;
 pub mod gen;
+#[cfg(feature = "gzip")]
+pub mod gzip;
 pub mod librarian;
 pub mod linker;
+pub mod loader;
 pub mod logger;
 pub mod types;
 pub mod utils;
@@ -9,69 +12,204 @@ pub mod utils;
 pub mod lib {
     use std::collections::BTreeMap;
     use std::fs;
-    use std::path::PathBuf;
+    use std::path::{Path, PathBuf};
 
-    use crate::types::errors::{LibError, ParseError};
+    use crate::common::Alignment;
+    use crate::librarian::is_out_file;
+    use crate::linker::editor::{LinkerEditor, LinkerInfo};
+    use crate::types::errors::{LibError, LinkError, ParseError};
     use crate::types::library::StaticLib;
     use crate::types::object::{parse_object_file, ObjectIn};
-    use crate::utils::read_object_file;
+    use crate::types::out::ObjectOut;
+    use crate::types::segment::Endianness;
+    use crate::types::symbol_table::SymbolName;
+    use crate::utils::try_read_object_file;
 
     type ObjectName = String;
 
+    // Everything link_directory needs besides the objects themselves
+    #[derive(Debug, Clone)]
+    pub struct LinkConfig {
+        pub text_start: i32,
+        pub data_start_boundary: i32,
+        pub bss_start_boundary: i32,
+        pub endianness: Endianness,
+        pub static_libs: Vec<String>, // paths, see read_lib
+        pub wrap_routines: Vec<SymbolName>,
+    }
+
+    impl Default for LinkConfig {
+        fn default() -> Self {
+            LinkConfig {
+                text_start: 0x0,
+                data_start_boundary: 0x0,
+                bss_start_boundary: 0x0,
+                endianness: Endianness::BigEndian,
+                static_libs: vec![],
+                wrap_routines: vec![],
+            }
+        }
+    }
+
     pub fn parse_object(fp: &str) -> Result<ObjectIn, ParseError> {
-        let file_contents = read_object_file(fp);
+        let file_contents = try_read_object_file(fp)?;
         parse_object_file(file_contents)
     }
 
+    // Panics on a file it can't read or parse, which suits tests. Use
+    // read_objects_from_dir_filtered to get the ParseError instead.
     pub fn read_objects_from_dir(dirname: &str) -> BTreeMap<ObjectName, ObjectIn> {
+        match read_objects_from_dir_filtered(dirname, is_object_path) {
+            Ok(objects) => objects,
+            Err(err) => panic!("read_objects_from_dir: {err:?}"),
+        }
+    }
+
+    fn is_object_path(path: &Path) -> bool {
+        !is_out_file(path.file_name().unwrap().to_str().unwrap())
+    }
+
+    // Same as read_objects_from_dir, but only files accepted by pred are parsed.
+    // Lets callers point the linker at directories with MAP files, READMEs, etc.
+    pub fn read_objects_from_dir_filtered(
+        dirname: &str,
+        pred: impl Fn(&Path) -> bool,
+    ) -> Result<BTreeMap<ObjectName, ObjectIn>, ParseError> {
         let mut objects = BTreeMap::new();
         let mut entries = fs::read_dir(dirname)
-            .unwrap()
+            .map_err(|_| ParseError::FileReadError(dirname.to_owned()))?
             .filter_map(|entry| entry.ok())
             .collect::<Vec<_>>();
         entries.sort_by_key(|entry| entry.file_name());
         for entry in entries {
             let path = entry.path();
-            if path.is_file()
-                && !path
-                    .file_name()
-                    .unwrap()
-                    .to_str()
-                    .unwrap()
-                    .ends_with("_out")
-            {
-                let file_contents = fs::read_to_string(&path).unwrap();
+            if path.is_file() && pred(&path) {
+                let file_contents = try_read_object_file(path.to_str().unwrap())?;
                 let file_name = path.file_name().unwrap().to_str().unwrap().to_string();
                 println!("reading {}", file_name.as_str());
-                match parse_object_file(file_contents) {
-                    Ok(object) => {
-                        objects.insert(file_name, object);
-                    }
-                    Err(err) => panic!("read_objects_from_dir: {err:?}"),
-                }
+                objects.insert(file_name, parse_object_file(file_contents)?);
             }
         }
-        objects
+        Ok(objects)
     }
 
-    pub fn read_objects(dirname: &str, obj_names: Vec<&str>) -> BTreeMap<ObjectName, ObjectIn> {
+    pub fn read_objects(
+        dirname: &str,
+        obj_names: impl IntoIterator<Item = impl AsRef<str>>,
+    ) -> Result<BTreeMap<ObjectName, ObjectIn>, ParseError> {
         let mut objects = BTreeMap::new();
         for obj_name in obj_names {
-            let path = PathBuf::from(dirname).join(PathBuf::from(obj_name));
-            let file_contents = fs::read_to_string(&path).unwrap();
+            let path = PathBuf::from(dirname).join(obj_name.as_ref());
+            let file_contents = try_read_object_file(&path.to_string_lossy())?;
             let file_name = path.file_name().unwrap().to_str().unwrap().to_string();
             println!("reading {}", file_name.as_str());
-            match parse_object_file(file_contents) {
-                Ok(object) => {
-                    objects.insert(file_name, object);
-                }
-                Err(err) => panic!("read_objects_from_dir: {err:?}"),
+            objects.insert(file_name, parse_object_file(file_contents)?);
+        }
+        Ok(objects)
+    }
+
+    // Parse objects from in-memory sources, keyed by object name. Handy for
+    // embedding the linker or testing it without touching the disk.
+    pub fn link_from_sources(
+        sources: BTreeMap<ObjectName, String>,
+    ) -> Result<BTreeMap<ObjectName, ObjectIn>, ParseError> {
+        let mut objects = BTreeMap::new();
+        for (obj_name, file_contents) in sources.into_iter() {
+            let object = parse_object_file(file_contents)?;
+            objects.insert(obj_name, object);
+        }
+        Ok(objects)
+    }
+
+    // Objects bundled in a (ustar) .tar file, e.g. a CI artifact. Entries are
+    // keyed by basename; like read_objects_from_dir, "_out" files are skipped.
+    #[cfg(feature = "archive")]
+    pub fn read_objects_from_archive(
+        archive_path: &str,
+    ) -> Result<BTreeMap<ObjectName, ObjectIn>, ParseError> {
+        let bytes = fs::read(archive_path)
+            .map_err(|_| ParseError::FileReadError(archive_path.to_owned()))?;
+        let mut objects = BTreeMap::new();
+        for (entry_name, contents) in tar_entries(&bytes)? {
+            let file_name = match Path::new(&entry_name).file_name() {
+                Some(n) => n.to_string_lossy().into_owned(),
+                None => continue,
+            };
+            if is_out_file(&file_name) {
+                continue;
+            }
+            let file_contents =
+                String::from_utf8(contents).map_err(|_| ParseError::InvalidArchive)?;
+            objects.insert(file_name, parse_object_file(file_contents)?);
+        }
+        Ok(objects)
+    }
+
+    // Regular file entries of a tar archive: 512 byte header blocks (name at 0,
+    // octal size at 124, type flag at 156, ustar name prefix at 345), each
+    // followed by the file data padded to 512 bytes. Two zero blocks end it.
+    #[cfg(feature = "archive")]
+    fn tar_entries(bytes: &[u8]) -> Result<Vec<(String, Vec<u8>)>, ParseError> {
+        const BLOCK: usize = 512;
+        let field = |hdr: &[u8], from: usize, to: usize| -> String {
+            let f = &hdr[from..to];
+            let end = f.iter().position(|&b| b == 0).unwrap_or(f.len());
+            String::from_utf8_lossy(&f[..end]).trim().to_owned()
+        };
+
+        let mut entries = vec![];
+        let mut off = 0;
+        while off + BLOCK <= bytes.len() {
+            let hdr = &bytes[off..off + BLOCK];
+            if hdr.iter().all(|&b| b == 0) {
+                return Ok(entries);
+            }
+            let size = usize::from_str_radix(&field(hdr, 124, 136), 8)
+                .map_err(|_| ParseError::InvalidArchive)?;
+            let data_start = off + BLOCK;
+            let data_end = data_start + size;
+            if data_end > bytes.len() {
+                return Err(ParseError::InvalidArchive);
+            }
+            // '0' (or NUL in old archives) is a regular file
+            if hdr[156] == b'0' || hdr[156] == 0 {
+                let name = field(hdr, 0, 100);
+                let prefix = field(hdr, 345, 500);
+                let name = if prefix.is_empty() {
+                    name
+                } else {
+                    format!("{prefix}/{name}")
+                };
+                entries.push((name, bytes[data_start..data_end].to_vec()));
             }
+            off = data_start + size.div_ceil(BLOCK) * BLOCK;
         }
-        objects
+        // no end-of-archive marker
+        Err(ParseError::InvalidArchive)
     }
 
     pub fn read_lib(dir: &str) -> Result<StaticLib, LibError> {
         StaticLib::parse(dir)
     }
+
+    // Read all the objects in dirname (see read_objects_from_dir) and the
+    // static libs in cfg, and link them in one go.
+    pub fn link_directory(
+        dirname: &str,
+        cfg: LinkConfig,
+    ) -> Result<(ObjectOut, LinkerInfo), LinkError> {
+        let objects = read_objects_from_dir_filtered(dirname, is_object_path)
+            .map_err(LinkError::ObjectParseFailure)?;
+        let mut static_libs = vec![];
+        for lib_path in cfg.static_libs.iter() {
+            let lib =
+                read_lib(lib_path).map_err(|e| LinkError::StaticLibParseFailure(Box::new(e)))?;
+            static_libs.push(lib);
+        }
+        let mut editor = LinkerEditor::new(cfg.text_start, 0x0, 0x0, false);
+        editor.set_data_start_boundary(Alignment::try_from(cfg.data_start_boundary)?)?;
+        editor.set_bss_start_boundary(Alignment::try_from(cfg.bss_start_boundary)?)?;
+        editor.set_endianness(cfg.endianness);
+        editor.link(objects, static_libs, cfg.wrap_routines)
+    }
 }
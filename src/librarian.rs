@@ -1,8 +1,33 @@
+#[cfg(feature = "elf")]
+use crate::types::segment::SegmentName;
+use std::fs;
+use std::path::PathBuf;
+
 use crate::{
+    loader::load,
     logger::*,
-    types::{errors::LibError, library::StaticLib},
+    types::{errors::LibError, library::StaticLib, object::parse_object_file, out::ObjectOut},
 };
 
+// What Librarian::write_output writes a linked object as
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum OutputFormat {
+    LinkObject, // same text format as the input objects
+    FlatBinary, // the image as loaded at its link address (see loader::load)
+    #[cfg(feature = "elf")]
+    Elf, // see ObjectOut::write_elf, entered at the start of .text
+}
+
+// Name of the output file for name. read_objects_from_dir skips files named
+// like this, so output can be written next to the objects it was linked from.
+pub fn out_file_name(name: &str) -> String {
+    format!("{name}_out")
+}
+
+pub fn is_out_file(name: &str) -> bool {
+    name.ends_with("_out")
+}
+
 pub struct Librarian {
     logger: Logger,
 }
@@ -36,6 +61,38 @@ impl Librarian {
         Ok(())
     }
 
+    // Same as build_libdir, for all the objects in src_dir (relative to
+    // basepath, like object names) but output files (see out_file_name). Every
+    // one of them must parse, or nothing is built.
+    pub fn build_libdir_from_dir(
+        &mut self,
+        basepath: Option<&str>,
+        libname: Option<&str>,
+        src_dir: &str,
+    ) -> Result<(), LibError> {
+        let dir = match basepath {
+            Some(p) => PathBuf::from(p).join(src_dir),
+            None => PathBuf::from(src_dir),
+        };
+        let mut object_files = vec![];
+        for entry in fs::read_dir(&dir)? {
+            let path = entry?.path();
+            let file_name = match path.file_name().and_then(|n| n.to_str()) {
+                Some(n) if path.is_file() && !is_out_file(n) => n.to_owned(),
+                _ => continue,
+            };
+            parse_object_file(fs::read_to_string(&path)?).map_err(LibError::ObjectParseFailure)?;
+            object_files.push(PathBuf::from(src_dir).join(file_name));
+        }
+        object_files.sort();
+        self.logger.do_log(
+            LogLevel::Info,
+            &format!("Found {} objects in {dir:?}", object_files.len()),
+        );
+        let object_files: Vec<&str> = object_files.iter().filter_map(|p| p.to_str()).collect();
+        self.build_libdir(basepath, libname, object_files)
+    }
+
     pub fn build_libfile(
         &mut self,
         basepath: Option<&str>,
@@ -83,4 +140,29 @@ impl Librarian {
         }
         Ok(())
     }
+
+    pub fn write_output(
+        &mut self,
+        out: &ObjectOut,
+        path: &str,
+        format: OutputFormat,
+    ) -> Result<(), LibError> {
+        self.logger.do_log(
+            LogLevel::Info,
+            &format!("Writing {format:?} output to {path:?}"),
+        );
+        match format {
+            OutputFormat::LinkObject => std::fs::write(path, out.ppr(false))?,
+            OutputFormat::FlatBinary => std::fs::write(path, load(out, 0))?,
+            #[cfg(feature = "elf")]
+            OutputFormat::Elf => {
+                let entry = out
+                    .segments
+                    .get(&SegmentName::TEXT)
+                    .map_or(0, |text| text.segment_start);
+                out.write_elf(path, entry, out.endianness)?
+            }
+        }
+        Ok(())
+    }
 }
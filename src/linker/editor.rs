@@ -1,21 +1,29 @@
-use std::collections::{BTreeMap, HashMap, HashSet};
-use std::ops::Deref;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::ops::Range;
 
 // use either::Either::{Left, Right};
 
 use either::Either::{self, Left, Right};
 
-use crate::common::{Defn, DefnProvenance, ObjectID, Refs};
+use crate::common::{
+    Alignment, Defn, DefnProvenance, GlobalSymbol, LibName, ObjectID, GOT_SYMBOL,
+    SHARED_LIBS_SYMBOL,
+};
+use crate::linker::memory::MemoryRegions;
+use crate::linker::script::LinkScript;
+use crate::linker::stats::LinkStats;
 use crate::types::errors::LinkError;
 use crate::types::library::StaticLib;
 use crate::types::object::ObjectIn;
 use crate::types::out::ObjectOut;
-use crate::types::relocation::{RelRef, RelType, Relocation};
-use crate::types::segment::{Segment, SegmentData, SegmentName};
+use crate::types::relocation::{common_block_ref_warnings, RelRef, RelType, Relocation};
+use crate::types::segment::{
+    Endianness, Segment, SegmentBytes, SegmentData, SegmentDescr, SegmentName,
+};
 use crate::types::stub::{StubLib, StubMember};
 use crate::types::symbol_table::{SymbolName, SymbolTableEntry};
-use crate::utils::{find_seg_start, mk_addr_4, mk_i_4, x_to_i2, x_to_i4};
-use crate::{logger::*, wrapped_symbol};
+use crate::utils::find_seg_start;
+use crate::{logger::*, symbol};
 
 #[derive(Eq, PartialEq)]
 pub enum LinkObjType {
@@ -23,14 +31,154 @@ pub enum LinkObjType {
     Executable,
 }
 
+// Where the GOT goes in the output. Architectures differ on this.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum GotPlacement {
+    AfterText,
+    AfterData,
+}
+
+// Whether the output gets a GOT. Auto allocates one when GP4 or GPS4
+// relocations need slots in it; Force always does, even an empty one; with
+// Disable, any relocation referring to the GOT, GA4 and GR4 included, fails
+// the link with GotRequiredButDisabled.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum GotMode {
+    Auto,
+    Force,
+    Disable,
+}
+
+// How symbol names are matched against each other. Case-insensitive links
+// key the global symbol table by the lowercased name; the per-object symbol
+// tables keep the original spelling.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum SymbolResolution {
+    CaseSensitive,
+    CaseInsensitive,
+}
+
+impl SymbolResolution {
+    // Name under which a symbol goes in the global symbol table
+    pub fn key(&self, name: &SymbolName) -> SymbolName {
+        match (self, name) {
+            (SymbolResolution::CaseSensitive, _) => name.clone(),
+            (SymbolResolution::CaseInsensitive, SymbolName::SName(s)) => {
+                SymbolName::SName(s.to_lowercase())
+            }
+            (SymbolResolution::CaseInsensitive, SymbolName::WrappedSName(s)) => {
+                SymbolName::WrappedSName(s.to_lowercase())
+            }
+            (SymbolResolution::CaseInsensitive, SymbolName::Local(s, obj_id)) => {
+                SymbolName::Local(s.to_lowercase(), obj_id.clone())
+            }
+        }
+    }
+
+    pub fn matches(&self, a: &SymbolName, b: &SymbolName) -> bool {
+        self.key(a) == self.key(b)
+    }
+}
+
+// A library member that defines a symbol, see lib_members_defining
+struct LibMember<'a> {
+    id: ObjectID, // as in the link, "{libname}_mod_{offset}" for FileLib members
+    obj: Option<&'a ObjectIn>,
+    weak: bool, // defines the symbol only weakly, see StaticLib::DirLib
+}
+
+// How static libraries are searched for undefined symbols
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum LibSearch {
+    // All libraries together, until nothing new is pulled in (the default)
+    Grouped,
+    // Each library on its own, left to right, like a plain ld a.a b.a: a
+    // member can't use an earlier library. The given ranges of libraries are
+    // searched together instead, like --start-group ... --end-group.
+    InOrder(Vec<Range<usize>>),
+}
+
+impl LibSearch {
+    // Ranges of libraries searched together, in search order
+    fn units(&self, nlibs: usize) -> Vec<Range<usize>> {
+        match self {
+            LibSearch::Grouped => std::iter::once(0..nlibs).collect(),
+            LibSearch::InOrder(groups) => {
+                let mut units = vec![];
+                let mut i = 0;
+                while i < nlibs {
+                    let end = groups
+                        .iter()
+                        .find(|g| g.start == i && g.end > i)
+                        .map_or(i + 1, |g| g.end.min(nlibs));
+                    units.push(i..end);
+                    i = end;
+                }
+                units
+            }
+        }
+    }
+}
+
+// What to do about symbols that are still undefined once all libraries have
+// been searched. Permissive links go through, and relocations referring to
+// such symbols are left for the loader (see LinkerInfo::deferred_relocations).
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum UndefinedPolicy {
+    Error,
+    Permissive,
+}
+
+// A relocation left undone, and the module it is in
+pub type DeferredRelocation = (ObjectID, Relocation);
+
+// Condition checked once the output is laid out, see LinkerEditor::add_assertion
+pub type Assertion = Box<dyn Fn(&LinkerInfo, &ObjectOut) -> bool>;
+
+// The parts of a link that take time on large inputs, in the order they run
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum LinkPhase {
+    Allocation,        // counts input objects
+    LibraryResolution, // counts library search units, see LibSearch
+    Relocation,        // counts modules
+}
+
+// Called with the phase and how much of it is done out of the total, see
+// LinkerEditor::set_on_progress
+pub type ProgressCallback = Box<dyn FnMut(LinkPhase, usize, usize)>;
+
 #[derive(Debug)]
 pub struct LinkerInfo {
     pub segment_mapping: BTreeMap<ObjectID, BTreeMap<SegmentName, i32>>,
+    pub segment_lengths: BTreeMap<ObjectID, BTreeMap<SegmentName, i32>>,
     pub common_block_mapping: HashMap<SymbolName, i32>,
     pub symbol_tables: HashMap<ObjectID, Vec<SymbolTableEntry>>,
-    pub global_symtable: BTreeMap<SymbolName, (Option<Defn>, Refs)>,
+    pub global_symtable: BTreeMap<SymbolName, GlobalSymbol>,
+    pub arch: Option<String>, // target of the link, set by the first object declaring one
+    used_libs: BTreeSet<LibName>,
+    shared_deps: Vec<LibName>, // see shared_dependencies
+    lib_objects: BTreeSet<ObjectID>,
+    relocation_counts: BTreeMap<RelType, usize>,
+    er4_relocations: usize, // created during the link, not in relocation_counts
+    pub deferred_relocations: Vec<DeferredRelocation>, // not applied, see UndefinedPolicy
+    pub entry_addr: Option<i32>, // of the entry symbol, if set and defined
+    segment_sizes: BTreeMap<SegmentName, i32>,
+    segment_checksums: BTreeMap<SegmentName, u32>, // crc32 of the segment data
+    got_entries: Vec<GotEntry>,
 }
 
+// A GOT slot filled by a GP4 or GPS4 relocation: its offset in the GOT, what
+// it points to and the address put in it. GPS4 slots point to a segment of a
+// module, named as a local symbol of that module.
+pub type GotEntry = (usize, SymbolName, i32);
+
+// see LinkerEditor::run_relocations
+type Relocated = (
+    BTreeMap<RelType, usize>,
+    Vec<DeferredRelocation>,
+    Vec<GotEntry>,
+);
+
 impl Default for LinkerInfo {
     fn default() -> Self {
         Self::new()
@@ -40,15 +188,140 @@ impl Default for LinkerInfo {
 impl LinkerInfo {
     pub fn new() -> LinkerInfo {
         let segment_mapping = BTreeMap::new();
+        let segment_lengths = BTreeMap::new();
         let common_block_mapping = HashMap::new();
         let symbol_tables = HashMap::new();
         let global_symtable = BTreeMap::new();
+        let arch = None;
+        let used_libs = BTreeSet::new();
+        let shared_deps = vec![];
+        let lib_objects = BTreeSet::new();
+        let relocation_counts = BTreeMap::new();
+        let er4_relocations = 0;
+        let deferred_relocations = vec![];
+        let entry_addr = None;
+        let segment_sizes = BTreeMap::new();
+        let segment_checksums = BTreeMap::new();
+        let got_entries = vec![];
         LinkerInfo {
             segment_mapping,
+            segment_lengths,
             common_block_mapping,
             symbol_tables,
             global_symtable,
+            arch,
+            used_libs,
+            shared_deps,
+            lib_objects,
+            relocation_counts,
+            er4_relocations,
+            deferred_relocations,
+            entry_addr,
+            segment_sizes,
+            segment_checksums,
+            got_entries,
+        }
+    }
+
+    // Libraries that provided at least one symbol definition during the link
+    pub fn used_libraries(&self) -> Vec<LibName> {
+        self.used_libs.iter().cloned().collect()
+    }
+
+    // Shared libraries the output needs at run time, i.e. the ones whose stubs
+    // provided definitions, in library order. The .lib segment lists them too.
+    pub fn shared_dependencies(&self) -> Vec<LibName> {
+        self.shared_deps.clone()
+    }
+
+    fn add_shared_dependency(&mut self, libname: &str) {
+        if !self.shared_deps.iter().any(|l| l == libname) {
+            self.shared_deps.push(libname.to_owned());
+        }
+    }
+
+    // Where the definition of a global symbol came from. None if the symbol
+    // is unknown or undefined. Names are as in global_symtable.
+    pub fn symbol_provenance(&self, name: &SymbolName) -> Option<&DefnProvenance> {
+        self.global_symtable
+            .get(name)
+            .and_then(|gsym| gsym.defn.as_ref())
+            .map(|defn| &defn.defn_prov)
+    }
+
+    // Where each object landed in the output: (start, end) addresses of its
+    // segments, end exclusive
+    pub fn address_ranges(&self) -> BTreeMap<ObjectID, BTreeMap<SegmentName, (i32, i32)>> {
+        let mut ranges = BTreeMap::new();
+        for (obj_id, seg_addrs) in self.segment_mapping.iter() {
+            let obj_ranges = seg_addrs
+                .iter()
+                .map(|(segname, &start)| {
+                    let len = self
+                        .segment_lengths
+                        .get(obj_id)
+                        .and_then(|lens| lens.get(segname))
+                        .copied()
+                        .unwrap_or(0);
+                    (segname.clone(), (start, start + len))
+                })
+                .collect();
+            ranges.insert(obj_id.clone(), obj_ranges);
         }
+        ranges
+    }
+
+    // The highest address used by the output, i.e. the end of its last
+    // segment, common blocks included. 0 for no segments.
+    pub fn image_size(&self, out: &ObjectOut) -> i32 {
+        out.segments
+            .values()
+            .map(|seg| seg.segment_start + seg.segment_len)
+            .max()
+            .unwrap_or(0)
+    }
+
+    // In GOT order
+    pub fn got_entries(&self) -> Vec<GotEntry> {
+        self.got_entries.clone()
+    }
+
+    pub fn stats(&self) -> LinkStats {
+        LinkStats {
+            objects: self.segment_mapping.len(),
+            lib_objects: self.lib_objects.len(),
+            symbols: self.global_symtable.len(),
+            resolved_symbols: self
+                .global_symtable
+                .values()
+                .filter(|gsym| gsym.is_defined())
+                .count(),
+            common_blocks: self.common_block_mapping.len(),
+            relocations: self.relocation_counts.clone(),
+            er4_relocations: self.er4_relocations,
+            deferred_relocations: self.deferred_relocations.len(),
+            segment_sizes: self.segment_sizes.clone(),
+            segment_checksums: self.segment_checksums.clone(),
+        }
+    }
+
+    // Relocations the loader still has to do, one per line:
+    //   mod_1: AS4 at .text+0x4 (foo)
+    pub fn deferred_relocations_report(&self) -> String {
+        let mut s = vec![String::from("Deferred relocations:")];
+        for (modname, r) in self.deferred_relocations.iter() {
+            let target = match r.rel_ref {
+                RelRef::SymbolRef(sym_i) => {
+                    format!(" ({})", self.symbol_tables[modname][sym_i].st_name)
+                }
+                _ => String::new(),
+            };
+            s.push(format!(
+                "  {modname}: {} at {}+0x{:X}{target}",
+                r.rel_type, r.rel_seg, r.rel_loc
+            ));
+        }
+        s.join("\n")
     }
 
     pub fn ppr(&self) -> String {
@@ -66,22 +339,66 @@ impl LinkerInfo {
             es.push(entry);
         }
         s.push_str(es.join("\n").as_str());
+        let mut syms = vec![];
+        for (name, gsym) in self.global_symtable.iter() {
+            if let Some(defn) = &gsym.defn {
+                let addr = defn
+                    .defn_addr
+                    .map_or("?".to_owned(), |addr| format!("{addr:X}"));
+                syms.push(format!(
+                    "  {name} => {addr} ({}, {})",
+                    defn.defn_mod_id, defn.defn_prov
+                ));
+            }
+        }
+        if !syms.is_empty() {
+            s.push_str("\nSymbols:\n");
+            s.push_str(syms.join("\n").as_str());
+        }
+        if !self.got_entries.is_empty() {
+            s.push_str("\nGOT:");
+            for (offset, name, addr) in self.got_entries.iter() {
+                s.push_str(format!("\n  {offset:X} => {name} {addr:X}").as_str());
+            }
+        }
+        if !self.segment_checksums.is_empty() {
+            s.push_str("\nChecksums:");
+            for s_n in SegmentName::order().iter() {
+                if let Some(crc) = self.segment_checksums.get(s_n) {
+                    s.push_str(format!("\n  {s_n} => {crc:08X}").as_str());
+                }
+            }
+        }
         s
     }
 }
 
-pub enum Endianness {
-    BigEndian,
-    LittleEndian,
-}
-
 pub struct LinkerEditor {
     text_start: i32, // exe/lib start
-    data_start_boundary: i32,
-    bss_start_boundary: i32,
+    data_start_boundary: Alignment,
+    bss_start_boundary: Alignment,
+    got_start_boundary: Alignment,
+    got_placement: GotPlacement,
+    got_mode: GotMode,
+    symbol_resolution: SymbolResolution,
+    lib_search: LibSearch,
+    undefined_policy: UndefinedPolicy,
+    warn_common_block_refs: bool, // see common_block_ref_warnings
+    strict_wrap: bool,            // wrapping a routine no object has is an error
+    strict_segment_permissions: bool,
+    split_segment_permissions: bool,
+    memory_regions: Option<MemoryRegions>,
+    assertions: Vec<(Assertion, String)>, // with the message to fail with
+    on_progress: Option<ProgressCallback>,
     pub session_objects: BTreeMap<ObjectID, ObjectIn>,
+    pending_objects: BTreeMap<ObjectID, ObjectIn>, // see add_objects
+    defined_symbols: BTreeMap<SymbolName, i32>,    // --defsym style absolute definitions
+    renamed_symbols: HashMap<SymbolName, SymbolName>, // see rename_symbols
+    entry: Option<SymbolName>,                     // see LinkerInfo::entry_addr
+    force_include: Vec<SymbolName>,                // see set_force_include
+    padding: BTreeMap<SegmentName, (i32, u8)>,     // see pad_segment
     logger: Logger,
-    _endianness: Endianness,
+    endianness: Endianness,
 }
 
 impl LinkerEditor {
@@ -90,15 +407,19 @@ impl LinkerEditor {
         self.logger
             .debug(&format!("text_start: {:X}", self.text_start));
         self.logger.debug(&format!(
-            "data_start_boundary: {:X}",
+            "data_start_boundary: {}",
             self.data_start_boundary
         ));
+        self.logger
+            .debug(&format!("bss_start_boundary: {}", self.bss_start_boundary));
         self.logger.debug(&format!(
-            "bss_start_boundary: {:X}",
-            self.bss_start_boundary
+            "got_start_boundary: {} ({:?})",
+            self.got_start_boundary, self.got_placement
         ));
     }
 
+    // The boundaries are 0 (packed) or a power of two, see Alignment. Other
+    // values panic; the set_*_start_boundary setters return an error instead.
     pub fn new(
         text_start: i32,
         data_start_boundary: i32,
@@ -107,35 +428,348 @@ impl LinkerEditor {
     ) -> LinkerEditor {
         let mut r = LinkerEditor {
             text_start,
-            data_start_boundary,
-            bss_start_boundary,
+            data_start_boundary: Alignment::try_from(data_start_boundary)
+                .expect("data start boundary is 0 or a power of two"),
+            bss_start_boundary: Alignment::try_from(bss_start_boundary)
+                .expect("bss start boundary is 0 or a power of two"),
+            got_start_boundary: Alignment::None,
+            got_placement: GotPlacement::AfterText,
+            got_mode: GotMode::Auto,
+            symbol_resolution: SymbolResolution::CaseSensitive,
+            lib_search: LibSearch::Grouped,
+            undefined_policy: UndefinedPolicy::Error,
+            warn_common_block_refs: false,
+            strict_wrap: false,
+            strict_segment_permissions: false,
+            split_segment_permissions: false,
+            memory_regions: None,
+            assertions: vec![],
+            on_progress: None,
             logger: Logger::new_stdout_logger(silent),
             session_objects: BTreeMap::new(), // this does not contain stubs (should it?)
-            _endianness: Endianness::BigEndian, // always BigEndian now ...
+            pending_objects: BTreeMap::new(),
+            defined_symbols: BTreeMap::new(),
+            renamed_symbols: HashMap::new(),
+            entry: None,
+            force_include: vec![],
+            padding: BTreeMap::new(),
+            endianness: Endianness::BigEndian, // see set_endianness
         };
         r.print_linker_editor_cfg();
         r
     }
 
+    // Define a symbol at an absolute address, as if some object provided it.
+    // Clashes with a real definition are reported as MultipleSymbolDefinitions.
+    pub fn define_symbol(&mut self, name: SymbolName, addr: i32) {
+        self.defined_symbols.insert(name, addr);
+    }
+
+    pub fn from_script(script: &LinkScript) -> LinkerEditor {
+        let mut editor = LinkerEditor::new(
+            script.text_start.unwrap_or(0x0),
+            script.data_align.unwrap_or(0x0),
+            script.bss_align.unwrap_or(0x0),
+            false,
+        );
+        if let Some(entry) = &script.entry {
+            editor.set_entry(entry.clone());
+        }
+        editor
+    }
+
+    pub fn set_entry(&mut self, entry: SymbolName) {
+        self.entry = Some(entry);
+    }
+
+    // Rename symbols in the input objects before linking, definitions and
+    // references alike (like objcopy --redefine-sym). Library members are
+    // left alone. Renaming onto a name that some object already defines, or
+    // that the same object already has, is RenamedSymbolNameAlreadyExists.
+    pub fn rename_symbols(&mut self, map: HashMap<SymbolName, SymbolName>) {
+        self.renamed_symbols.extend(map);
+    }
+
+    // Like ld -u: library members defining these symbols are linked in even
+    // if nothing refers to them (e.g. members only reached through data
+    // tables). Symbols no library defines are not an error.
+    pub fn set_force_include(&mut self, force_include: Vec<SymbolName>) {
+        self.force_include = force_include;
+    }
+
+    // Grow the output segment to to_len bytes of fill before the segments
+    // after it are placed. A segment already longer than that fails the
+    // link with SegmentExceedsPadding.
+    pub fn pad_segment(&mut self, name: SegmentName, to_len: i32, fill: u8) {
+        self.padding.insert(name, (to_len, fill));
+    }
+
+    // GOT goes right after TEXT by default, unaligned
+    pub fn set_got_layout(
+        &mut self,
+        placement: GotPlacement,
+        got_start_boundary: i32,
+    ) -> Result<(), LinkError> {
+        self.got_start_boundary = Alignment::try_from(got_start_boundary)?;
+        self.got_placement = placement;
+        Ok(())
+    }
+
+    pub fn set_got_mode(&mut self, got_mode: GotMode) {
+        self.got_mode = got_mode;
+    }
+
+    // Unlike the numeric boundaries of new, these can't mistake 0 for an
+    // alignment: pass Alignment::None to pack the segment
+    pub fn set_data_start_boundary(&mut self, boundary: Alignment) -> Result<(), LinkError> {
+        self.data_start_boundary = LinkerEditor::valid_alignment(boundary)?;
+        Ok(())
+    }
+
+    pub fn set_bss_start_boundary(&mut self, boundary: Alignment) -> Result<(), LinkError> {
+        self.bss_start_boundary = LinkerEditor::valid_alignment(boundary)?;
+        Ok(())
+    }
+
+    fn valid_alignment(boundary: Alignment) -> Result<Alignment, LinkError> {
+        match boundary {
+            Alignment::Bytes(n) if !boundary.is_valid() => {
+                Err(LinkError::InvalidAlignment(n as i32))
+            }
+            _ => Ok(boundary),
+        }
+    }
+
+    pub fn set_text_start(&mut self, text_start: i32) {
+        self.text_start = text_start;
+    }
+
+    pub fn set_endianness(&mut self, endianness: Endianness) {
+        self.endianness = endianness;
+    }
+
+    pub fn set_symbol_resolution(&mut self, symbol_resolution: SymbolResolution) {
+        self.symbol_resolution = symbol_resolution;
+    }
+
+    pub fn set_lib_search(&mut self, lib_search: LibSearch) {
+        self.lib_search = lib_search;
+    }
+
+    pub fn set_undefined_policy(&mut self, undefined_policy: UndefinedPolicy) {
+        self.undefined_policy = undefined_policy;
+    }
+
+    pub fn set_warn_common_block_refs(&mut self, warn_common_block_refs: bool) {
+        self.warn_common_block_refs = warn_common_block_refs;
+    }
+
+    pub fn set_strict_wrap(&mut self, strict_wrap: bool) {
+        self.strict_wrap = strict_wrap;
+    }
+
+    // Modules contributing to the same output segment with different R/W
+    // permissions fail the link with SegmentPermissionConflict, rather than
+    // the output segment getting all of them
+    pub fn set_strict_segment_permissions(&mut self, strict_segment_permissions: bool) {
+        self.strict_segment_permissions = strict_segment_permissions;
+    }
+
+    // Read-only .data (no W) goes to a .rodata output segment of its own,
+    // right after .text, rather than being merged with the writable .data
+    // of other modules. A module with a .rodata already keeps its .data.
+    pub fn set_split_segment_permissions(&mut self, split_segment_permissions: bool) {
+        self.split_segment_permissions = split_segment_permissions;
+    }
+
+    // Segments start in their region when they can't follow on from the one
+    // before them, and must fit in it: RegionOverflow otherwise
+    pub fn set_memory_regions(&mut self, memory_regions: MemoryRegions) {
+        self.memory_regions = Some(memory_regions);
+    }
+
+    // Like ASSERT(cond, msg) of linker scripts: the link fails with
+    // AssertionFailed(msg) unless f holds for the laid out output. Assertions
+    // are checked after symbols are resolved, before any relocation.
+    pub fn add_assertion(
+        &mut self,
+        f: impl Fn(&LinkerInfo, &ObjectOut) -> bool + 'static,
+        msg: String,
+    ) {
+        self.assertions.push((Box::new(f), msg));
+    }
+
+    // A missing segment is of size 0
+    pub fn assert_segment_max_size(&mut self, segment_name: SegmentName, max_size: i32) {
+        let msg = format!("{segment_name} is larger than 0x{max_size:X} bytes");
+        self.add_assertion(
+            move |_, out| {
+                out.segments
+                    .get(&segment_name)
+                    .is_none_or(|seg| seg.segment_len <= max_size)
+            },
+            msg,
+        );
+    }
+
+    // For permissive links that still need some symbols defined. Names are
+    // as in global_symtable.
+    pub fn assert_symbol_defined(&mut self, name: SymbolName) {
+        let msg = format!("{name} is not defined");
+        self.add_assertion(
+            move |info, _| {
+                info.global_symtable
+                    .get(&name)
+                    .is_some_and(GlobalSymbol::is_defined)
+            },
+            msg,
+        );
+    }
+
+    // Each phase reports 0 done when it starts, then again after every object,
+    // search unit or module. Library resolution stops early once nothing is
+    // undefined, reporting all of it done. Dry runs do no relocation.
+    pub fn set_on_progress(&mut self, f: impl FnMut(LinkPhase, usize, usize) + 'static) {
+        self.on_progress = Some(Box::new(f));
+    }
+
+    fn progress(&mut self, phase: LinkPhase, done: usize, total: usize) {
+        if let Some(f) = self.on_progress.as_mut() {
+            f(phase, done, total);
+        }
+    }
+
+    pub fn set_logger(&mut self, logger: Logger) {
+        self.logger = logger;
+    }
+
+    pub fn logger(&self) -> &Logger {
+        &self.logger
+    }
+
+    // Redo just the relocations of an earlier link, e.g. after moving its
+    // segments (and text_start) to another base address. Segment data is
+    // restored from the session objects first, so nothing is relocated twice.
+    pub fn relocate_only(
+        &mut self,
+        out: &mut ObjectOut,
+        info: &LinkerInfo,
+    ) -> Result<(), LinkError> {
+        self.restore_segment_data(out, info)?;
+        // ER4 relocations in the output are all produced by run_relocations
+        out.relocations.clear();
+        self.run_relocations(out, info).map(|_| ())
+    }
+
+    fn restore_segment_data(
+        &self,
+        out: &mut ObjectOut,
+        info: &LinkerInfo,
+    ) -> Result<(), LinkError> {
+        for (modname, mod_obj) in self.session_objects.iter() {
+            for (i, segment) in mod_obj.segments.iter().enumerate() {
+                let seg_addr = match info
+                    .segment_mapping
+                    .get(modname)
+                    .and_then(|addrs| addrs.get(&segment.segment_name))
+                {
+                    None => return Err(LinkError::UnexpectedLinkError),
+                    Some(addr) => *addr,
+                };
+                let out_seg_start = match out.segments.get(&segment.segment_name) {
+                    None => return Err(LinkError::UnexpectedLinkError),
+                    Some(s) => s.segment_start,
+                };
+                let sd = match out.object_data.get_mut(&segment.segment_name) {
+                    None => return Err(LinkError::UnexpectedLinkError),
+                    Some(sd) => sd,
+                };
+                sd.write_bytes((seg_addr - out_seg_start) as usize, &mod_obj.object_data[i])
+                    .ok_or(LinkError::RelocationOutOfRange)?;
+            }
+        }
+        // GOT slots are filled in by run_relocations
+        if let Some(got) = out.object_data.get_mut(&SegmentName::GOT) {
+            *got = SegmentData::new(got.len());
+        }
+        Ok(())
+    }
+
     pub fn link(
         &mut self,
         objs_in: BTreeMap<ObjectID, ObjectIn>,
         static_libs: Vec<StaticLib>,
         wrap_routines: Vec<SymbolName>,
     ) -> Result<(ObjectOut, LinkerInfo), LinkError> {
-        match self.do_link(objs_in, static_libs, wrap_routines, LinkObjType::Executable) {
+        match self.do_link(
+            objs_in,
+            static_libs,
+            wrap_routines,
+            LinkObjType::Executable,
+            false,
+        ) {
             Err(e) => Err(e),
             Ok((out, _, info)) => Ok((out, info)),
         }
     }
 
+    // Same checks and layout as link (including the undefined symbol
+    // check), but no relocations are run: the LinkerInfo has the segment
+    // sizes and symbol addresses, while relocation counts and checksums
+    // stay empty. For "will it link?" and size estimates.
+    pub fn link_dry_run(
+        &mut self,
+        objs_in: BTreeMap<ObjectID, ObjectIn>,
+        static_libs: Vec<StaticLib>,
+        wrap_routines: Vec<SymbolName>,
+    ) -> Result<LinkerInfo, LinkError> {
+        self.do_link(
+            objs_in,
+            static_libs,
+            wrap_routines,
+            LinkObjType::Executable,
+            true,
+        )
+        .map(|(_, _, info)| info)
+    }
+
+    // Two-phase alternative to link, for callers that find their objects a
+    // batch at a time: add_objects any number of times, then finalize once.
+    // link is still the one-shot path and ignores anything added here.
+    pub fn add_objects(&mut self, objs_in: BTreeMap<ObjectID, ObjectIn>) -> Result<(), LinkError> {
+        if objs_in
+            .keys()
+            .any(|obj_id| self.pending_objects.contains_key(obj_id))
+        {
+            return Err(LinkError::DuplicateObjectError);
+        }
+        self.pending_objects.extend(objs_in);
+        Ok(())
+    }
+
+    // Link everything added with add_objects so far
+    pub fn finalize(
+        &mut self,
+        static_libs: Vec<StaticLib>,
+        wrap_routines: Vec<SymbolName>,
+    ) -> Result<(ObjectOut, LinkerInfo), LinkError> {
+        let objs_in = std::mem::take(&mut self.pending_objects);
+        self.link(objs_in, static_libs, wrap_routines)
+    }
+
     pub fn link_lib(
         &mut self,
         objs_in: BTreeMap<ObjectID, ObjectIn>,
         static_libs: Vec<StaticLib>,
         wrap_routines: Vec<SymbolName>,
     ) -> Result<(ObjectOut, StaticLib, LinkerInfo), LinkError> {
-        match self.do_link(objs_in, static_libs, wrap_routines, LinkObjType::SharedLib) {
+        match self.do_link(
+            objs_in,
+            static_libs,
+            wrap_routines,
+            LinkObjType::SharedLib,
+            false,
+        ) {
             Err(e) => Err(e),
             Ok((out, lib, info)) => Ok((out, lib.unwrap(), info)),
         }
@@ -153,18 +787,36 @@ impl LinkerEditor {
         static_libs: Vec<StaticLib>,
         wrap_routines: Vec<SymbolName>,
         link_obj_ty: LinkObjType,
+        dry_run: bool,
     ) -> Result<(ObjectOut, Option<StaticLib>, LinkerInfo), LinkError> {
         let mut out = ObjectOut::new();
+        out.endianness = self.endianness;
         let mut info = LinkerInfo::new();
 
+        // renames first: routines to wrap go by their new names
+        self.apply_symbol_renames(&mut objs_in)?;
+
         // wrap specified routines
         self.wrap_routines(&mut objs_in, &wrap_routines)?;
 
+        // symbols defined at link time go in first, so that any object
+        // defining them as well is caught as a duplicate
+        for (name, addr) in self.defined_symbols.iter() {
+            info.global_symtable.insert(
+                self.symbol_resolution.key(name),
+                GlobalSymbol::defined(Defn::defsym_defn(*addr)),
+            );
+        }
+
         // initial pass over input objects
         let mut got_size = 0;
-        for (obj_id, obj) in objs_in.into_iter() {
+        let nobjs = objs_in.len();
+        self.progress(LinkPhase::Allocation, 0, nobjs);
+        for (i, (obj_id, obj)) in objs_in.into_iter().enumerate() {
+            let obj = self.split_read_only_data(obj);
             got_size += self.alloc_storage_and_symtables(&obj_id, &obj, &mut out, &mut info)?;
             self.session_objects.insert(obj_id, obj);
+            self.progress(LinkPhase::Allocation, i + 1, nobjs);
         }
 
         self.logger
@@ -172,18 +824,57 @@ impl LinkerEditor {
         self.logger
             .debug(format!("Info (initial allocation):\n{}", info.ppr()).as_str());
 
-        let mut undef_syms: Vec<SymbolName> = vec![];
-        // check if all definitions are in place. if not - check/link libaries
-        for (name, (defn, _)) in info.global_symtable.iter() {
-            if defn.is_none() {
-                undef_syms.push(name.clone());
+        // check if all definitions are in place. if not - check/link libaries,
+        // a search unit (see LibSearch) at a time
+        let units = self.lib_search.units(static_libs.len());
+        let nunits = units.len();
+        self.progress(LinkPhase::LibraryResolution, 0, nunits);
+        for (i, unit) in units.into_iter().enumerate() {
+            let mut undef_syms: Vec<SymbolName> = vec![];
+            for (name, gsym) in info.global_symtable.iter() {
+                if !gsym.is_defined() {
+                    undef_syms.push(name.clone());
+                }
+            }
+            for name in self.force_include.iter() {
+                let name = self.symbol_resolution.key(name);
+                if !info
+                    .global_symtable
+                    .get(&name)
+                    .is_some_and(GlobalSymbol::is_defined)
+                    && !undef_syms.contains(&name)
+                {
+                    undef_syms.push(name);
+                }
+            }
+            if undef_syms.is_empty() {
+                break;
             }
-        }
-        if !undef_syms.is_empty() {
             self.logger
                 .info(&format!("Undefined symbols:\n  {undef_syms:?}"));
-            self.logger.info("Checking static libs");
-            self.static_libs_symbol_lookup(&mut out, &mut info, &mut undef_syms, &static_libs)?;
+            self.logger.info(&format!("Checking static libs {unit:?}"));
+            self.static_libs_symbol_lookup(
+                &mut out,
+                &mut info,
+                &mut undef_syms,
+                &static_libs[unit],
+                &static_libs,
+            )?;
+            if i + 1 < nunits {
+                self.progress(LinkPhase::LibraryResolution, i + 1, nunits);
+            }
+        }
+        if nunits > 0 {
+            self.progress(LinkPhase::LibraryResolution, nunits, nunits);
+        }
+        // shared libraries are listed in the order they were given
+        info.shared_deps
+            .sort_by_key(|libname| static_libs.iter().position(|l| l.get_name() == libname));
+
+        self.pad_segments(&mut out)?;
+        let got_size = self.got_size(got_size)?;
+        if self.got_mode == GotMode::Disable && (got_size != 0 || self.uses_got()) {
+            return Err(LinkError::GotRequiredButDisabled);
         }
 
         // update segment offsets
@@ -196,21 +887,70 @@ impl LinkerEditor {
         // Implement Unix-style common blocks. That is, scan the symbol table for undefined symbols
         // with non-zero values, and add space of appropriate size to the .bss segment.
         self.common_block_allocation(&mut out, &mut info, bss_start);
+        self.warn_empty_segments_at_zero(&out);
+
+        // names of the shared libraries to load, for the runtime linker
+        self.alloc_shared_libs_segment(&mut out, &mut info)?;
+
+        // code may refer to the GOT by name rather than through GA4
+        self.define_got_symbol(&out, &mut info)?;
+
+        self.check_memory_regions(&out)?;
 
         // Check for undefined symbols
-        if info
+        let undef_syms: Vec<&SymbolName> = info
             .global_symtable
-            .values()
-            .any(|(defn, _)| defn.is_none())
-        {
-            return Err(LinkError::UndefinedSymbolError);
+            .iter()
+            .filter(|(_, gsym)| !gsym.is_defined())
+            .map(|(name, _)| name)
+            .collect();
+        if !undef_syms.is_empty() {
+            if self.undefined_policy == UndefinedPolicy::Error {
+                return Err(LinkError::UndefinedSymbolError);
+            }
+            self.logger
+                .info(&format!("Leaving undefined symbols:\n  {undef_syms:?}"));
         }
 
         // resolve global symbols offsets
         self.resolve_global_sym_offsets(&mut info);
+        if let Some(entry) = &self.entry {
+            info.entry_addr = info
+                .global_symtable
+                .get(&self.symbol_resolution.key(entry))
+                .and_then(GlobalSymbol::address);
+            if info.entry_addr.is_none() {
+                self.logger
+                    .info(&format!("Entry symbol {entry} is not defined"));
+            }
+        }
+
+        for (segment_name, seg) in out.segments.iter() {
+            info.segment_sizes
+                .insert(segment_name.clone(), seg.segment_len);
+        }
+        if let Some((_, msg)) = self.assertions.iter().find(|(f, _)| !f(&info, &out)) {
+            return Err(LinkError::AssertionFailed(msg.clone()));
+        }
+        if dry_run {
+            self.logger.debug("Dry run complete");
+            return Ok((out, None, info));
+        }
 
         // perform relocations
-        self.run_relocations(&mut out, &info)?;
+        let out_relocations = out.relocations.len();
+        let (counts, deferred, got_entries) = self.run_relocations(&mut out, &info)?;
+        info.relocation_counts = counts;
+        info.got_entries = got_entries;
+        info.er4_relocations = out.relocations.len() - out_relocations;
+        info.deferred_relocations = deferred;
+        if !info.deferred_relocations.is_empty() {
+            self.logger.info(&info.deferred_relocations_report());
+        }
+        for (segment_name, segment_data) in out.object_data.iter() {
+            info.segment_checksums
+                .insert(segment_name.clone(), segment_data.crc32());
+        }
 
         /////////////////////////////////////////////
         self.logger.debug("Linking complete");
@@ -239,32 +979,65 @@ impl LinkerEditor {
             obj_id,
             obj.ppr(true).as_str()
         ));
+        // objects built for another target must not be mixed in
+        if let Some(arch) = &obj.arch {
+            match &info.arch {
+                Some(link_arch) if link_arch != arch => return Err(LinkError::ArchMismatch),
+                Some(_) => {}
+                None => info.arch = Some(arch.clone()),
+            }
+        }
+
         let mut seg_offsets = BTreeMap::new();
+        let mut seg_lens = BTreeMap::new();
         for (i, segment) in obj.segments.iter().enumerate() {
+            seg_lens.insert(segment.segment_name.clone(), segment.segment_len);
             // allocate storage
-            out.segments
-                .entry(segment.segment_name.clone())
-                .and_modify(|out_seg| {
-                    let seg_offset = out_seg.segment_len;
+            match out.segments.get_mut(&segment.segment_name) {
+                Some(out_seg) => {
+                    // pad up to the alignment this module asks for
+                    let seg_offset = find_seg_start(out_seg.segment_len, segment.segment_align);
                     seg_offsets.insert(segment.segment_name.clone(), seg_offset);
-                    out_seg.segment_len = seg_offset + segment.segment_len;
+                    out_seg.segment_len = match seg_offset.checked_add(segment.segment_len) {
+                        None => return Err(LinkError::IntOverflowError),
+                        Some(len) => len,
+                    };
+                    // the output segment is aligned for its strictest module
+                    out_seg.segment_align = out_seg.segment_align.max(segment.segment_align);
+                    // and gets the descriptors of all its modules, unless their
+                    // permissions must agree (see set_strict_segment_permissions)
+                    if self.strict_segment_permissions
+                        && Self::rw(&out_seg.segment_descr) != Self::rw(&segment.segment_descr)
+                    {
+                        return Err(LinkError::SegmentPermissionConflict(
+                            segment.segment_name.clone(),
+                        ));
+                    }
+                    for descr in segment.segment_descr.iter() {
+                        if !out_seg.segment_descr.contains(descr) {
+                            out_seg.segment_descr.push(descr.clone());
+                        }
+                    }
                     self.logger.debug(&format!(
                         "new len for {}: 0x{:X} + 0x{:X} = 0x{:X}",
                         segment.segment_name, seg_offset, segment.segment_len, out_seg.segment_len
                     ));
-                })
-                .or_insert_with(|| {
+                }
+                None => {
                     out.nsegs += 1;
                     seg_offsets.insert(segment.segment_name.clone(), 0);
                     let mut s = segment.clone();
                     s.segment_start = 0;
-                    s
-                });
+                    out.segments.insert(segment.segment_name.clone(), s);
+                }
+            }
             // object data
+            let seg_offset = seg_offsets[&segment.segment_name] as usize;
             out.object_data
                 .entry(segment.segment_name.clone())
                 .and_modify(|segment_data| {
-                    *segment_data = segment_data.concat(&obj.object_data[i]);
+                    let padding = SegmentData::new(seg_offset - segment_data.len());
+                    *segment_data = segment_data.concat(&padding).concat(&obj.object_data[i]);
                 })
                 .or_insert_with(|| obj.object_data[i].clone());
         }
@@ -275,11 +1048,12 @@ impl LinkerEditor {
         }
 
         info.segment_mapping.insert(obj_id.to_string(), seg_offsets);
+        info.segment_lengths.insert(obj_id.to_string(), seg_lens);
         // common blocks
         for ste in obj.symbol_table.iter() {
             if ste.is_common_block() {
                 info.common_block_mapping
-                    .entry(ste.st_name.clone())
+                    .entry(self.symbol_resolution.key(&ste.st_name))
                     .and_modify({
                         |size| {
                             if ste.st_value > *size {
@@ -300,7 +1074,9 @@ impl LinkerEditor {
 
         let mut got_size = 0;
         for r in obj.relocations.iter() {
-            if r.rel_type == RelType::GP4 {
+            // pinned slots are counted once all objects are in, see got_size
+            if (r.rel_type == RelType::GP4 && r.got_slot().is_none()) || r.rel_type == RelType::GPS4
+            {
                 got_size += 4;
             }
         }
@@ -322,38 +1098,53 @@ impl LinkerEditor {
             if symbol.is_common_block() {
                 continue;
             };
+            // local symbols are resolved within their module (see symbol_addr)
+            // and go in namespaced by it
+            let name = if symbol.is_local() {
+                self.symbol_resolution.key(&SymbolName::Local(
+                    symbol.st_name.base().to_owned(),
+                    obj_id.to_string(),
+                ))
+            } else {
+                self.symbol_resolution.key(&symbol.st_name)
+            };
             // if symbol already defined in global table - error out
             if symbol.is_defined()
                 && info
                     .global_symtable
-                    .get(&symbol.st_name)
-                    .map_or(false, |x| x.0.is_some())
+                    .get(&name)
+                    .is_some_and(GlobalSymbol::is_defined)
             {
                 return Some(LinkError::MultipleSymbolDefinitions);
             }
             info.global_symtable
-                .entry(symbol.st_name.clone())
-                .and_modify(|(defn, refs)| {
+                .entry(name)
+                .and_modify(|gsym| {
                     if symbol.is_defined() {
-                        assert!(defn.is_none());
-                        *defn = Some(Defn::new(obj_id.to_string(), i, None));
+                        assert!(!gsym.is_defined());
+                        gsym.defn = Some(Defn::new(obj_id.to_string(), i, None));
                     } else {
-                        refs.insert(obj_id.to_string(), i);
+                        gsym.refs.insert(obj_id.to_string(), i);
                     }
                 })
                 .or_insert_with(|| {
                     if symbol.is_defined() {
-                        (Some(Defn::new(obj_id.to_string(), i, None)), HashMap::new())
+                        GlobalSymbol::defined(Defn::new(obj_id.to_string(), i, None))
                     } else {
                         let mut refs = HashMap::new();
                         refs.insert(obj_id.to_string(), i);
-                        (None, refs)
+                        GlobalSymbol::new(None, refs)
                     }
                 });
         }
         None
     }
 
+    // Empty segments take no room: the segment after one starts where the
+    // empty one does. With text_start 0 and an empty TEXT (a data-only link),
+    // DATA starts at 0 too. ObjectOut::verify doesn't take that for an overlap,
+    // but see warn_empty_segments_at_zero.
+    //
     // Update TEXT start and patch segment addrs in link info
     // If we are building PiC - factor in and allocate global offset table.
     // Then do the same patching in DATA segment - update start and adjust address in info.
@@ -366,46 +1157,245 @@ impl LinkerEditor {
         got_size: i32,
     ) -> i32 {
         self.patch_text_seg(out, info);
-        if got_size != 0 {
+        self.patch_rodata_seg(out, info);
+        let alloc_got = match self.got_mode {
+            GotMode::Auto => got_size != 0,
+            GotMode::Force => true,
+            GotMode::Disable => false,
+        };
+        if alloc_got {
             self.logger.debug("GOT segment will be allocated");
-            self.alloc_got(out, got_size);
         }
-        // TODO: what if those segments are missing? ..
+        if alloc_got && self.got_placement == GotPlacement::AfterText {
+            self.alloc_got(out, got_size, SegmentName::RODATA);
+        }
         self.patch_data_seg(out, info);
+        if alloc_got && self.got_placement == GotPlacement::AfterData {
+            self.alloc_got(out, got_size, SegmentName::DATA);
+        }
         self.patch_bss_seg(out, info)
     }
 
-    fn patch_text_seg(&mut self, out: &mut ObjectOut, info: &mut LinkerInfo) {
-        out.segments
-            .entry(SegmentName::TEXT)
-            .and_modify(|s| s.segment_start = self.text_start);
-        for (_, addrs) in info.segment_mapping.iter_mut() {
-            addrs.entry(SegmentName::TEXT).and_modify(|addr| {
-                *addr += self.text_start;
-            });
+    fn pad_segments(&mut self, out: &mut ObjectOut) -> Result<(), LinkError> {
+        for (name, (to_len, fill)) in self.padding.iter() {
+            let Some(seg) = out.segments.get_mut(name) else {
+                continue;
+            };
+            if seg.segment_len > *to_len {
+                return Err(LinkError::SegmentExceedsPadding(name.clone()));
+            }
+            let pad = vec![*fill; (*to_len - seg.segment_len) as usize];
+            if let Some(data) = out.object_data.get_mut(name) {
+                data.splice(data.len(), 0, &pad)?;
+            }
+            self.logger.debug(&format!(
+                "Padding {name} from {:X} to {to_len:X}",
+                seg.segment_len
+            ));
+            seg.segment_len = *to_len;
         }
+        Ok(())
     }
 
-    fn alloc_got(&self, out: &mut ObjectOut, got_size: i32) {
-        let mut got_segment = Segment::new(SegmentName::GOT);
-        let text_end = out.segments.get(&SegmentName::TEXT).unwrap().segment_start
-            + out.segments.get(&SegmentName::TEXT).unwrap().segment_len;
-        got_segment.segment_start = text_end;
-        got_segment.segment_len = got_size;
-        out.segments.insert(SegmentName::GOT, got_segment);
-        out.object_data
-            .insert(SegmentName::GOT, SegmentData::new(got_size as usize));
+    // Size of the GOT with slots_size bytes of slots given out in order, which
+    // take the lowest slots GP4 relocations are not pinned to
+    fn got_size(&self, slots_size: i32) -> Result<i32, LinkError> {
+        let pinned = self.pinned_got_slots()?;
+        let mut slots = slots_size as usize / 4;
+        let mut nslots = 0;
+        while slots > 0 {
+            if !pinned.contains_key(&nslots) {
+                slots -= 1;
+            }
+            nslots += 1;
+        }
+        let nslots = pinned
+            .keys()
+            .next_back()
+            .map_or(nslots, |last| nslots.max(last + 1));
+        Ok(nslots as i32 * 4)
     }
 
-    fn patch_data_seg(&mut self, out: &mut ObjectOut, info: &mut LinkerInfo) {
-        let last_seg_name = match out.segments.get(&SegmentName::GOT) {
-            Some(_) => SegmentName::GOT,
-            None => SegmentName::TEXT,
-        };
-        let last_seg_end = out.segments.get(&last_seg_name).unwrap().segment_start
-            + out.segments.get(&last_seg_name).unwrap().segment_len;
-        let data_start = find_seg_start(last_seg_end, self.data_start_boundary);
-        out.segments
+    // whether any relocation of the objects linked refers to the GOT
+    fn uses_got(&self) -> bool {
+        self.session_objects
+            .values()
+            .any(|obj| obj.relocations.iter().any(|r| r.rel_type.uses_got()))
+    }
+
+    // GOT slots GP4 relocations are pinned to, and the symbol in each
+    fn pinned_got_slots(&self) -> Result<BTreeMap<usize, SymbolName>, LinkError> {
+        let mut pinned = BTreeMap::new();
+        for (modname, obj) in self.session_objects.iter() {
+            for r in obj.relocations.iter() {
+                if let Some(slot) = r.got_slot() {
+                    let name = self.symbol_resolution.key(&self.got_slot_name(modname, r));
+                    if *pinned.entry(slot).or_insert_with(|| name.clone()) != name {
+                        return Err(LinkError::GotSlotConflict(slot));
+                    }
+                }
+            }
+        }
+        Ok(pinned)
+    }
+
+    // Address 0 often means "not set" to whoever reads the output, so an empty
+    // segment there alongside another one is pointed out
+    fn warn_empty_segments_at_zero(&mut self, out: &ObjectOut) {
+        let at_zero: Vec<&Segment> = SegmentName::order()
+            .iter()
+            .filter_map(|name| out.segments.get(name))
+            .filter(|seg| seg.segment_start == 0)
+            .collect();
+        for empty in at_zero.iter().filter(|seg| seg.segment_len == 0) {
+            if let Some(seg) = at_zero.iter().find(|seg| seg.segment_len > 0) {
+                self.logger.warn(&format!(
+                    "Empty {} shares address 0 with {}",
+                    empty.segment_name, seg.segment_name
+                ));
+            }
+        }
+    }
+
+    fn patch_text_seg(&mut self, out: &mut ObjectOut, info: &mut LinkerInfo) {
+        let text_start = self.place(out, &SegmentName::TEXT, self.text_start);
+        out.segments
+            .entry(SegmentName::TEXT)
+            .and_modify(|s| s.segment_start = text_start);
+        for (_, addrs) in info.segment_mapping.iter_mut() {
+            addrs.entry(SegmentName::TEXT).and_modify(|addr| {
+                *addr += text_start;
+            });
+        }
+    }
+
+    // Whether descriptors make a segment readable and writable
+    fn rw(descr: &[SegmentDescr]) -> (bool, bool) {
+        (
+            descr.contains(&SegmentDescr::R),
+            descr.contains(&SegmentDescr::W),
+        )
+    }
+
+    // R and W a segment needs from its memory region. Segments the linker
+    // makes itself may have no descriptors yet.
+    fn seg_perms(out: &ObjectOut, segname: &SegmentName) -> Vec<SegmentDescr> {
+        match out.segments.get(segname) {
+            Some(seg) if !seg.segment_descr.is_empty() => seg.segment_descr.clone(),
+            _ => match segname {
+                SegmentName::TEXT | SegmentName::RODATA | SegmentName::LIB => {
+                    vec![SegmentDescr::R]
+                }
+                _ => vec![SegmentDescr::R, SegmentDescr::W],
+            },
+        }
+    }
+
+    // Where a segment that would follow on at addr starts: at addr, unless
+    // addr is outside the segment's memory region
+    fn place(&self, out: &ObjectOut, segname: &SegmentName, addr: i32) -> i32 {
+        let region = self
+            .memory_regions
+            .as_ref()
+            .and_then(|regions| regions.region_for(&LinkerEditor::seg_perms(out, segname)));
+        match region {
+            Some(region) if !region.contains(addr) => region.start,
+            _ => addr,
+        }
+    }
+
+    fn check_memory_regions(&self, out: &ObjectOut) -> Result<(), LinkError> {
+        let regions = match &self.memory_regions {
+            None => return Ok(()),
+            Some(regions) => regions,
+        };
+        for (segment_name, seg) in out.segments.iter() {
+            let region = regions
+                .region_for(&LinkerEditor::seg_perms(out, segment_name))
+                .ok_or_else(|| LinkError::NoMemoryRegion(segment_name.clone()))?;
+            let needed = seg.segment_start + seg.segment_len - region.start;
+            if seg.segment_start < region.start || needed > region.len {
+                return Err(LinkError::RegionOverflow {
+                    region: region.name.clone(),
+                    needed,
+                    available: region.len,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    // A missing segment takes no space: it ends where the one before it does.
+    // Objects with nothing but common blocks may leave out any segment.
+    fn seg_end(&self, out: &ObjectOut, segname: &SegmentName) -> i32 {
+        match (out.segments.get(segname), segname) {
+            (Some(seg), _) => seg.segment_start + seg.segment_len,
+            (None, SegmentName::DATA)
+                if self.got_placement == GotPlacement::AfterText
+                    && out.segments.contains_key(&SegmentName::GOT) =>
+            {
+                self.seg_end(out, &SegmentName::GOT)
+            }
+            (None, SegmentName::DATA) => self.seg_end(out, &SegmentName::RODATA),
+            (None, SegmentName::RODATA) => self.seg_end(out, &SegmentName::TEXT),
+            (None, SegmentName::BSS)
+                if self.got_placement == GotPlacement::AfterData
+                    && out.segments.contains_key(&SegmentName::GOT) =>
+            {
+                self.seg_end(out, &SegmentName::GOT)
+            }
+            (None, SegmentName::BSS) => self.seg_end(out, &SegmentName::DATA),
+            (None, _) => self.text_start,
+        }
+    }
+
+    fn seg_align(out: &ObjectOut, segname: &SegmentName) -> i32 {
+        out.segments.get(segname).map_or(0, |seg| seg.segment_align)
+    }
+
+    fn alloc_got(&self, out: &mut ObjectOut, got_size: i32, after: SegmentName) {
+        let mut got_segment = Segment::new(SegmentName::GOT);
+        let prev_end = self.place(out, &SegmentName::GOT, self.seg_end(out, &after));
+        got_segment.segment_start = self.got_start_boundary.align(prev_end);
+        got_segment.segment_len = got_size;
+        out.segments.insert(SegmentName::GOT, got_segment);
+        out.nsegs += 1;
+        out.object_data
+            .insert(SegmentName::GOT, SegmentData::new(got_size as usize));
+    }
+
+    // Read-only data follows TEXT, at the alignment its modules need
+    fn patch_rodata_seg(&mut self, out: &mut ObjectOut, info: &mut LinkerInfo) {
+        let last_seg_end = self.place(
+            out,
+            &SegmentName::RODATA,
+            self.seg_end(out, &SegmentName::TEXT),
+        );
+        let rodata_start = find_seg_start(
+            last_seg_end,
+            LinkerEditor::seg_align(out, &SegmentName::RODATA),
+        );
+        out.segments
+            .entry(SegmentName::RODATA)
+            .and_modify(|s| s.segment_start = rodata_start);
+        for (_, addrs) in info.segment_mapping.iter_mut() {
+            addrs.entry(SegmentName::RODATA).and_modify(|addr| {
+                *addr += rodata_start;
+            });
+        }
+    }
+
+    fn patch_data_seg(&mut self, out: &mut ObjectOut, info: &mut LinkerInfo) {
+        let last_seg_name = match out.segments.get(&SegmentName::GOT) {
+            Some(_) => SegmentName::GOT,
+            None => SegmentName::RODATA,
+        };
+        let last_seg_end = self.place(out, &SegmentName::DATA, self.seg_end(out, &last_seg_name));
+        let data_start = find_seg_start(
+            self.data_start_boundary.align(last_seg_end),
+            LinkerEditor::seg_align(out, &SegmentName::DATA),
+        );
+        out.segments
             .entry(SegmentName::DATA)
             .and_modify(|s| s.segment_start = data_start);
         for (_, addrs) in info.segment_mapping.iter_mut() {
@@ -416,9 +1406,17 @@ impl LinkerEditor {
     }
 
     fn patch_bss_seg(&mut self, out: &mut ObjectOut, info: &mut LinkerInfo) -> i32 {
-        let data_end = out.segments.get(&SegmentName::DATA).unwrap().segment_start
-            + out.segments.get(&SegmentName::DATA).unwrap().segment_len;
-        let bss_start = find_seg_start(data_end, self.bss_start_boundary);
+        let last_seg_name = match self.got_placement {
+            GotPlacement::AfterData if out.segments.contains_key(&SegmentName::GOT) => {
+                SegmentName::GOT
+            }
+            _ => SegmentName::DATA,
+        };
+        let last_seg_end = self.place(out, &SegmentName::BSS, self.seg_end(out, &last_seg_name));
+        let bss_start = find_seg_start(
+            self.bss_start_boundary.align(last_seg_end),
+            LinkerEditor::seg_align(out, &SegmentName::BSS),
+        );
         out.segments
             .entry(SegmentName::BSS)
             .and_modify(|s| s.segment_start = bss_start);
@@ -436,7 +1434,27 @@ impl LinkerEditor {
         info: &mut LinkerInfo,
         bss_start: i32,
     ) {
+        // a common symbol that some object defines is just a reference to
+        // that definition and takes no space
+        info.common_block_mapping.retain(|name, _| {
+            info.global_symtable
+                .get(name)
+                .is_none_or(|gsym| !gsym.is_defined())
+        });
         let common_block = info.common_block_mapping.values().sum();
+        // the rest go at the end of BSS (in name order) and get a definition there
+        let mut common_addr = bss_start
+            + out
+                .segments
+                .get(&SegmentName::BSS)
+                .map_or(0, |seg| seg.segment_len);
+        let mut common_syms: Vec<_> = info.common_block_mapping.iter().collect();
+        common_syms.sort();
+        for (name, size) in common_syms {
+            let gsym = info.global_symtable.entry(name.clone()).or_default();
+            gsym.defn = Some(Defn::common_block_defn(common_addr));
+            common_addr += size;
+        }
         if common_block != 0 {
             self.logger
                 .debug(format!("Appending common block of size {common_block:X} to BSS:").as_str());
@@ -458,17 +1476,82 @@ impl LinkerEditor {
         }
     }
 
+    // The .lib segment goes last. It holds the names of the shared libraries
+    // the output depends on, each NUL terminated, and SHARED_LIBS_SYMBOL
+    // is defined at its start.
+    fn alloc_shared_libs_segment(
+        &mut self,
+        out: &mut ObjectOut,
+        info: &mut LinkerInfo,
+    ) -> Result<(), LinkError> {
+        if info.shared_deps.is_empty() {
+            return Ok(());
+        }
+        let mut names = vec![];
+        for libname in info.shared_deps.iter() {
+            names.extend_from_slice(libname.as_bytes());
+            names.push(0);
+        }
+        let mut lib_segment = Segment::new(SegmentName::LIB);
+        lib_segment.segment_start =
+            self.place(out, &SegmentName::LIB, self.seg_end(out, &SegmentName::BSS));
+        lib_segment.segment_len = names.len() as i32;
+        lib_segment.segment_descr = vec![SegmentDescr::R, SegmentDescr::P];
+        let name = self
+            .symbol_resolution
+            .key(&SymbolName::SName(SHARED_LIBS_SYMBOL.to_owned()));
+        let gsym = info.global_symtable.entry(name).or_default();
+        if gsym.is_defined() {
+            return Err(LinkError::MultipleSymbolDefinitions);
+        }
+        gsym.defn = Some(Defn::shared_libs_defn(lib_segment.segment_start));
+        self.logger.debug(&format!(
+            "Listing shared libraries {:?} in {} at 0x{:X}",
+            info.shared_deps,
+            SegmentName::LIB,
+            lib_segment.segment_start
+        ));
+        let mut segment_data = SegmentData::new(names.len());
+        segment_data.write_bytes(0, &names);
+        out.segments.insert(SegmentName::LIB, lib_segment);
+        out.object_data.insert(SegmentName::LIB, segment_data);
+        out.nsegs += 1;
+        Ok(())
+    }
+
+    fn define_got_symbol(&self, out: &ObjectOut, info: &mut LinkerInfo) -> Result<(), LinkError> {
+        let name = self
+            .symbol_resolution
+            .key(&SymbolName::SName(GOT_SYMBOL.to_owned()));
+        if let Some(gsym) = info.global_symtable.get_mut(&name) {
+            if !gsym.is_defined() {
+                match out.segments.get(&SegmentName::GOT) {
+                    None => return Err(LinkError::NoGlobalOffsetTable),
+                    Some(got) => gsym.defn = Some(Defn::got_defn(got.segment_start)),
+                }
+            }
+        }
+        Ok(())
+    }
+
     // this assumes all definitions have been spotted and are in place
     fn resolve_global_sym_offsets(&mut self, info: &mut LinkerInfo) {
         let syms: Vec<_> = info.global_symtable.keys().collect();
         self.logger.debug(&format!(
             "Resolving global symbol offsets\n  Symbols: {syms:?}"
         ));
-        for (defn, _) in info.global_symtable.values_mut() {
+        for GlobalSymbol { defn, .. } in info.global_symtable.values_mut() {
             self.logger.debug(&format!("Looking at {defn:?}"));
-            if let Some(Defn {
+            if defn.is_none() {
+                continue; // left undefined, see UndefinedPolicy
+            } else if let Some(Defn {
                 defn_addr: Some(_),
-                defn_prov: DefnProvenance::FromSharedLib(..),
+                defn_prov:
+                    DefnProvenance::FromSharedLib(..)
+                    | DefnProvenance::FromDefSym
+                    | DefnProvenance::FromCommonBlock
+                    | DefnProvenance::FromGot
+                    | DefnProvenance::FromLibSegment,
                 ..
             }) = defn
             {
@@ -481,10 +1564,14 @@ impl LinkerEditor {
             }) = defn
             {
                 let ste: &SymbolTableEntry = &info.symbol_tables.get(defn_mod_id).unwrap()[*ste_ix];
-                assert!(ste.st_seg > 0);
-                let seg_i = ste.st_seg as usize - 1;
-                let sym_seg =
-                    &self.session_objects.get(defn_mod_id).unwrap().segments[seg_i].segment_name;
+                if ste.st_seg == 0 {
+                    *defn_addr = Some(ste.st_value); // absolute
+                    continue;
+                }
+                let sym_seg = &self.session_objects[defn_mod_id]
+                    .segment_by_ordinal(ste.st_seg)
+                    .unwrap()
+                    .segment_name;
                 let segment_offset = *info
                     .segment_mapping
                     .get(defn_mod_id)
@@ -498,82 +1585,141 @@ impl LinkerEditor {
         }
     }
 
+    // Whether static_libs_symbol_lookup would pull member obj_name of lib
+    // into a link that has got as far as info: it defines a symbol that is
+    // still undefined, and is not in the link already. Members of a FileLib
+    // go by "{libname}_mod_{offset}". Members only defining weak symbols
+    // count, as they would go in for want of anything else. Stubs never do.
+    pub fn would_include(&self, info: &LinkerInfo, lib: &StaticLib, obj_name: &str) -> bool {
+        if info.lib_objects.contains(obj_name) {
+            return false;
+        }
+        info.global_symtable
+            .iter()
+            .filter(|(_, gsym)| !gsym.is_defined())
+            .any(|(name, _)| {
+                self.lib_members_defining(lib, name)
+                    .iter()
+                    .any(|member| member.id == obj_name)
+            })
+    }
+
+    // Members of lib defining sym, in the order static_libs_symbol_lookup
+    // tries them: DirLib members by name, FileLib members by offset. Stubs
+    // have none, their symbols are resolved without pulling anything in.
+    fn lib_members_defining<'a>(&self, lib: &'a StaticLib, sym: &SymbolName) -> Vec<LibMember<'a>> {
+        let resolution = self.symbol_resolution;
+        match lib {
+            StaticLib::DirLib {
+                symbols,
+                weak_symbols,
+                objects,
+                ..
+            } => symbols
+                .iter()
+                .filter_map(|(name, syms)| {
+                    let defined: Vec<&SymbolName> =
+                        syms.iter().filter(|s| resolution.matches(s, sym)).collect();
+                    if defined.is_empty() {
+                        return None;
+                    }
+                    let weak = weak_symbols
+                        .get(name)
+                        .is_some_and(|weak| defined.iter().all(|s| weak.contains(*s)));
+                    Some(LibMember {
+                        id: name.clone(),
+                        obj: objects.get(name),
+                        weak,
+                    })
+                })
+                .collect(),
+            StaticLib::FileLib {
+                symbols,
+                objects,
+                libname,
+            } => {
+                let mut offsets: Vec<usize> = symbols
+                    .iter()
+                    .filter(|(s, _)| resolution.matches(s, sym))
+                    .map(|(_, &offset)| offset)
+                    .collect();
+                offsets.sort_unstable();
+                offsets.dedup();
+                offsets
+                    .into_iter()
+                    .map(|offset| LibMember {
+                        id: format!("{libname}_mod_{offset}"),
+                        obj: objects.get(offset),
+                        weak: false,
+                    })
+                    .collect()
+            }
+            StaticLib::Stub(_) => vec![],
+        }
+    }
+
+    // Pull in library members for undef_syms, and for whatever those members
+    // need in turn, until static_libs has nothing more to offer. all_libs is
+    // where stubs look for the libraries they refer to.
     fn static_libs_symbol_lookup(
         &mut self,
         out: &mut ObjectOut,
         info: &mut LinkerInfo,
         undef_syms: &mut Vec<SymbolName>,
         static_libs: &[StaticLib],
+        all_libs: &[StaticLib],
     ) -> Result<(), LinkError> {
         let mut visited_libs_objs: HashSet<String> = HashSet::new();
-        while !undef_syms.is_empty() {
-            let undef_sym = undef_syms.pop().unwrap();
-
+        // symbols only weak members were found for, see StaticLib::DirLib
+        let mut weak_only: Vec<SymbolName> = vec![];
+        loop {
+            let (undef_sym, allow_weak) = match undef_syms.pop() {
+                Some(sym) => (sym, false),
+                None => {
+                    // weak members go in last, for what is still undefined
+                    weak_only.retain(|sym| {
+                        info.global_symtable
+                            .get(&self.symbol_resolution.key(sym))
+                            .is_some_and(|gsym| !gsym.is_defined())
+                    });
+                    match weak_only.pop() {
+                        Some(sym) => (sym, true),
+                        None => break,
+                    }
+                }
+            };
             'outer: for lib in static_libs.iter() {
                 match lib {
-                    StaticLib::DirLib {
-                        symbols, objects, ..
-                    } => {
-                        for (lib_obj_name, lib_obj_syms) in symbols.iter() {
-                            if visited_libs_objs.contains(lib_obj_name) {
+                    StaticLib::DirLib { libname, .. } | StaticLib::FileLib { libname, .. } => {
+                        for member in self.lib_members_defining(lib, &undef_sym) {
+                            if visited_libs_objs.contains(&member.id) {
                                 continue;
                             }
-                            for lib_obj_sym in lib_obj_syms.iter() {
-                                if *lib_obj_sym == undef_sym {
-                                    // found symbol definition in this lib
-                                    self.logger.debug(&format!(
-                                        "Found symbol '{undef_sym}' in {lib_obj_name}"
-                                    ));
-                                    if let Some(lib_obj) = objects.get(lib_obj_name).cloned() {
-                                        self.alloc_storage_and_symtables(
-                                            lib_obj_name,
-                                            &lib_obj,
-                                            out,
-                                            info,
-                                        )?;
-                                        self.session_objects
-                                            .insert(lib_obj_name.to_string(), lib_obj.clone());
-                                        for ste in lib_obj.symbol_table.iter() {
-                                            if !ste.is_defined() {
-                                                undef_syms.push(ste.st_name.clone());
-                                            }
-                                        }
-                                        visited_libs_objs.insert(lib_obj_name.to_string());
-                                    }
-                                    break 'outer;
-                                }
+                            if member.weak && !allow_weak {
+                                self.logger.debug(&format!(
+                                    "Deferring weak symbol '{undef_sym}' in {}",
+                                    member.id
+                                ));
+                                weak_only.push(undef_sym.clone());
+                                continue;
                             }
-                        }
-                    }
-                    StaticLib::FileLib {
-                        symbols,
-                        objects,
-                        libname,
-                    } => {
-                        for (lib_obj_sym, obj_offset) in symbols.iter() {
-                            if *lib_obj_sym == undef_sym {
-                                // found symbol definition in this lib file
-                                if let Some(lib_obj) = objects.get(*obj_offset) {
-                                    let libobj_id = format!("{libname}_mod_{obj_offset}");
-                                    self.logger.debug(&format!(
-                                        "Found symbol '{undef_sym}' at offset {obj_offset}"
-                                    ));
-                                    if visited_libs_objs.contains(&libobj_id) {
-                                        continue;
-                                    }
-                                    self.alloc_storage_and_symtables(
-                                        &libobj_id, lib_obj, out, info,
-                                    )?;
-                                    self.session_objects
-                                        .insert(libobj_id.to_string(), lib_obj.clone());
-                                    for ste in lib_obj.symbol_table.iter() {
-                                        if !ste.is_defined() {
-                                            undef_syms.push(ste.st_name.clone());
-                                        }
+                            // found symbol definition in this lib
+                            self.logger
+                                .debug(&format!("Found symbol '{undef_sym}' in {}", member.id));
+                            if let Some(lib_obj) = member.obj {
+                                let lib_obj = self.split_read_only_data(lib_obj.clone());
+                                self.alloc_storage_and_symtables(&member.id, &lib_obj, out, info)?;
+                                for ste in lib_obj.symbol_table.iter() {
+                                    if !ste.is_defined() {
+                                        undef_syms.push(ste.st_name.clone());
                                     }
-                                    visited_libs_objs.insert(libobj_id);
                                 }
+                                self.session_objects.insert(member.id.clone(), lib_obj);
+                                info.used_libs.insert(libname.to_owned());
+                                info.lib_objects.insert(member.id.clone());
+                                visited_libs_objs.insert(member.id);
                             }
+                            break 'outer;
                         }
                     }
                     StaticLib::Stub(stublib) => {
@@ -582,12 +1728,18 @@ impl LinkerEditor {
                             if visited_libs_objs.contains(&libobj_id) {
                                 continue;
                             }
-                            match stub.syms.get(&undef_sym) {
+                            let resolution = self.symbol_resolution;
+                            let stub_sym = stub
+                                .syms
+                                .iter()
+                                .find(|(name, _)| resolution.matches(name, &undef_sym))
+                                .map(|(_, v)| v);
+                            match stub_sym {
                                 Some(Right(libname)) => {
                                     self.logger.debug(&format!(
                                         " Symbol defn is in '{undef_sym}' in other library '{libname}'\n"
                                     ));
-                                    for lib in static_libs.iter() {
+                                    for lib in all_libs.iter() {
                                         if let StaticLib::Stub(stublib) = lib {
                                             if &stublib.libname != libname {
                                                 continue;
@@ -597,15 +1749,18 @@ impl LinkerEditor {
                                                     return Err(LinkError::SharedLibRefDefnNotFound)
                                                 }
                                                 Some(sym_addr) => {
+                                                    info.used_libs.insert(libname.to_owned());
+                                                    info.add_shared_dependency(libname);
                                                     info.global_symtable
-                                                        .entry(undef_sym.to_owned())
-                                                        .and_modify(|(defn, _refs)| {
-                                                            assert!(defn.is_none());
-                                                            *defn = Some(Defn::shared_lib_defn(
-                                                                stub.name.clone(),
-                                                                sym_addr,
-                                                                stub.libname.to_owned(),
-                                                            ));
+                                                        .entry(resolution.key(&undef_sym))
+                                                        .and_modify(|gsym| {
+                                                            assert!(!gsym.is_defined());
+                                                            gsym.defn =
+                                                                Some(Defn::shared_lib_defn(
+                                                                    stub.name.clone(),
+                                                                    sym_addr,
+                                                                    stub.libname.to_owned(),
+                                                                ));
                                                         });
                                                     break 'outer;
                                                 }
@@ -618,16 +1773,18 @@ impl LinkerEditor {
                                         " Found defn for symbol '{undef_sym}' in '{}' stub member",
                                         stub.name
                                     ));
-                                    info.global_symtable.entry(undef_sym.to_owned()).and_modify(
-                                        |(defn, _refs)| {
-                                            assert!(defn.is_none());
-                                            *defn = Some(Defn::shared_lib_defn(
+                                    info.used_libs.insert(stublib.libname.to_owned());
+                                    info.add_shared_dependency(&stublib.libname);
+                                    info.global_symtable
+                                        .entry(resolution.key(&undef_sym))
+                                        .and_modify(|gsym| {
+                                            assert!(!gsym.is_defined());
+                                            gsym.defn = Some(Defn::shared_lib_defn(
                                                 stub.name.clone(),
                                                 *addr,
                                                 stub.libname.to_owned(),
                                             ));
-                                        },
-                                    );
+                                        });
                                     break 'outer;
                                 }
                                 None => {}
@@ -642,479 +1799,733 @@ impl LinkerEditor {
         Ok(())
     }
 
-    fn run_relocations(&mut self, out: &mut ObjectOut, info: &LinkerInfo) -> Result<(), LinkError> {
-        let mut got_offset = 0;
+    // parse_relocation checks refs, but objects built or modified in code
+    // may not have gone through it. BSS has no contents to relocate.
+    fn validate_relocations(&mut self) -> Result<(), LinkError> {
         for (modname, mod_obj) in self.session_objects.iter() {
-            if !mod_obj.relocations.is_empty() {
+            for r in mod_obj.relocations.iter() {
+                if r.rel_seg == SegmentName::BSS {
+                    return Err(LinkError::RelocationInBss);
+                }
+                let in_range = match r.rel_ref {
+                    RelRef::SegmentRef(seg_i) => seg_i < mod_obj.segments.len(),
+                    RelRef::SymbolRef(sym_i) => sym_i < mod_obj.symbol_table.len(),
+                    RelRef::NoRef => true,
+                };
+                if !in_range {
+                    return Err(LinkError::RelocationRefOutOfRange);
+                }
+            }
+            if self.warn_common_block_refs {
+                for w in common_block_ref_warnings(&mod_obj.relocations, &mod_obj.symbol_table) {
+                    self.logger.warn(&format!("{modname}: {w}"));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Absolute address of symbol sym_i of a module. Local symbols are resolved
+    // within the module itself, the rest through the global symbol table.
+    fn symbol_addr(
+        info: &LinkerInfo,
+        resolution: SymbolResolution,
+        modname: &str,
+        mod_obj: &ObjectIn,
+        sym_i: usize,
+    ) -> i32 {
+        let ste = &mod_obj.symbol_table[sym_i];
+        if !ste.is_local() {
+            return info
+                .global_symtable
+                .get(&resolution.key(&ste.st_name))
+                .and_then(GlobalSymbol::address)
+                .unwrap();
+        }
+        if ste.st_seg == 0 {
+            return ste.st_value; // absolute
+        }
+        let seg_name = &mod_obj.segment_by_ordinal(ste.st_seg).unwrap().segment_name;
+        info.segment_mapping[modname][seg_name] + ste.st_value
+    }
+
+    // Returns the number of relocations applied, per type, the ones referring
+    // to undefined symbols, which are not applied, and the GOT slots filled
+    fn run_relocations(
+        &mut self,
+        out: &mut ObjectOut,
+        info: &LinkerInfo,
+    ) -> Result<Relocated, LinkError> {
+        self.validate_relocations()?;
+        let resolution = self.symbol_resolution;
+        let mut counts = BTreeMap::new();
+        let mut deferred = vec![];
+        let mut got_offset = 0;
+        let mut got_entries = vec![];
+        let pinned = self.pinned_got_slots()?;
+        let modnames: Vec<ObjectID> = self.session_objects.keys().cloned().collect();
+        self.progress(LinkPhase::Relocation, 0, modnames.len());
+        for (i, modname) in modnames.iter().enumerate() {
+            let relocations = self.session_objects[modname].relocations.clone();
+            if !relocations.is_empty() {
                 self.logger
                     .debug(&format!("Running relocations for {modname:}"));
             }
-            // println!("DEBUG: {mod_obj:?}");
-            for r in mod_obj.relocations.iter() {
-                let reloc_entity = match r.rel_ref {
-                    RelRef::SegmentRef(seg_i) => {
-                        format!("segment {} reference", mod_obj.segments[seg_i].segment_name)
-                    }
-                    RelRef::SymbolRef(sym_i) => {
-                        format!("symbol '{}' reference", mod_obj.symbol_table[sym_i].st_name)
+            for r in relocations.iter() {
+                let mod_obj = &self.session_objects[modname];
+                if let RelRef::SymbolRef(sym_i) = r.rel_ref {
+                    let ste = &mod_obj.symbol_table[sym_i];
+                    if !ste.is_local()
+                        && info
+                            .global_symtable
+                            .get(&resolution.key(&ste.st_name))
+                            .is_some_and(|gsym| !gsym.is_defined())
+                    {
+                        self.logger.debug(&format!(
+                            "Deferring {} relocation of undefined '{}'",
+                            r.rel_type, ste.st_name
+                        ));
+                        deferred.push((modname.clone(), r.clone()));
+                        continue;
                     }
-                    RelRef::NoRef => String::new(),
-                };
+                }
                 self.logger.debug(&format!(
-                    "Relocation {} of {reloc_entity} at offset 0x{:X} (segment {})",
-                    r.rel_type, r.rel_loc, r.rel_seg
+                    "Relocation {} of {} at offset 0x{:X} (segment {})",
+                    r.rel_type,
+                    r.describe(mod_obj),
+                    r.rel_loc,
+                    r.rel_seg
                 ));
-                match r.rel_type {
-                    RelType::A4 => {
-                        match r.rel_ref {
-                            RelRef::SymbolRef(_) => panic!("run_relocations: A4 with SymbolRef"),
-                            RelRef::NoRef => panic!("run_relocations: A4 with NoRef"),
-                            RelRef::SegmentRef(seg_i) => {
-                                // what segment are we relocating? note that we are relocating reference
-                                // to the segment of module the contains that relocation entry
-                                let seg_name = mod_obj.segments[seg_i].segment_name.clone();
-                                // absolute segment ref target address
-                                let mod_seg_off = *info
-                                    .segment_mapping
-                                    .get(modname)
-                                    .unwrap()
-                                    .get(&seg_name)
-                                    .unwrap();
-                                match mk_addr_4(mod_seg_off as usize) {
-                                    None => return Err(LinkError::AddressOverflowError),
-                                    Some(saa) => {
-                                        // fix up the code!
-                                        out.object_data.entry(r.rel_seg.clone()).and_modify(|sd| {
-                                            let reloc_seg_start =
-                                                out.segments.get(&r.rel_seg).unwrap().segment_start
-                                                    - info
-                                                        .segment_mapping
-                                                        .get(modname)
-                                                        .unwrap()
-                                                        .get(&r.rel_seg)
-                                                        .unwrap();
-                                            let reloc_seg_off = reloc_seg_start + r.rel_loc;
-                                            self.logger
-                                                .debug(&format!("  Setting 0x{mod_seg_off:08X}"));
-                                            sd.update(reloc_seg_off as usize, 4, saa);
-                                        })
-                                    }
-                                };
-                                // create PiC relocations
-                                let er_rel_loc = *info
-                                    .segment_mapping
-                                    .get(modname)
-                                    .unwrap()
-                                    .get(&r.rel_seg)
-                                    .unwrap()
-                                    + r.rel_loc
-                                    - out.segments.get(&r.rel_seg).unwrap().segment_start;
-                                self.logger.debug(&format!(
-                                    "  Creating ER4 relocation at 0x{er_rel_loc:08X}"
-                                ));
-                                out.relocations.push(Relocation {
-                                    rel_loc: er_rel_loc,
-                                    rel_seg: r.rel_seg.clone(),
-                                    rel_ref: RelRef::NoRef,
-                                    rel_type: RelType::ER4,
-                                });
-                            }
-                        };
+                let pinned_slot = r.got_slot().map(|slot| slot * 4);
+                if pinned_slot.is_none() {
+                    // slots given out in order go around the pinned ones
+                    while pinned.contains_key(&(got_offset / 4)) {
+                        got_offset += 4;
                     }
-                    RelType::R4 => {
-                        match r.rel_ref {
-                            RelRef::SymbolRef(_) => panic!("run_relocations: R4 with SymbolRef"),
-                            RelRef::NoRef => panic!("run_relocations: R4 with NoRef"),
-                            RelRef::SegmentRef(seg_i) => {
-                                let seg_name = mod_obj.segments[seg_i].segment_name.clone();
-                                let mod_seg_off = *info
-                                    .segment_mapping
-                                    .get(modname)
-                                    .unwrap()
-                                    .get(&seg_name)
-                                    .unwrap();
-                                // relocation loc + 4
-                                let next_insr_loc = *info
-                                    .segment_mapping
-                                    .get(modname)
-                                    .unwrap()
-                                    .get(&r.rel_seg)
-                                    .unwrap()
-                                    + r.rel_loc
-                                    + 4;
-                                // fix up the code!
-                                out.object_data.entry(r.rel_seg.clone()).and_modify(|sd| {
-                                    let loc_off = next_insr_loc
-                                        - 4
-                                        - out.segments.get(&r.rel_seg).unwrap().segment_start;
-                                    let addend =
-                                        x_to_i4(sd.get_at(loc_off as usize, 0x4).unwrap()).unwrap();
-                                    let rel_addr_val = mk_i_4(next_insr_loc - mod_seg_off + addend);
-                                    self.logger.debug(&format!(
-                                        "  Setting 0x{:08X}",
-                                        next_insr_loc - mod_seg_off + addend
-                                    ));
-                                    sd.update(loc_off as usize, 4, rel_addr_val);
-                                });
-                            }
-                        }
-                    }
-                    RelType::AS4 => {
-                        match r.rel_ref {
-                            RelRef::SegmentRef(_) => panic!("run_relocations: AS4 with SegmentRef"),
-                            RelRef::NoRef => panic!("run_relocations: AS4 with NoRef"),
-                            RelRef::SymbolRef(sym_i) => {
-                                // what symbol are we relocating? note that we are relocating reference
-                                // to the segment of module the contains that relocation entry
-                                let sym_name = &mod_obj.symbol_table[sym_i].st_name;
-                                // absolute symbol ref target address
-                                let mod_sym_off = info
-                                    .global_symtable
-                                    .get(sym_name)
-                                    .unwrap()
-                                    .0
-                                    .as_ref()
-                                    .unwrap()
-                                    .defn_addr
-                                    .unwrap();
-                                let loc_off = *info
-                                    .segment_mapping
-                                    .get(modname)
-                                    .unwrap()
-                                    .get(&r.rel_seg)
-                                    .unwrap()
-                                    + r.rel_loc
-                                    - out.segments.get(&r.rel_seg).unwrap().segment_start;
-                                let addend = x_to_i4(
-                                    out.object_data
-                                        .get(&r.rel_seg)
-                                        .unwrap()
-                                        .get_at(loc_off as usize, 0x4)
-                                        .unwrap(),
-                                )
-                                .unwrap();
-                                match mk_addr_4((mod_sym_off + addend) as usize) {
-                                    None => return Err(LinkError::AddressOverflowError),
-                                    Some(v) => {
-                                        // fix up the code!
-                                        out.object_data.entry(r.rel_seg.clone()).and_modify(|sd| {
-                                            self.logger.debug(&format!(
-                                                "  Setting 0x{:08X}",
-                                                mod_sym_off + addend
-                                            ));
-                                            sd.update(loc_off as usize, 4, v);
-                                        });
-                                    }
-                                }
-                                // create PiC relocations
-                                let er_rel_loc = *info
-                                    .segment_mapping
-                                    .get(modname)
-                                    .unwrap()
-                                    .get(&r.rel_seg)
-                                    .unwrap()
-                                    + r.rel_loc
-                                    - out.segments.get(&r.rel_seg).unwrap().segment_start;
-                                self.logger.debug(&format!(
-                                    "  Creating ER4 relocation at 0x{er_rel_loc:08X}"
-                                ));
-                                out.relocations.push(Relocation {
-                                    rel_loc: er_rel_loc,
-                                    rel_seg: r.rel_seg.clone(),
-                                    rel_ref: RelRef::NoRef,
-                                    rel_type: RelType::ER4,
-                                });
-                            }
-                        }
-                    }
-                    RelType::RS4 => match r.rel_ref {
-                        RelRef::SegmentRef(_) => panic!("run_relocations: RS4 with SegmentRef"),
-                        RelRef::NoRef => panic!("run_relocations: RS4 with NoRef"),
-                        RelRef::SymbolRef(sym_i) => {
-                            let sym_name = &mod_obj.symbol_table[sym_i].st_name;
-                            // absolute symbol ref target address
-                            let mod_sym_off = info
-                                .global_symtable
-                                .get(sym_name)
-                                .unwrap()
-                                .0
-                                .as_ref()
-                                .unwrap()
-                                .defn_addr
-                                .unwrap();
-                            let loc_addr = *info
-                                .segment_mapping
-                                .get(modname)
-                                .unwrap()
-                                .get(&r.rel_seg)
-                                .unwrap();
-                            let loc_off = loc_addr + r.rel_loc
-                                - out.segments.get(&r.rel_seg).unwrap().segment_start;
-                            let addend = x_to_i4(
-                                out.object_data
-                                    .get(&r.rel_seg)
-                                    .unwrap()
-                                    .get_at(loc_off as usize, 0x4)
-                                    .unwrap(),
-                            )
-                            .unwrap();
-                            // fix up the code!
-                            out.object_data.entry(r.rel_seg.clone()).and_modify(|sd| {
-                                let rel_addr_val = mk_i_4(loc_addr + 4 - mod_sym_off + addend);
-                                self.logger.debug(&format!(
-                                    "  Setting 0x{:08X}",
-                                    loc_addr + 4 - mod_sym_off + addend
-                                ));
-                                sd.update(loc_off as usize, 0x4, rel_addr_val);
-                            });
-                        }
-                    },
-                    RelType::U2 => {
-                        match r.rel_ref {
-                            RelRef::SegmentRef(_) => panic!("run_relocations: U2 with SegmentRef"),
-                            RelRef::NoRef => panic!("run_relocations: U2 with NoRef"),
-                            RelRef::SymbolRef(sym_i) => {
-                                // what symbol are we relocating? note that we are relocating reference
-                                // to the segment of module the contains that relocation entry
-                                let sym_name = &mod_obj.symbol_table[sym_i].st_name;
-                                // absolute symbol ref target address
-                                let mod_sym_off = info
-                                    .global_symtable
-                                    .get(sym_name)
-                                    .unwrap()
-                                    .0
-                                    .as_ref()
-                                    .unwrap()
-                                    .defn_addr
-                                    .unwrap();
-                                let loc_addr = *info
-                                    .segment_mapping
-                                    .get(modname)
-                                    .unwrap()
-                                    .get(&r.rel_seg)
-                                    .unwrap();
-                                let loc_off = loc_addr + r.rel_loc
-                                    - out.segments.get(&r.rel_seg).unwrap().segment_start;
-                                match mk_addr_4(mod_sym_off as usize) {
-                                    None => return Err(LinkError::AddressOverflowError),
-                                    Some(v) => {
-                                        // fix up the code!
-                                        out.object_data.entry(r.rel_seg.clone()).and_modify(|sd| {
-                                            self.logger.debug(&format!(
-                                                "  Setting 0x{:04X}",
-                                                x_to_i2(&v[0..2]).unwrap()
-                                            ));
-                                            sd.update(loc_off as usize, 2, v[0..2].to_vec());
-                                        });
-                                    }
-                                }
-                            }
-                        }
-                    }
-                    RelType::L2 => {
-                        match r.rel_ref {
-                            RelRef::SegmentRef(_) => panic!("run_relocations: L2 with SegmentRef"),
-                            RelRef::NoRef => panic!("run_relocations: L2 with NoRef"),
-                            RelRef::SymbolRef(sym_i) => {
-                                // what symbol are we relocating? note that we are relocating reference
-                                // to the segment of module the contains that relocation entry
-                                let sym_name = &mod_obj.symbol_table[sym_i].st_name;
-                                // absolute symbol ref target address
-                                let mod_sym_off = info
-                                    .global_symtable
-                                    .get(sym_name)
-                                    .unwrap()
-                                    .0
-                                    .as_ref()
-                                    .unwrap()
-                                    .defn_addr
-                                    .unwrap();
-                                let loc_addr = *info
-                                    .segment_mapping
-                                    .get(modname)
-                                    .unwrap()
-                                    .get(&r.rel_seg)
-                                    .unwrap();
-                                let loc_off = loc_addr + r.rel_loc
-                                    - out.segments.get(&r.rel_seg).unwrap().segment_start;
-                                match mk_addr_4(mod_sym_off as usize) {
-                                    None => return Err(LinkError::AddressOverflowError),
-                                    Some(v) => {
-                                        // fix up the code!
-                                        out.object_data.entry(r.rel_seg.clone()).and_modify(|sd| {
-                                            self.logger.debug(&format!(
-                                                "  Setting 0x{:04X}",
-                                                x_to_i2(&v[2..4]).unwrap()
-                                            ));
-                                            sd.update(loc_off as usize, 2, v[2..4].to_vec());
-                                        });
-                                    }
-                                }
-                            }
-                        }
-                    }
-                    RelType::GA4 => {
-                        match r.rel_ref {
-                            RelRef::SegmentRef(_) => panic!("run_relocations: GA4 with SegmentRef"),
-                            RelRef::SymbolRef(_) => panic!("run_relocations: GA4 with SymbolRef"),
-                            RelRef::NoRef => {
-                                let seg_addr = *info
-                                    .segment_mapping
-                                    .get(modname)
-                                    .unwrap()
-                                    .get(&r.rel_seg)
-                                    .unwrap();
-                                let loc_off = seg_addr + r.rel_loc
-                                    - out.segments.get(&r.rel_seg).unwrap().segment_start;
-                                let got_off =
-                                    out.segments.get(&SegmentName::GOT).unwrap().segment_start;
-                                let dist_to_got = got_off - (seg_addr + r.rel_loc);
-                                match mk_addr_4(dist_to_got as usize) {
-                                    None => return Err(LinkError::AddressOverflowError),
-                                    Some(v) => {
-                                        // fix up the code!
-                                        out.object_data.entry(r.rel_seg.clone()).and_modify(|sd| {
-                                            self.logger
-                                                .debug(&format!("  Setting 0x{dist_to_got:08X}",));
-                                            sd.update(loc_off as usize, 4, v[0..4].to_vec());
-                                        });
-                                    }
-                                }
-                            }
-                        }
-                    }
-                    RelType::GP4 => {
-                        match r.rel_ref {
-                            RelRef::SegmentRef(_) => panic!("run_relocations: GP4 with SegmentRef"),
-                            RelRef::NoRef => panic!("run_relocations: GP4 with NoRef"),
-                            RelRef::SymbolRef(sym_i) => {
-                                let sz = 4;
-                                let sym_name = &mod_obj.symbol_table[sym_i].st_name;
-                                let mod_sym_off = info
-                                    .global_symtable
-                                    .get(sym_name)
-                                    .unwrap()
-                                    .0
-                                    .as_ref()
-                                    .unwrap()
-                                    .defn_addr
-                                    .unwrap();
-                                match mk_addr_4((mod_sym_off) as usize) {
-                                    None => return Err(LinkError::AddressOverflowError),
-                                    Some(v) => {
-                                        // fix up the code!
-                                        out.object_data.entry(SegmentName::GOT).and_modify(|sd| {
-                                            self.logger.debug(&format!(
-                                                "  Setting 0x{mod_sym_off:08X} in GOT at offset {got_offset}"
-                                            ));
-                                            sd.update(got_offset, sz, v);
-                                        });
-                                    }
-                                }
-                                let loc_off = *info
-                                    .segment_mapping
-                                    .get(modname)
-                                    .unwrap()
-                                    .get(&r.rel_seg)
-                                    .unwrap()
-                                    + r.rel_loc
-                                    - out.segments.get(&r.rel_seg).unwrap().segment_start;
-                                match mk_addr_4(got_offset) {
-                                    None => return Err(LinkError::AddressOverflowError),
-                                    Some(v) => {
-                                        // fix up the code!
-                                        out.object_data.entry(r.rel_seg.clone()).and_modify(|sd| {
-                                            self.logger.debug(&format!(
-                                                "  Setting GOT offset 0x{got_offset:08X} in {}",
-                                                r.rel_seg
-                                            ));
-                                            sd.update(loc_off as usize, sz, v);
-                                        });
-                                    }
-                                }
-                                got_offset += sz;
-                            }
-                        }
+                }
+                let got_slot = pinned_slot.unwrap_or(got_offset);
+                let mut next_got_slot = got_slot;
+                let er4 = match r.rel_type {
+                    RelType::A4 => self.apply_a4(out, info, modname, r)?,
+                    RelType::R4 => self.apply_r4(out, info, modname, r)?,
+                    RelType::AS4 => self.apply_as4(out, info, modname, r)?,
+                    RelType::RS4 => self.apply_rs4(out, info, modname, r)?,
+                    RelType::U2 | RelType::L2 => self.apply_u2_l2(out, info, modname, r)?,
+                    RelType::GA4 => self.apply_ga4(out, info, modname, r)?,
+                    RelType::GP4 => self.apply_gp4(out, info, modname, r, &mut next_got_slot)?,
+                    RelType::GPS4 => self.apply_gps4(out, info, modname, r, &mut next_got_slot)?,
+                    RelType::GR4 => self.apply_gr4(out, info, modname, r)?,
+                    RelType::ER4 => self.apply_er4(out, info, modname, r)?,
+                    RelType::SEGN => self.apply_segn(out, info, modname, r)?,
+                };
+                if next_got_slot != got_slot {
+                    if pinned_slot.is_none() {
+                        got_offset = next_got_slot;
                     }
-                    RelType::GR4 => {
-                        match r.rel_ref {
-                            RelRef::SymbolRef(_) => panic!("run_relocations: GR4 with SymbolRef"),
-                            RelRef::NoRef => panic!("run_relocations: GR4 with NoRef"),
-                            RelRef::SegmentRef(seg_i) => {
-                                let loc_off = *info
-                                    .segment_mapping
-                                    .get(modname)
-                                    .unwrap()
-                                    .get(&r.rel_seg)
-                                    .unwrap()
-                                    + r.rel_loc
-                                    - out.segments.get(&r.rel_seg).unwrap().segment_start;
-                                let addr_off = x_to_i4(
-                                    out.object_data
-                                        .get(&r.rel_seg)
-                                        .unwrap()
-                                        .get_at(loc_off as usize, 0x4)
-                                        .unwrap(),
-                                )
-                                .unwrap();
-                                let seg_name = mod_obj.segments[seg_i].segment_name.clone();
-                                let seg_ref_addr = *info
-                                    .segment_mapping
-                                    .get(modname)
-                                    .unwrap()
-                                    .get(&seg_name)
-                                    .unwrap();
-                                let got_off =
-                                    out.segments.get(&SegmentName::GOT).unwrap().segment_start;
-                                // fix up the code!
-                                out.object_data.entry(r.rel_seg.clone()).and_modify(|sd| {
-                                    let rel_addr_val = mk_i_4(seg_ref_addr + addr_off - got_off);
-                                    self.logger.debug(&format!(
-                                        "  Setting 0x{:08X}",
-                                        seg_ref_addr + addr_off - got_off
-                                    ));
-                                    sd.update(loc_off as usize, 4, rel_addr_val);
-                                });
-                            }
-                        }
+                    // a pinned slot may be shared
+                    if !got_entries.iter().any(|(off, _, _)| *off == got_slot) {
+                        got_entries.push(self.got_entry(out, modname, r, got_slot));
                     }
-                    RelType::ER4 => {
-                        match r.rel_ref {
-                            RelRef::SymbolRef(_) => panic!("run_relocations: ER4 with SymbolRef"),
-                            RelRef::SegmentRef(_) => panic!("run_relocations: ER4 with SegmentRef"),
-                            RelRef::NoRef => {
-                                let loc_off = *info
-                                    .segment_mapping
-                                    .get(modname)
-                                    .unwrap()
-                                    .get(&r.rel_seg)
-                                    .unwrap()
-                                    + r.rel_loc
-                                    - out.segments.get(&r.rel_seg).unwrap().segment_start;
-                                let addr = x_to_i4(
-                                    out.object_data
-                                        .get(&r.rel_seg)
-                                        .unwrap()
-                                        .get_at(loc_off as usize, 0x4)
-                                        .unwrap(),
-                                )
-                                .unwrap();
-                                match mk_addr_4((addr + self.text_start) as usize) {
-                                    None => return Err(LinkError::AddressOverflowError),
-                                    Some(v) => {
-                                        // fix up the code!
-                                        out.object_data.entry(r.rel_seg.clone()).and_modify(|sd| {
-                                            self.logger.debug(&format!(
-                                                "  Setting 0x{:08X}",
-                                                addr + self.text_start
-                                            ));
-                                            sd.update(loc_off as usize, 4, v);
-                                        });
-                                    }
-                                }
-                            }
-                        }
+                }
+                if let Some(er4) = er4 {
+                    self.logger.debug(&format!(
+                        "  Creating ER4 relocation at 0x{:08X}",
+                        er4.rel_loc
+                    ));
+                    out.relocations.push(er4);
+                }
+                *counts.entry(r.rel_type.clone()).or_insert(0) += 1;
+            }
+            self.progress(LinkPhase::Relocation, i + 1, modnames.len());
+        }
+        Ok((counts, deferred, got_entries))
+    }
+
+    // What GP4/GPS4 relocation r of module modname put in the GOT at offset
+    fn got_entry(&self, out: &ObjectOut, modname: &str, r: &Relocation, offset: usize) -> GotEntry {
+        let name = self.got_slot_name(modname, r);
+        let addr = out.object_data[&SegmentName::GOT]
+            .read_i32(offset, self.endianness)
+            .unwrap();
+        (offset, name, addr)
+    }
+
+    // The symbol GP4/GPS4 relocation r of module modname puts in the GOT,
+    // segments and local symbols namespaced by the module
+    fn got_slot_name(&self, modname: &str, r: &Relocation) -> SymbolName {
+        let mod_obj = &self.session_objects[modname];
+        match r.rel_ref {
+            RelRef::SymbolRef(sym_i) => {
+                let ste = &mod_obj.symbol_table[sym_i];
+                if ste.is_local() {
+                    SymbolName::Local(ste.st_name.base().to_owned(), modname.to_owned())
+                } else {
+                    ste.st_name.clone()
+                }
+            }
+            RelRef::SegmentRef(seg_i) => SymbolName::Local(
+                mod_obj.segments[seg_i].segment_name.to_string(),
+                modname.to_owned(),
+            ),
+            RelRef::NoRef => panic!("run_relocations: GOT slot with NoRef"),
+        }
+    }
+
+    // Address of the segment (of module modname) relocation r is in, and the
+    // offset of the relocated location in the output segment data
+    fn relocation_site(
+        out: &ObjectOut,
+        info: &LinkerInfo,
+        modname: &str,
+        r: &Relocation,
+    ) -> Result<(i32, usize), LinkError> {
+        let seg_addr = info
+            .segment_mapping
+            .get(modname)
+            .and_then(|addrs| addrs.get(&r.rel_seg));
+        match (seg_addr, out.segments.get(&r.rel_seg)) {
+            (Some(seg_addr), Some(out_seg)) => {
+                let loc_off = seg_addr
+                    .checked_add(r.rel_loc)
+                    .and_then(|v| v.checked_sub(out_seg.segment_start))
+                    .ok_or(LinkError::RelocationOutOfRange)?;
+                Ok((*seg_addr, loc_off as usize))
+            }
+            _ => Err(LinkError::RelocationTargetSegmentMissing(r.rel_seg.clone())),
+        }
+    }
+
+    fn segment_data<'a>(
+        out: &'a mut ObjectOut,
+        segment_name: &SegmentName,
+    ) -> Result<&'a mut dyn SegmentBytes, LinkError> {
+        match out.object_data.get_mut(segment_name) {
+            Some(sd) => Ok(sd),
+            None => Err(LinkError::RelocationOutOfRange),
+        }
+    }
+
+    // Absolute address of segment seg_i of a module
+    fn segment_ref_addr(info: &LinkerInfo, modname: &str, mod_obj: &ObjectIn, seg_i: usize) -> i32 {
+        let seg_name = &mod_obj.segments[seg_i].segment_name;
+        info.segment_mapping[modname][seg_name]
+    }
+
+    // R4, RS4, GA4 and GR4 displacements are 32 bit
+    fn displacement_overflow(r: &Relocation) -> LinkError {
+        LinkError::RelocationDisplacementOverflow {
+            seg: r.rel_seg.clone(),
+            loc: r.rel_loc,
+        }
+    }
+
+    // The apply_* functions do a single relocation r of module modname (one of
+    // session_objects) in the output. They return the ER4 relocation the
+    // output needs for it to be loaded elsewhere, if any.
+
+    // Absolute address of a segment of the module, plus the offset at loc
+    pub fn apply_a4(
+        &mut self,
+        out: &mut ObjectOut,
+        info: &LinkerInfo,
+        modname: &str,
+        r: &Relocation,
+    ) -> Result<Option<Relocation>, LinkError> {
+        let seg_i = match r.rel_ref {
+            RelRef::SymbolRef(_) => panic!("run_relocations: A4 with SymbolRef"),
+            RelRef::NoRef => panic!("run_relocations: A4 with NoRef"),
+            RelRef::SegmentRef(seg_i) => seg_i,
+        };
+        let (_, loc_off) = LinkerEditor::relocation_site(out, info, modname, r)?;
+        // note that we are relocating reference to the segment of module the
+        // contains that relocation entry
+        let mod_seg_off =
+            LinkerEditor::segment_ref_addr(info, modname, &self.session_objects[modname], seg_i);
+        let sd = LinkerEditor::segment_data(out, &r.rel_seg)?;
+        // value already at loc is an offset into the segment
+        let addend = sd
+            .read_i32(loc_off, self.endianness)
+            .ok_or(LinkError::RelocationOutOfRange)?;
+        let addr = match mod_seg_off.checked_add(addend) {
+            Some(addr) if addr >= 0 => addr,
+            _ => return Err(LinkError::AddressOverflowError),
+        };
+        // fix up the code!
+        self.logger.debug(&format!("  Setting 0x{addr:08X}"));
+        sd.write_i32(loc_off, addr, self.endianness)
+            .ok_or(LinkError::RelocationOutOfRange)?;
+        Ok(Some(Relocation {
+            rel_loc: loc_off as i32,
+            rel_seg: r.rel_seg.clone(),
+            rel_ref: RelRef::NoRef,
+            rel_type: RelType::ER4,
+            rel_extra: vec![],
+        }))
+    }
+
+    // Displacement from the next instruction (loc + 4) to a segment of the
+    // module, plus the offset at loc
+    pub fn apply_r4(
+        &mut self,
+        out: &mut ObjectOut,
+        info: &LinkerInfo,
+        modname: &str,
+        r: &Relocation,
+    ) -> Result<Option<Relocation>, LinkError> {
+        let seg_i = match r.rel_ref {
+            RelRef::SymbolRef(_) => panic!("run_relocations: R4 with SymbolRef"),
+            RelRef::NoRef => panic!("run_relocations: R4 with NoRef"),
+            RelRef::SegmentRef(seg_i) => seg_i,
+        };
+        let (seg_addr, loc_off) = LinkerEditor::relocation_site(out, info, modname, r)?;
+        let mod_seg_off =
+            LinkerEditor::segment_ref_addr(info, modname, &self.session_objects[modname], seg_i);
+        let sd = LinkerEditor::segment_data(out, &r.rel_seg)?;
+        let addend = sd
+            .read_i32(loc_off, self.endianness)
+            .ok_or(LinkError::RelocationOutOfRange)?;
+        // fix up the code! relocation loc + 4 is the next instruction
+        let rel_addr_val = seg_addr
+            .checked_add(r.rel_loc)
+            .and_then(|v| v.checked_add(4))
+            .and_then(|v| v.checked_sub(mod_seg_off))
+            .and_then(|v| v.checked_add(addend))
+            .ok_or_else(|| LinkerEditor::displacement_overflow(r))?;
+        self.logger
+            .debug(&format!("  Setting 0x{rel_addr_val:08X}"));
+        sd.write_i32(loc_off, rel_addr_val, self.endianness)
+            .ok_or(LinkError::RelocationOutOfRange)?;
+        Ok(None)
+    }
+
+    // Absolute address of a symbol, plus the offset at loc
+    pub fn apply_as4(
+        &mut self,
+        out: &mut ObjectOut,
+        info: &LinkerInfo,
+        modname: &str,
+        r: &Relocation,
+    ) -> Result<Option<Relocation>, LinkError> {
+        let sym_i = match r.rel_ref {
+            RelRef::SegmentRef(_) => panic!("run_relocations: AS4 with SegmentRef"),
+            RelRef::NoRef => panic!("run_relocations: AS4 with NoRef"),
+            RelRef::SymbolRef(sym_i) => sym_i,
+        };
+        let (_, loc_off) = LinkerEditor::relocation_site(out, info, modname, r)?;
+        let mod_sym_off = LinkerEditor::symbol_addr(
+            info,
+            self.symbol_resolution,
+            modname,
+            &self.session_objects[modname],
+            sym_i,
+        );
+        let sd = LinkerEditor::segment_data(out, &r.rel_seg)?;
+        let addend = sd
+            .read_i32(loc_off, self.endianness)
+            .ok_or(LinkError::RelocationOutOfRange)?;
+        let addr = match mod_sym_off.checked_add(addend) {
+            Some(addr) if addr >= 0 => addr,
+            _ => return Err(LinkError::AddressOverflowError),
+        };
+        // fix up the code!
+        self.logger.debug(&format!("  Setting 0x{addr:08X}"));
+        sd.write_i32(loc_off, addr, self.endianness)
+            .ok_or(LinkError::RelocationOutOfRange)?;
+        Ok(Some(Relocation {
+            rel_loc: loc_off as i32,
+            rel_seg: r.rel_seg.clone(),
+            rel_ref: RelRef::NoRef,
+            rel_type: RelType::ER4,
+            rel_extra: vec![],
+        }))
+    }
+
+    pub fn apply_rs4(
+        &mut self,
+        out: &mut ObjectOut,
+        info: &LinkerInfo,
+        modname: &str,
+        r: &Relocation,
+    ) -> Result<Option<Relocation>, LinkError> {
+        let sym_i = match r.rel_ref {
+            RelRef::SegmentRef(_) => panic!("run_relocations: RS4 with SegmentRef"),
+            RelRef::NoRef => panic!("run_relocations: RS4 with NoRef"),
+            RelRef::SymbolRef(sym_i) => sym_i,
+        };
+        let (seg_addr, loc_off) = LinkerEditor::relocation_site(out, info, modname, r)?;
+        let mod_sym_off = LinkerEditor::symbol_addr(
+            info,
+            self.symbol_resolution,
+            modname,
+            &self.session_objects[modname],
+            sym_i,
+        );
+        let sd = LinkerEditor::segment_data(out, &r.rel_seg)?;
+        let addend = sd
+            .read_i32(loc_off, self.endianness)
+            .ok_or(LinkError::RelocationOutOfRange)?;
+        // fix up the code!
+        let rel_addr_val = seg_addr
+            .checked_add(4)
+            .and_then(|v| v.checked_sub(mod_sym_off))
+            .and_then(|v| v.checked_add(addend))
+            .ok_or_else(|| LinkerEditor::displacement_overflow(r))?;
+        self.logger
+            .debug(&format!("  Setting 0x{rel_addr_val:08X}"));
+        sd.write_i32(loc_off, rel_addr_val, self.endianness)
+            .ok_or(LinkError::RelocationOutOfRange)?;
+        Ok(None)
+    }
+
+    // Upper (U2) or lower (L2) half of the address of a symbol, or of a
+    // segment of the module
+    pub fn apply_u2_l2(
+        &mut self,
+        out: &mut ObjectOut,
+        info: &LinkerInfo,
+        modname: &str,
+        r: &Relocation,
+    ) -> Result<Option<Relocation>, LinkError> {
+        let mod_obj = &self.session_objects[modname];
+        let addr = match r.rel_ref {
+            RelRef::NoRef => panic!("run_relocations: {} with NoRef", r.rel_type),
+            RelRef::SegmentRef(seg_i) => {
+                LinkerEditor::segment_ref_addr(info, modname, mod_obj, seg_i)
+            }
+            RelRef::SymbolRef(sym_i) => {
+                LinkerEditor::symbol_addr(info, self.symbol_resolution, modname, mod_obj, sym_i)
+            }
+        };
+        let (_, loc_off) = LinkerEditor::relocation_site(out, info, modname, r)?;
+        if addr < 0 {
+            return Err(LinkError::AddressOverflowError);
+        }
+        let half = if r.rel_type == RelType::U2 {
+            (addr as u32 >> 16) as u16
+        } else {
+            (addr as u32 & 0xFFFF) as u16
+        };
+        // fix up the code!
+        self.logger.debug(&format!("  Setting 0x{half:04X}"));
+        LinkerEditor::segment_data(out, &r.rel_seg)?
+            .write_u16(loc_off, half, self.endianness)
+            .ok_or(LinkError::RelocationOutOfRange)?;
+        Ok(None)
+    }
+
+    // Distance from loc to the GOT
+    pub fn apply_ga4(
+        &mut self,
+        out: &mut ObjectOut,
+        info: &LinkerInfo,
+        modname: &str,
+        r: &Relocation,
+    ) -> Result<Option<Relocation>, LinkError> {
+        match r.rel_ref {
+            RelRef::SegmentRef(_) => panic!("run_relocations: GA4 with SegmentRef"),
+            RelRef::SymbolRef(_) => panic!("run_relocations: GA4 with SymbolRef"),
+            RelRef::NoRef => {}
+        }
+        let (seg_addr, loc_off) = LinkerEditor::relocation_site(out, info, modname, r)?;
+        let got_off = out
+            .segments
+            .get(&SegmentName::GOT)
+            .ok_or(LinkError::NoGlobalOffsetTable)?
+            .segment_start;
+        let dist_to_got = seg_addr
+            .checked_add(r.rel_loc)
+            .and_then(|v| got_off.checked_sub(v))
+            .ok_or_else(|| LinkerEditor::displacement_overflow(r))?;
+        if dist_to_got < 0 {
+            return Err(LinkError::AddressOverflowError);
+        }
+        // fix up the code!
+        self.logger
+            .debug(&format!("  Setting 0x{dist_to_got:08X}",));
+        LinkerEditor::segment_data(out, &r.rel_seg)?
+            .write_i32(loc_off, dist_to_got, self.endianness)
+            .ok_or(LinkError::RelocationOutOfRange)?;
+        Ok(None)
+    }
+
+    // The next GOT slot (at got_offset, moved past it) gets the address of a
+    // symbol, loc the offset of the slot
+    pub fn apply_gp4(
+        &mut self,
+        out: &mut ObjectOut,
+        info: &LinkerInfo,
+        modname: &str,
+        r: &Relocation,
+        got_offset: &mut usize,
+    ) -> Result<Option<Relocation>, LinkError> {
+        let sym_i = match r.rel_ref {
+            RelRef::SegmentRef(_) => panic!("run_relocations: GP4 with SegmentRef"),
+            RelRef::NoRef => panic!("run_relocations: GP4 with NoRef"),
+            RelRef::SymbolRef(sym_i) => sym_i,
+        };
+        let mod_sym_off = LinkerEditor::symbol_addr(
+            info,
+            self.symbol_resolution,
+            modname,
+            &self.session_objects[modname],
+            sym_i,
+        );
+        if mod_sym_off < 0 {
+            return Err(LinkError::AddressOverflowError);
+        }
+        self.fill_got_slot(out, info, modname, r, mod_sym_off, got_offset)?;
+        Ok(None)
+    }
+
+    // Same as GP4, for the address of a segment of the module
+    pub fn apply_gps4(
+        &mut self,
+        out: &mut ObjectOut,
+        info: &LinkerInfo,
+        modname: &str,
+        r: &Relocation,
+        got_offset: &mut usize,
+    ) -> Result<Option<Relocation>, LinkError> {
+        let seg_i = match r.rel_ref {
+            RelRef::SymbolRef(_) => panic!("run_relocations: GPS4 with SymbolRef"),
+            RelRef::NoRef => panic!("run_relocations: GPS4 with NoRef"),
+            RelRef::SegmentRef(seg_i) => seg_i,
+        };
+        let seg_ref_addr =
+            LinkerEditor::segment_ref_addr(info, modname, &self.session_objects[modname], seg_i);
+        if seg_ref_addr < 0 {
+            return Err(LinkError::AddressOverflowError);
+        }
+        self.fill_got_slot(out, info, modname, r, seg_ref_addr, got_offset)?;
+        Ok(None)
+    }
+
+    fn fill_got_slot(
+        &mut self,
+        out: &mut ObjectOut,
+        info: &LinkerInfo,
+        modname: &str,
+        r: &Relocation,
+        addr: i32,
+        got_offset: &mut usize,
+    ) -> Result<(), LinkError> {
+        let sz = 4;
+        let (_, loc_off) = LinkerEditor::relocation_site(out, info, modname, r)?;
+        // fix up the code!
+        self.logger.debug(&format!(
+            "  Setting GOT offset 0x{:08X} in {}",
+            *got_offset, r.rel_seg
+        ));
+        LinkerEditor::segment_data(out, &r.rel_seg)?
+            .write_i32(loc_off, *got_offset as i32, self.endianness)
+            .ok_or(LinkError::RelocationOutOfRange)?;
+        // put the address in the GOT
+        self.logger.debug(&format!(
+            "  Setting 0x{addr:08X} in GOT at offset {}",
+            *got_offset
+        ));
+        LinkerEditor::segment_data(out, &SegmentName::GOT)?
+            .write_i32(*got_offset, addr, self.endianness)
+            .ok_or(LinkError::RelocationOutOfRange)?;
+        *got_offset += sz;
+        Ok(())
+    }
+
+    // Address of a segment of the module, plus the offset at loc, relative
+    // to the GOT
+    pub fn apply_gr4(
+        &mut self,
+        out: &mut ObjectOut,
+        info: &LinkerInfo,
+        modname: &str,
+        r: &Relocation,
+    ) -> Result<Option<Relocation>, LinkError> {
+        let seg_i = match r.rel_ref {
+            RelRef::SymbolRef(_) => panic!("run_relocations: GR4 with SymbolRef"),
+            RelRef::NoRef => panic!("run_relocations: GR4 with NoRef"),
+            RelRef::SegmentRef(seg_i) => seg_i,
+        };
+        let (_, loc_off) = LinkerEditor::relocation_site(out, info, modname, r)?;
+        let seg_ref_addr =
+            LinkerEditor::segment_ref_addr(info, modname, &self.session_objects[modname], seg_i);
+        let got_off = out
+            .segments
+            .get(&SegmentName::GOT)
+            .ok_or(LinkError::NoGlobalOffsetTable)?
+            .segment_start;
+        let sd = LinkerEditor::segment_data(out, &r.rel_seg)?;
+        let addr_off = sd
+            .read_i32(loc_off, self.endianness)
+            .ok_or(LinkError::RelocationOutOfRange)?;
+        // fix up the code!
+        let rel_addr_val = seg_ref_addr
+            .checked_add(addr_off)
+            .and_then(|v| v.checked_sub(got_off))
+            .ok_or_else(|| LinkerEditor::displacement_overflow(r))?;
+        self.logger
+            .debug(&format!("  Setting 0x{rel_addr_val:08X}"));
+        sd.write_i32(loc_off, rel_addr_val, self.endianness)
+            .ok_or(LinkError::RelocationOutOfRange)?;
+        Ok(None)
+    }
+
+    // Adds text_start to the address at loc, as the loader does when
+    // loading at a base address
+    pub fn apply_er4(
+        &mut self,
+        out: &mut ObjectOut,
+        info: &LinkerInfo,
+        modname: &str,
+        r: &Relocation,
+    ) -> Result<Option<Relocation>, LinkError> {
+        match r.rel_ref {
+            RelRef::SymbolRef(_) => panic!("run_relocations: ER4 with SymbolRef"),
+            RelRef::SegmentRef(_) => panic!("run_relocations: ER4 with SegmentRef"),
+            RelRef::NoRef => {}
+        }
+        let (_, loc_off) = LinkerEditor::relocation_site(out, info, modname, r)?;
+        let sd = LinkerEditor::segment_data(out, &r.rel_seg)?;
+        let addr = sd
+            .read_i32(loc_off, self.endianness)
+            .ok_or(LinkError::RelocationOutOfRange)?;
+        let addr = match addr.checked_add(self.text_start) {
+            Some(addr) if addr >= 0 => addr,
+            _ => return Err(LinkError::AddressOverflowError),
+        };
+        // fix up the code!
+        self.logger.debug(&format!("  Setting 0x{addr:08X}"));
+        sd.write_i32(loc_off, addr, self.endianness)
+            .ok_or(LinkError::RelocationOutOfRange)?;
+        Ok(None)
+    }
+
+    // Number of the output segment the symbol is defined in, see RelType::SEGN
+    pub fn apply_segn(
+        &mut self,
+        out: &mut ObjectOut,
+        info: &LinkerInfo,
+        modname: &str,
+        r: &Relocation,
+    ) -> Result<Option<Relocation>, LinkError> {
+        let sym_i = match r.rel_ref {
+            RelRef::SegmentRef(_) => panic!("run_relocations: SEGN with SegmentRef"),
+            RelRef::NoRef => panic!("run_relocations: SEGN with NoRef"),
+            RelRef::SymbolRef(sym_i) => sym_i,
+        };
+        let (_, loc_off) = LinkerEditor::relocation_site(out, info, modname, r)?;
+        let segn = self
+            .symbol_segment(info, modname, sym_i)
+            .and_then(|seg_name| {
+                SegmentName::order()
+                    .iter()
+                    .filter(|name| out.segments.contains_key(name))
+                    .position(|name| *name == seg_name)
+            })
+            .map_or(0, |i| i + 1);
+        // fix up the code!
+        self.logger.debug(&format!("  Setting segment {segn}"));
+        LinkerEditor::segment_data(out, &r.rel_seg)?
+            .write_i32(loc_off, segn as i32, self.endianness)
+            .ok_or(LinkError::RelocationOutOfRange)?;
+        Ok(None)
+    }
+
+    // Output segment symbol sym_i of a module is defined in, None for
+    // absolute symbols and ones from shared libraries
+    fn symbol_segment(
+        &self,
+        info: &LinkerInfo,
+        modname: &str,
+        sym_i: usize,
+    ) -> Option<SegmentName> {
+        let ste_segment = |modname: &str, ste: &SymbolTableEntry| match ste.st_seg {
+            0 => None,
+            seg => self.session_objects[modname]
+                .segment_by_ordinal(seg)
+                .map(|s| s.segment_name.clone()),
+        };
+        let ste = &self.session_objects[modname].symbol_table[sym_i];
+        if ste.is_local() {
+            return ste_segment(modname, ste);
+        }
+        let defn = info
+            .global_symtable
+            .get(&self.symbol_resolution.key(&ste.st_name))?
+            .defn
+            .as_ref()?;
+        match (&defn.defn_prov, defn.defn_ste_ix) {
+            (DefnProvenance::FromObjectIn, Some(ste_ix)) => ste_segment(
+                &defn.defn_mod_id,
+                &info.symbol_tables[&defn.defn_mod_id][ste_ix],
+            ),
+            (DefnProvenance::FromCommonBlock, _) => Some(SegmentName::BSS),
+            (DefnProvenance::FromGot, _) => Some(SegmentName::GOT),
+            (DefnProvenance::FromLibSegment, _) => Some(SegmentName::LIB),
+            _ => None,
+        }
+    }
+
+    // see set_split_segment_permissions
+    fn split_read_only_data(&self, mut obj: ObjectIn) -> ObjectIn {
+        if !self.split_segment_permissions || obj.segment_ordinal(&SegmentName::RODATA).is_some() {
+            return obj;
+        }
+        let Some(seg) = obj
+            .segments
+            .iter_mut()
+            .find(|seg| seg.segment_name == SegmentName::DATA)
+        else {
+            return obj;
+        };
+        if seg.segment_descr.contains(&SegmentDescr::W) {
+            return obj;
+        }
+        seg.segment_name = SegmentName::RODATA;
+        for r in obj.relocations.iter_mut() {
+            if r.rel_seg == SegmentName::DATA {
+                r.rel_seg = SegmentName::RODATA;
+            }
+        }
+        obj
+    }
+
+    fn apply_symbol_renames(
+        &mut self,
+        objs_in: &mut BTreeMap<ObjectID, ObjectIn>,
+    ) -> Result<(), LinkError> {
+        if self.renamed_symbols.is_empty() {
+            return Ok(());
+        }
+        let resolution = self.symbol_resolution;
+        let renames: HashMap<SymbolName, &SymbolName> = self
+            .renamed_symbols
+            .iter()
+            .map(|(from, to)| (resolution.key(from), to))
+            .collect();
+        let renamed = |name: &SymbolName| renames.contains_key(&resolution.key(name));
+        // names that stay, defined somewhere or used in the object itself
+        let defined: HashSet<SymbolName> = objs_in
+            .values()
+            .flat_map(|obj| obj.symbol_table.iter())
+            .filter(|ste| ste.is_defined() && !ste.is_local() && !renamed(&ste.st_name))
+            .map(|ste| resolution.key(&ste.st_name))
+            .collect();
+        for (obj_id, obj) in objs_in.iter_mut() {
+            let kept: HashSet<SymbolName> = obj
+                .symbol_table
+                .iter()
+                .filter(|ste| !renamed(&ste.st_name))
+                .map(|ste| resolution.key(&ste.st_name))
+                .collect();
+            for ste in obj.symbol_table.iter_mut() {
+                if let Some(&to) = renames.get(&resolution.key(&ste.st_name)) {
+                    let to_key = resolution.key(to);
+                    if kept.contains(&to_key) || (ste.is_defined() && defined.contains(&to_key)) {
+                        return Err(LinkError::RenamedSymbolNameAlreadyExists(to.clone()));
                     }
+                    self.logger
+                        .debug(&format!("{obj_id}: renaming {} to {to}", ste.st_name));
+                    ste.st_name = to.clone();
                 }
             }
         }
@@ -1126,21 +2537,46 @@ impl LinkerEditor {
         objs_in: &mut BTreeMap<ObjectID, ObjectIn>,
         routine_names: &[SymbolName],
     ) -> Result<(), LinkError> {
-        let mut already_wrapped = HashSet::new();
+        let mut already_wrapped: Vec<SymbolName> = vec![];
         for (_, obj) in objs_in.iter_mut() {
-            for sym in obj.symbol_table.iter_mut() {
-                if sym.st_name.deref().starts_with("wrap_")
-                    || sym.st_name.deref().starts_with("real_")
+            let index = obj.build_symbol_index();
+            let to_wrap: Vec<usize> = routine_names
+                .iter()
+                .filter_map(|name| index.get(name))
+                .flatten()
+                .copied()
+                .collect();
+            for i in to_wrap {
+                let sym = &mut obj.symbol_table[i];
+                sym.st_name = SymbolName::WrappedSName(sym.st_name.base().to_owned());
+                if !already_wrapped.contains(&sym.st_name) {
+                    already_wrapped.push(sym.st_name.clone());
+                }
+            }
+        }
+        // only checked once everything is wrapped, so that a wrap_foo or
+        // real_foo clashes with foo whichever object comes first
+        for obj in objs_in.values() {
+            for sym in obj.symbol_table.iter() {
+                let name = sym.st_name.base();
+                if let Some(n) = name
+                    .strip_prefix("wrap_")
+                    .or_else(|| name.strip_prefix("real_"))
                 {
-                    let n = sym.st_name.deref()[5..].to_owned();
-                    if already_wrapped.contains(&wrapped_symbol!(n)) {
+                    if already_wrapped.iter().any(|w| w.same_base(&symbol!(n))) {
                         return Err(LinkError::WrappedSymbolNameAlreadyExists);
                     }
                 }
-                if routine_names.contains(&sym.st_name) {
-                    sym.st_name = SymbolName::WrappedSName(sym.st_name.deref().to_owned());
-                    already_wrapped.insert(&sym.st_name);
+            }
+        }
+        // a misspelled routine name would otherwise go unnoticed
+        for name in routine_names.iter() {
+            if !already_wrapped.iter().any(|w| w.same_base(name)) {
+                if self.strict_wrap {
+                    return Err(LinkError::WrapRoutineNotFound(name.clone()));
                 }
+                self.logger
+                    .warn(&format!("Routine to wrap {name} is not in any object"));
             }
         }
         Ok(())
@@ -1151,13 +2587,20 @@ impl LinkerEditor {
         let mut stub_lib = StubLib::new("stublib".to_string());
         for (obj_id, obj) in self.session_objects.iter() {
             let mut syms = BTreeMap::new();
-            for ste in obj.symbol_table.iter() {
-                match info.global_symtable.get(&ste.st_name) {
+            for ste in obj.symbol_table.iter().filter(|ste| !ste.is_local()) {
+                match info
+                    .global_symtable
+                    .get(&self.symbol_resolution.key(&ste.st_name))
+                {
                     None => panic!("build_stub_lib: global symbol '{}' not found", ste.st_name),
-                    Some((maybe_defn, _refs)) => match maybe_defn {
+                    Some(gsym) => match &gsym.defn {
                         None => panic!("build_stub_lib: global symbol defn undefined"),
                         Some(defn) => match &defn.defn_prov {
-                            DefnProvenance::FromObjectIn => {
+                            DefnProvenance::FromObjectIn
+                            | DefnProvenance::FromDefSym
+                            | DefnProvenance::FromCommonBlock
+                            | DefnProvenance::FromGot
+                            | DefnProvenance::FromLibSegment => {
                                 syms.insert(
                                     ste.st_name.to_owned(),
                                     Either::Left(defn.defn_addr.unwrap()),
@@ -1175,6 +2618,15 @@ impl LinkerEditor {
             }
             let stub_member =
                 StubMember::new(stub_lib.libname.to_owned(), format!("{obj_id}_stub"), syms);
+            let defined_syms = stub_member
+                .syms
+                .iter()
+                .filter(|(_, v)| v.is_left())
+                .map(|(k, _)| k.clone())
+                .collect();
+            stub_lib
+                .defs
+                .insert(stub_member.name.to_owned(), defined_syms);
             stub_lib
                 .members
                 .insert(stub_member.name.to_owned(), stub_member);
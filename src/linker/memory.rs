@@ -0,0 +1,60 @@
+use crate::types::segment::SegmentDescr;
+
+// Address ranges the output has to fit in, like the MEMORY command of an
+// ld script:
+//   FLASH (R)  : start 0x0,    length 0x8000
+//   RAM   (RW) : start 0x8000, length 0x2000
+// Each segment goes in the first region with the permissions it needs (R and
+// W of its descriptors), read only segments preferring read only regions.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct MemoryRegions {
+    pub regions: Vec<MemoryRegion>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MemoryRegion {
+    pub name: String,
+    pub start: i32,
+    pub len: i32,
+    pub perms: Vec<SegmentDescr>, // R and/or W, P is ignored
+}
+
+impl MemoryRegion {
+    pub fn new(name: &str, start: i32, len: i32, perms: Vec<SegmentDescr>) -> Self {
+        MemoryRegion {
+            name: name.to_owned(),
+            start,
+            len,
+            perms,
+        }
+    }
+
+    pub fn contains(&self, addr: i32) -> bool {
+        self.start <= addr && addr < self.start + self.len
+    }
+
+    fn allows(&self, perms: &[SegmentDescr]) -> bool {
+        perms
+            .iter()
+            .filter(|p| **p != SegmentDescr::P)
+            .all(|p| self.perms.contains(p))
+    }
+
+    fn is_writable(&self) -> bool {
+        self.perms.contains(&SegmentDescr::W)
+    }
+}
+
+impl MemoryRegions {
+    pub fn new(regions: Vec<MemoryRegion>) -> Self {
+        MemoryRegions { regions }
+    }
+
+    pub fn region_for(&self, perms: &[SegmentDescr]) -> Option<&MemoryRegion> {
+        let writable = perms.contains(&SegmentDescr::W);
+        self.regions
+            .iter()
+            .find(|r| r.is_writable() == writable && r.allows(perms))
+            .or_else(|| self.regions.iter().find(|r| r.allows(perms)))
+    }
+}
@@ -1 +1,4 @@
 pub mod editor;
+pub mod memory;
+pub mod script;
+pub mod stats;
@@ -0,0 +1,56 @@
+use crate::common::Alignment;
+use crate::types::errors::ParseError;
+use crate::types::symbol_table::SymbolName;
+
+// Link settings, as an alternative to LinkerEditor::new arguments:
+//   # comments run to the end of the line
+//   text_start = 0x1000;
+//   data_align = 0x100;
+//   bss_align = 0x4;
+//   entry = _start;
+// Numbers are decimal, or hex with a 0x prefix. Every setting is optional,
+// unset ones are as for LinkerEditor::new(0, 0, 0, ..).
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct LinkScript {
+    pub text_start: Option<i32>,
+    pub data_align: Option<i32>, // data_start_boundary
+    pub bss_align: Option<i32>,  // bss_start_boundary
+    pub entry: Option<SymbolName>,
+}
+
+impl LinkScript {
+    pub fn parse(text: &str) -> Result<LinkScript, ParseError> {
+        let mut script = LinkScript::default();
+        for (i, line) in text.lines().enumerate() {
+            let err = ParseError::InvalidLinkScript(i + 1);
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+            let stmt = match line.strip_suffix(';') {
+                None => return Err(err),
+                Some(stmt) => stmt,
+            };
+            let (key, value) = match stmt.split_once('=') {
+                None => return Err(err),
+                Some((key, value)) => (key.trim(), value.trim()),
+            };
+            let num = || match value.strip_prefix("0x") {
+                Some(hex) => i32::from_str_radix(hex, 16).ok(),
+                None => value.parse::<i32>().ok(),
+            };
+            // 0 or a power of two, see Alignment
+            let align = || num().filter(|n| Alignment::try_from(*n).is_ok());
+            match key {
+                "text_start" => script.text_start = Some(num().ok_or(err)?),
+                "data_align" => script.data_align = Some(align().ok_or(err)?),
+                "bss_align" => script.bss_align = Some(align().ok_or(err)?),
+                "entry" if !value.is_empty() && !value.contains(char::is_whitespace) => {
+                    script.entry = Some(SymbolName::SName(value.to_owned()))
+                }
+                _ => return Err(err),
+            }
+        }
+        Ok(script)
+    }
+}
@@ -0,0 +1,53 @@
+use std::collections::BTreeMap;
+
+use crate::types::relocation::RelType;
+use crate::types::segment::SegmentName;
+
+// Summary of a finished link, see LinkerInfo::stats
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct LinkStats {
+    pub objects: usize,     // all objects linked, including library members
+    pub lib_objects: usize, // objects pulled from static libraries
+    pub symbols: usize,     // global symbols
+    pub resolved_symbols: usize,
+    pub common_blocks: usize,
+    pub relocations: BTreeMap<RelType, usize>, // applied, per type
+    pub er4_relocations: usize,                // created for the loader
+    pub deferred_relocations: usize,           // left for the loader
+    pub segment_sizes: BTreeMap<SegmentName, i32>,
+    pub segment_checksums: BTreeMap<SegmentName, u32>, // crc32, see SegmentData::crc32
+}
+
+impl LinkStats {
+    pub fn ppr(&self) -> String {
+        let mut s = vec![String::from("Link Stats:")];
+        s.push(format!(
+            "  objects: {} ({} from libraries)",
+            self.objects, self.lib_objects
+        ));
+        s.push(format!(
+            "  symbols: {} ({} resolved, {} common blocks)",
+            self.symbols, self.resolved_symbols, self.common_blocks
+        ));
+        let rels: Vec<String> = self
+            .relocations
+            .iter()
+            .map(|(rel_type, n)| format!("{rel_type}: {n}"))
+            .collect();
+        s.push(format!("  relocations: {}", rels.join(", ")));
+        s.push(format!(
+            "  loader relocations: {} ER4 created, {} deferred",
+            self.er4_relocations, self.deferred_relocations
+        ));
+        for segment_name in SegmentName::order().iter() {
+            if let Some(size) = self.segment_sizes.get(segment_name) {
+                let crc = self
+                    .segment_checksums
+                    .get(segment_name)
+                    .map_or(String::new(), |crc| format!(", crc32: {crc:08X}"));
+                s.push(format!("  {segment_name} size: {size:X}{crc}"));
+            }
+        }
+        s.join("\n")
+    }
+}
@@ -0,0 +1,48 @@
+use crate::types::out::ObjectOut;
+use crate::types::relocation::RelType;
+use crate::types::segment::Endianness;
+
+// Build the in-memory image of a linked object loaded at base, the way a
+// loader would at program start. The image spans all segments, from the
+// lowest segment start (offset 0) to the highest segment end. Every ER4
+// (executable relative) location gets base added to it.
+pub fn load(out: &ObjectOut, base: i32) -> Vec<u8> {
+    let image_start = out
+        .segments
+        .values()
+        .map(|s| s.segment_start)
+        .min()
+        .unwrap_or(0);
+    let image_end = out
+        .segments
+        .values()
+        .map(|s| s.segment_start + s.segment_len)
+        .max()
+        .unwrap_or(0);
+
+    let mut image = vec![0; (image_end - image_start) as usize];
+    for (segment_name, segment_data) in out.object_data.iter() {
+        let seg = out.segments.get(segment_name).unwrap();
+        let off = (seg.segment_start - image_start) as usize;
+        image[off..off + segment_data.len()].copy_from_slice(segment_data);
+    }
+
+    for r in out
+        .relocations
+        .iter()
+        .filter(|r| r.rel_type == RelType::ER4)
+    {
+        let seg = out.segments.get(&r.rel_seg).unwrap();
+        let off = (seg.segment_start - image_start + r.rel_loc) as usize;
+        let bytes: [u8; 4] = match image.get(off..off + 4) {
+            Some(bytes) => bytes.try_into().unwrap(),
+            None => panic!("load: ER4 relocation at 0x{off:X} out of range"),
+        };
+        let val = match out.endianness {
+            Endianness::BigEndian => (i32::from_be_bytes(bytes) + base).to_be_bytes(),
+            Endianness::LittleEndian => (i32::from_le_bytes(bytes) + base).to_le_bytes(),
+        };
+        image[off..off + 4].copy_from_slice(&val);
+    }
+    image
+}
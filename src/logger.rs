@@ -1,9 +1,12 @@
 use colored::Colorize;
+use std::env;
+use std::io::{self, IsTerminal};
 
 pub struct Logger {
     logger_ty: LoggerType,
     log_entries: Vec<(LogLevel, String)>,
     pub silent: bool,
+    pub color: bool, // colored level prefixes, see color_default
 }
 
 #[derive(Eq, PartialEq)]
@@ -26,6 +29,7 @@ impl Logger {
             logger_ty: LoggerType::StdOut,
             log_entries: vec![],
             silent,
+            color: Logger::color_default(),
         }
     }
 
@@ -35,23 +39,48 @@ impl Logger {
             logger_ty: LoggerType::TestLogger,
             log_entries: vec![],
             silent,
+            color: Logger::color_default(),
         }
     }
 
+    // Color only when writing to a terminal, and never when NO_COLOR is set
+    // (to anything but the empty string, see no-color.org)
+    pub fn color_default() -> bool {
+        let no_color = env::var_os("NO_COLOR").is_some_and(|v| !v.is_empty());
+        !no_color && io::stdout().is_terminal()
+    }
+
+    // Lines logged so far, test loggers only
+    pub fn entries(&self) -> &[(LogLevel, String)] {
+        &self.log_entries
+    }
+
     fn push(&mut self, lvl: LogLevel, msg: &str) {
         self.log_entries.push((lvl, String::from(msg)));
     }
 
     pub fn do_log(&mut self, lvl: LogLevel, msg: &str) {
         let pref = match lvl {
-            LogLevel::Info => "[INFO]".to_string().bold(),
-            LogLevel::Debug => "[DEBUG]".to_string().dimmed(),
-            LogLevel::Warn => "[WARN]".to_string().yellow(),
-            LogLevel::Error => "[ERROR]".to_string().red(),
+            LogLevel::Info => "[INFO]",
+            LogLevel::Debug => "[DEBUG]",
+            LogLevel::Warn => "[WARN]",
+            LogLevel::Error => "[ERROR]",
         };
-        println! {"{pref}: {msg}"};
+        let pref = if !self.color {
+            pref.to_string()
+        } else {
+            match lvl {
+                LogLevel::Info => pref.bold(),
+                LogLevel::Debug => pref.dimmed(),
+                LogLevel::Warn => pref.yellow(),
+                LogLevel::Error => pref.red(),
+            }
+            .to_string()
+        };
+        let line = format!("{pref}: {msg}");
+        println! {"{line}"};
         if self.logger_ty == LoggerType::TestLogger {
-            self.push(lvl, msg);
+            self.push(lvl, &line);
         }
     }
 
@@ -64,4 +93,9 @@ impl Logger {
     pub fn info(&mut self, msg: &str) {
         self.do_log(LogLevel::Info, msg);
     }
+
+    #[allow(dead_code)]
+    pub fn warn(&mut self, msg: &str) {
+        self.do_log(LogLevel::Warn, msg);
+    }
 }
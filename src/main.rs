@@ -1,7 +1,5 @@
-mod logger;
-
+use linkerloader::logger::{LogLevel, Logger};
 use linkerloader::types::object::MAGIC_NUMBER;
-use logger::{LogLevel, Logger};
 
 fn main() {
     let mut logger = Logger::new_stdout_logger(false);
@@ -1,9 +1,14 @@
+use crate::types::segment::SegmentName;
+use crate::types::symbol_table::SymbolName;
+
 #[derive(Debug, PartialEq, Eq)]
 pub enum ParseError {
     UnexpectedParseError,
 
     MissingMagicNumber,
     InvalidMagicNumber,
+    UnsupportedVersion(u32),
+    InvalidArch,
     MissingNSegsNSumsNRels,
     InvalidNSegsNSumsNRels,
     InvalidNSegsValue,
@@ -14,15 +19,21 @@ pub enum ParseError {
     InvalidSegmentName,
     InvalidSegmentStart,
     InvalidSegmentLen,
+    SegmentStartOutOfRange, // above i32::MAX
+    SegmentLenOutOfRange,
     InvalidSegmentDescr,
+    InvalidSegmentAlign,
     InvalidNumOfSegments,
 
     InvalidSymbolTableEntry,
     InvalidSTEType,
+    InvalidSTEVisibility,
     InvalidSTEValue,
     InvalidSTESegment,
+    InvalidSTESize,
     InvalidNumOfSTEs,
     STESegmentRefOutOfRange,
+    DuplicateSymbolInObject(String), // defined twice
 
     InvalidRelocationEntry,
     InvalidRelRef,
@@ -35,6 +46,26 @@ pub enum ParseError {
     InvalidObjectData,
     SegmentDataLengthMismatch,
     SegmentDataOutOfBounds,
+    SectionCountMismatch,
+
+    InvalidArchive,
+    InvalidGzip,              // see gzip::gunzip
+    InvalidLinkScript(usize), // line of the bad statement, see LinkScript::parse
+    IOError(std::io::ErrorKind),
+    FileReadError(String), // path of the file that could not be read
+
+    // where in an object file the error is, see parse_object_file
+    At { line: usize, kind: Box<ParseError> },
+}
+
+impl ParseError {
+    // The error itself, without the location
+    pub fn kind(&self) -> &ParseError {
+        match self {
+            ParseError::At { kind, .. } => kind.kind(),
+            e => e,
+        }
+    }
 }
 
 #[derive(Debug, PartialEq, Eq)]
@@ -46,8 +77,48 @@ pub enum LinkError {
     AddressOverflowError,
     IntOverflowError,
     WrappedSymbolNameAlreadyExists,
+    WrapRoutineNotFound(SymbolName), // no object has the symbol, with strict wrapping
+    RenamedSymbolNameAlreadyExists(SymbolName), // see LinkerEditor::rename_symbols
     SharedLibsReferenceCycle,
     SharedLibRefDefnNotFound,
+    ArchMismatch,
+    EndiannessMismatch,    // see ObjectOut::merge
+    InvalidAlignment(i32), // see Alignment
+    RelocationOutOfRange,
+    RelocationRefOutOfRange,
+    RelocationInBss,
+    // the segment the relocation is in is not in the output
+    RelocationTargetSegmentMissing(SegmentName),
+    // relative displacement doesn't fit in the relocated location
+    RelocationDisplacementOverflow {
+        seg: SegmentName,
+        loc: i32,
+    },
+    StaticLibParseFailure(Box<LibError>),
+    ObjectParseFailure(ParseError), // see lib::link_directory
+    OverlappingSegments,
+    NoGlobalOffsetTable,    // GOT_SYMBOL referenced, but there is no GOT
+    GotRequiredButDisabled, // see GotMode::Disable
+    GotSlotConflict(usize), // GP4 relocations pinned to the slot point at different symbols
+    // see LinkerEditor::set_memory_regions
+    NoMemoryRegion(SegmentName),
+    RegionOverflow {
+        region: String,
+        needed: i32,
+        available: i32,
+    },
+    AssertionFailed(String),                // see LinkerEditor::add_assertion
+    SegmentPermissionConflict(SegmentName), // see LinkerEditor::set_strict_segment_permissions
+    SegmentExceedsPadding(SegmentName),     // see LinkerEditor::pad_segment
+    SegmentCountMismatch,
+    SegmentDataMismatch(SegmentName), // missing, or not as long as the segment
+    // start..start+len is not within the segment data (SegmentBytes::update,
+    // SegmentData::fill, splice)
+    SegmentDataOutOfRange {
+        start: usize,
+        len: usize,
+    },
+    IOError(std::io::ErrorKind), // see FileSegmentData
 }
 
 #[derive(Debug, PartialEq, Eq)]
@@ -57,11 +128,15 @@ pub enum LibError {
     StubMemberParseFailure(ParseError),
     ObjectLinkError(LinkError),
     ParseLibError,
-    IOError,
+    LibOffsetOutOfRange(usize), // module or directory offset past the end of a file lib
+    IOError(std::io::ErrorKind),
+    DuplicateModuleName(String),
+    FileReadError(String), // path of the lib (member) that could not be read
+    NotAFileLib(String),   // see StaticLib::write
 }
 
 impl From<std::io::Error> for LibError {
-    fn from(_: std::io::Error) -> Self {
-        LibError::IOError
+    fn from(e: std::io::Error) -> Self {
+        LibError::IOError(e.kind())
     }
 }
@@ -4,12 +4,12 @@ use std::io::Write;
 use std::path::{Path, PathBuf};
 use std::{env, fs};
 
-use crate::common::{ObjectID, MAGIC_NUMBER_LIB, MAP_FILE_NAME};
+use crate::common::{ObjectID, MAGIC_NUMBER_LIB, MAP_FILE_NAME, WEAK_SYMBOL_PREFIX};
 use crate::linker::editor::LinkerEditor;
 use crate::types::errors::LibError;
 use crate::types::object::{parse_object_file, ObjectIn, MAGIC_NUMBER};
 use crate::types::symbol_table::SymbolName;
-use crate::utils::{count_new_lines, read_object_file};
+use crate::utils::count_new_lines;
 
 use super::stub::StubLib;
 
@@ -20,6 +20,9 @@ pub enum StaticLib {
     DirLib {
         libname: String,
         symbols: BTreeMap<ObjectID, BTreeSet<SymbolName>>,
+        // The subset of symbols marked "weak:" in the MAP: a member is only
+        // pulled in for those if nothing else defines them.
+        weak_symbols: BTreeMap<ObjectID, BTreeSet<SymbolName>>,
         objects: HashMap<ObjectID, ObjectIn>,
     },
     FileLib {
@@ -79,17 +82,20 @@ impl StaticLib {
 
     fn parse_dir_lib(path: &str) -> Result<Self, LibError> {
         let mut symbols = BTreeMap::new();
+        let mut weak_symbols = BTreeMap::new();
         let mut objects = HashMap::new();
 
         let lib_path = Path::new(path);
         let entries = fs::read_dir(lib_path)
-            .unwrap()
+            .map_err(|_| LibError::FileReadError(path.to_owned()))?
             .filter_map(|entry| entry.ok())
             .collect::<Vec<_>>();
         for entry in entries {
             let path = entry.path();
-            if path.is_file() {
-                let file_contents = fs::read_to_string(&path).unwrap();
+            // not is_file: a broken symlink should be reported, not skipped
+            if !path.is_dir() {
+                let file_contents = fs::read_to_string(&path)
+                    .map_err(|_| LibError::FileReadError(path.to_string_lossy().into_owned()))?;
                 let file_name = path.file_name().unwrap().to_str().unwrap().to_string();
                 if path
                     .file_name()
@@ -100,16 +106,30 @@ impl StaticLib {
                 {
                     println!("reading MAP file");
                     for l in file_contents.lines() {
-                        let toks: Vec<String> = l.split(' ').map(|s| s.trim().to_owned()).collect();
+                        let toks: Vec<&str> = l.split_whitespace().collect();
                         match toks.as_slice() {
                             [mod_name, syms @ ..] => {
-                                let mod_symbols = syms
-                                    .iter()
-                                    .map(|s| SymbolName::SName(s.to_owned()))
-                                    .collect();
+                                let mut mod_symbols = BTreeSet::new();
+                                let mut mod_weak_symbols = BTreeSet::new();
+                                for s in syms.iter() {
+                                    match s.strip_prefix(WEAK_SYMBOL_PREFIX) {
+                                        Some(weak) => {
+                                            mod_weak_symbols
+                                                .insert(SymbolName::SName(weak.to_string()));
+                                            mod_symbols.insert(SymbolName::SName(weak.to_string()));
+                                        }
+                                        None => {
+                                            mod_symbols.insert(SymbolName::SName(s.to_string()));
+                                        }
+                                    }
+                                }
                                 symbols.insert(mod_name.to_string(), mod_symbols);
+                                if !mod_weak_symbols.is_empty() {
+                                    weak_symbols.insert(mod_name.to_string(), mod_weak_symbols);
+                                }
                             }
-                            _ => panic!("parse_dir_lib: empty MAP entry"),
+                            // blank line
+                            [] => continue,
                         }
                     }
                 } else {
@@ -132,6 +152,7 @@ impl StaticLib {
             .into_owned();
         Ok(StaticLib::DirLib {
             symbols,
+            weak_symbols,
             objects,
             libname,
         })
@@ -140,16 +161,26 @@ impl StaticLib {
     fn parse_file_lib(path: &str) -> Result<Self, LibError> {
         let mut objects = vec![];
         let mut symbols = HashMap::new();
-        let file_contents = read_object_file(path);
+        let file_contents =
+            fs::read_to_string(path).map_err(|_| LibError::FileReadError(path.to_owned()))?;
         let file_lines: Vec<&str> = file_contents.lines().collect();
-        let hdr: Vec<&str> = file_lines[0].split(' ').map(|s| s.trim()).collect();
+        let hdr: Vec<&str> = match file_lines.first() {
+            Some(l) => l.split(' ').map(|s| s.trim()).collect(),
+            None => return Err(LibError::ParseLibError),
+        };
+        let hex = |s: &str| usize::from_str_radix(s, 16).map_err(|_| LibError::ParseLibError);
         let (num_of_mods, lib_dir_offset) = match hdr.as_slice() {
-            ["LIBRARY", num_of_mods, lib_dir_offs] => (
-                usize::from_str_radix(num_of_mods, 16).unwrap(),
-                usize::from_str_radix(lib_dir_offs, 16).unwrap(),
-            ),
+            ["LIBRARY", num_of_mods, lib_dir_offs] => (hex(num_of_mods)?, hex(lib_dir_offs)?),
             _ => return Err(LibError::ParseLibError),
         };
+        // a truncated or tampered file must not send us past its end
+        if lib_dir_offset == 0
+            || lib_dir_offset
+                .checked_add(num_of_mods)
+                .is_none_or(|end| end - 1 > file_lines.len())
+        {
+            return Err(LibError::LibOffsetOutOfRange(lib_dir_offset));
+        }
         for i in 0..num_of_mods {
             let mod_entry: Vec<&str> = file_lines[lib_dir_offset + i - 1]
                 .split(' ')
@@ -158,12 +189,16 @@ impl StaticLib {
             match mod_entry.as_slice() {
                 [offs, mod_len, syms @ ..] => {
                     let mut obj_in = vec![MAGIC_NUMBER];
-                    let offset = usize::from_str_radix(offs, 16).unwrap() - 1;
-                    let len = usize::from_str_radix(mod_len, 16).unwrap();
-                    #[allow(clippy::needless_range_loop)]
-                    for j in offset..offset + len {
-                        obj_in.push(file_lines[j]);
+                    let offset = hex(offs)?;
+                    let len = hex(mod_len)?;
+                    if offset == 0
+                        || offset
+                            .checked_add(len)
+                            .is_none_or(|end| end - 1 > file_lines.len())
+                    {
+                        return Err(LibError::LibOffsetOutOfRange(offset));
                     }
+                    obj_in.extend_from_slice(&file_lines[offset - 1..offset - 1 + len]);
                     let obj_str = obj_in.join("\n");
                     match parse_object_file(obj_str) {
                         Err(e) => {
@@ -190,12 +225,44 @@ impl StaticLib {
         })
     }
 
+    // File lib of already parsed objects, in name order. Same layout as
+    // build_static_filelib gives, without reading anything from disk.
+    pub fn from_objects(libname: &str, objects: BTreeMap<String, ObjectIn>) -> StaticLib {
+        let mut symbols = HashMap::new();
+        for (i, obj) in objects.values().enumerate() {
+            for sym in obj.symbol_table.iter() {
+                if sym.is_defined() && !sym.is_local() {
+                    symbols.insert(sym.st_name.clone(), i);
+                }
+            }
+        }
+        StaticLib::FileLib {
+            libname: libname.to_owned(),
+            symbols,
+            objects: objects.into_values().collect(),
+        }
+    }
+
+    // Write a file lib to the file at path, in the format parse reads back.
+    // Other kinds of lib have no single file to write to.
+    pub fn write(&self, path: &str) -> Result<(), LibError> {
+        match self {
+            StaticLib::FileLib { objects, .. } => {
+                let objects: Vec<&ObjectIn> = objects.iter().collect();
+                let mut lib_file = File::create(path)?;
+                lib_file.write_all(StaticLib::make_staticlib_file(&objects).as_bytes())?;
+                Ok(())
+            }
+            _ => Err(LibError::NotAFileLib(self.get_name().to_owned())),
+        }
+    }
+
     fn make_map_file(objects: HashMap<&str, ObjectIn>) -> String {
         let mut map_file = vec![];
         for (name, o) in objects.iter() {
             let mut entry = vec![*name];
             for sym in o.symbol_table.iter() {
-                if sym.is_defined() {
+                if sym.is_defined() && !sym.is_local() {
                     entry.push(&sym.st_name);
                 }
             }
@@ -204,22 +271,22 @@ impl StaticLib {
         map_file.join("\n")
     }
 
-    fn make_staticlib_file(objects: HashMap<&str, ObjectIn>) -> String {
+    // modules are written in the order given, which is their index in the
+    // library directory
+    fn make_staticlib_file(objects: &[&ObjectIn]) -> String {
         // add dummy first row for header which will be updated at the end
         let mut res = vec![String::new()];
         let mut modules = vec![];
         let mut mod_details = vec![];
         let mut offset: usize = 2; // account for header line
 
-        let mut entries: Vec<_> = objects.iter().collect();
-        entries.sort_by(|a, b| a.0.partial_cmp(b.0).unwrap());
-        for (_, obj) in entries {
+        for obj in objects.iter() {
             let printed_obj = obj.ppr(false);
             let mod_len = count_new_lines(&printed_obj) + 1;
             // find all the defined symbols
             let mut defined_syms = vec![];
             for s in obj.symbol_table.iter() {
-                if s.is_defined() {
+                if s.is_defined() && !s.is_local() {
                     defined_syms.push(s.st_name.as_str());
                 }
             }
@@ -250,11 +317,25 @@ impl StaticLib {
             None => PathBuf::from("staticlib"),
         };
         let lib_path = path.join(&name);
+
+        // modules are stored under their basename, so those must be unique
+        let mut mod_names = vec![];
+        for object_file in object_files.iter() {
+            let mod_name = match Path::new(*object_file).file_name().and_then(|n| n.to_str()) {
+                Some(n) => n,
+                None => return Err(LibError::UnexpectedLibError),
+            };
+            if mod_names.contains(&mod_name) {
+                return Err(LibError::DuplicateModuleName(mod_name.to_owned()));
+            }
+            mod_names.push(mod_name);
+        }
+
         match std::fs::create_dir(&lib_path) {
             Ok(_) => (),
             Err(e) => {
                 if e.kind() != std::io::ErrorKind::AlreadyExists {
-                    panic!("Error creating static lib file: {e}");
+                    return Err(e.into());
                 } else {
                     panic!("static lib file at {basepath:?} already exists, deal with it first!");
                 }
@@ -262,16 +343,16 @@ impl StaticLib {
         }
 
         let mut objects = HashMap::new();
-        for object_file in object_files.into_iter() {
+        for (object_file, mod_name) in object_files.into_iter().zip(mod_names) {
             let obj_path = path.clone().join(object_file);
-            let contents = fs::read_to_string(obj_path)?;
+            let contents = fs::read_to_string(&obj_path)?;
             match parse_object_file(contents) {
                 Err(e) => {
                     return Err(LibError::ObjectParseFailure(e));
                 }
                 Ok(o) => {
-                    objects.insert(object_file, o);
-                    std::fs::copy(path.join(object_file), lib_path.join(object_file))?;
+                    objects.insert(mod_name, o);
+                    std::fs::copy(obj_path, lib_path.join(mod_name))?;
                 }
             }
         }
@@ -310,8 +391,11 @@ impl StaticLib {
             }
         }
 
+        let mut entries: Vec<_> = objects.iter().collect();
+        entries.sort_by(|a, b| a.0.partial_cmp(b.0).unwrap());
+        let objects: Vec<&ObjectIn> = entries.into_iter().map(|(_, o)| o).collect();
         let mut map_file = File::create(lib_path)?;
-        map_file.write_all(StaticLib::make_staticlib_file(objects).as_bytes())?;
+        map_file.write_all(StaticLib::make_staticlib_file(&objects).as_bytes())?;
         Ok(name.to_str().unwrap().to_owned())
     }
 
@@ -1,16 +1,26 @@
+use std::collections::{HashMap, HashSet};
 use std::iter::Peekable;
 use std::num::ParseIntError;
 use std::ops::Deref;
 use std::str::Lines;
 
 use crate::types::errors::ParseError;
-use crate::types::relocation::{parse_relocation, Relocation};
-use crate::types::segment::{parse_segment, parse_segment_data, Segment, SegmentData};
+use crate::types::relocation::{
+    parse_relocation, relocation_table, RelRef, Relocation, SEGMENT_REF_MARK,
+};
+use crate::types::segment::{
+    parse_segment, parse_segment_data, Segment, SegmentData, SegmentDescr, SegmentName,
+};
 use crate::types::symbol_table::{parse_symbol_table_entry, SymbolTableEntry};
 use crate::types::symbol_table::{SymbolName, SymbolTableEntryType};
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct ObjectIn {
+    // Optional target architecture, declared by an "ARCH <name>" line right
+    // after the magic number. None means the object links with any target.
+    pub arch: Option<String>,
+    // Format version, from "LINK <version>". A bare "LINK" is version 1.
+    pub version: u32,
     pub nsegs: i32,
     pub nsyms: i32,
     pub nrels: i32,
@@ -22,95 +32,178 @@ pub struct ObjectIn {
     // easy to read and write section data in perl.) Each pair of hex digits
     // represents one byte. The segment data strings are in the same order as
     // the segment table, and there must be segment data for each "present" segment.
+    // (For every segment in version 1 objects, see segment.rs.)
     // The length of the hex string is determined by the the defined length of the
     pub object_data: Vec<SegmentData>,
 }
 
 pub const MAGIC_NUMBER: &str = "LINK";
+pub const ARCH_TAG: &str = "ARCH";
+pub const SUPPORTED_VERSIONS: [u32; 2] = [1, 2];
+
+// Segments are numbered from 1 in the order the object lists them, by symbol
+// st_seg and relocation seg alike. 0 is no segment (absolute symbols).
+pub fn segment_ordinal(segs: &[Segment], name: &SegmentName) -> Option<i32> {
+    segs.iter()
+        .position(|s| s.segment_name == *name)
+        .map(|i| i as i32 + 1)
+}
+
+pub fn segment_by_ordinal(segs: &[Segment], ordinal: i32) -> Option<&Segment> {
+    usize::try_from(ordinal - 1).ok().and_then(|i| segs.get(i))
+}
 
 impl ObjectIn {
+    pub fn segment_ordinal(&self, name: &SegmentName) -> Option<i32> {
+        segment_ordinal(&self.segments, name)
+    }
+
+    pub fn segment_by_ordinal(&self, ordinal: i32) -> Option<&Segment> {
+        segment_by_ordinal(&self.segments, ordinal)
+    }
+
+    // Name -> symbol table positions, for objects where scanning symbol_table
+    // on every lookup gets expensive. A name may have more than one entry
+    // (a reference and a definition, say), in symbol table order.
+    pub fn build_symbol_index(&self) -> HashMap<&SymbolName, Vec<usize>> {
+        let mut index: HashMap<&SymbolName, Vec<usize>> =
+            HashMap::with_capacity(self.symbol_table.len());
+        for (i, ste) in self.symbol_table.iter().enumerate() {
+            index.entry(&ste.st_name).or_default().push(i);
+        }
+        index
+    }
+
+    // Relocations with their refs resolved to segment and symbol names
+    pub fn relocation_table(&self) -> String {
+        let rows: Vec<(&Relocation, String)> = self
+            .relocations
+            .iter()
+            .map(|r| (r, r.describe(self)))
+            .collect();
+        relocation_table(&rows)
+    }
+
+    // One line per item; sections are not separated by blank lines. Every
+    // segment gets a data line, which is empty for a zero-length segment.
+    // From version 2 on only present segments do (see parse_object_data),
+    // but without the header a reader takes the text for version 1.
     pub fn ppr(&self, include_hdr: bool) -> String {
-        let mut s = String::new();
-        if include_hdr {
-            s.push_str(MAGIC_NUMBER);
-            s.push('\n');
+        let mut lines = vec![];
+        // version 1 objects keep the bare magic number older readers expect
+        if include_hdr && self.version >= 2 {
+            lines.push(format!("{MAGIC_NUMBER} {}", self.version));
+        } else if include_hdr {
+            lines.push(MAGIC_NUMBER.to_owned());
         }
-        s.push_str(format!("{:X} {:X} {:X}\n", self.nsegs, self.nsyms, self.nrels).as_str());
-        let mut segs = vec![];
+        if let Some(arch) = &self.arch {
+            lines.push(format!("{ARCH_TAG} {arch}"));
+        }
+        lines.push(format!(
+            "{:X} {:X} {:X}",
+            self.nsegs, self.nsyms, self.nrels
+        ));
         for seg in self.segments.iter() {
             let descrs = seg.ppr_seg_descr();
-            segs.push(format!(
+            let mut line = format!(
                 "{} {:X} {:X} {descrs}",
                 seg.segment_name, seg.segment_start, seg.segment_len
-            ))
+            );
+            if seg.segment_align != 0 {
+                line.push_str(format!(" {:X}", seg.segment_align).as_str());
+            }
+            lines.push(line)
         }
-        s.push_str(segs.join("\n").as_str());
-        s.push('\n');
 
-        let mut stes = vec![];
         for ste in self.symbol_table.iter() {
             let name = match &ste.st_name {
-                SymbolName::SName(s) => s.to_owned(),
+                SymbolName::SName(s) | SymbolName::Local(s, _) => s.to_owned(),
                 SymbolName::WrappedSName(s) => match &ste.st_type {
                     SymbolTableEntryType::D => format!("real_{s}"),
                     SymbolTableEntryType::U => format!("wrap_{s}"),
                 },
             };
-            stes.push(format!(
-                "{name} {:X} {:X} {}",
+            // global is the default and not printed
+            let vis = if ste.is_local() { " L" } else { "" };
+            let size = ste.st_size.map_or(String::new(), |sz| format!(" {sz:X}"));
+            lines.push(format!(
+                "{name} {:X} {:X} {}{vis}{size}",
                 ste.st_value, ste.st_seg, ste.st_type
             ))
         }
-        s.push_str(stes.join("\n").as_str());
-        s.push('\n');
 
-        let mut rels = vec![];
         for rel in self.relocations.iter() {
-            let seg = self
-                .segments
-                .iter()
-                .position(|s| s.segment_name == rel.rel_seg)
-                .unwrap()
-                + 1;
-            rels.push(format!(
-                "{:X} {:X} {} {}",
-                rel.rel_loc, seg, rel.rel_ref, rel.rel_type
+            // 0 if the object doesn't have the segment (any more)
+            let seg = self.segment_ordinal(&rel.rel_seg).unwrap_or(0);
+            // refs are stored 0-based but written 1-based, 0 when unused
+            let rel_ref = match rel.rel_ref {
+                RelRef::SegmentRef(i) if rel.rel_type.is_segment_or_symbol_rel() => {
+                    format!("{SEGMENT_REF_MARK}{:X}", i + 1)
+                }
+                RelRef::SegmentRef(i) | RelRef::SymbolRef(i) => format!("{:X}", i + 1),
+                RelRef::NoRef => String::from("0"),
+            };
+            let extra: String = rel.rel_extra.iter().map(|f| format!(" {f:X}")).collect();
+            lines.push(format!(
+                "{:X} {:X} {rel_ref} {}{extra}",
+                rel.rel_loc, seg, rel.rel_type
             ));
         }
-        s.push_str(rels.join("\n").as_str());
 
-        let mut code_data = vec![];
-        for data in self.object_data.iter() {
+        let version = if include_hdr { self.version } else { 1 };
+        for (seg, data) in self.segments.iter().zip(self.object_data.iter()) {
+            if !has_data_line(seg, version) {
+                continue;
+            }
             let mut ppr_data = vec![];
             for d in data.deref().iter() {
                 ppr_data.push(format!("{d:02X}"));
             }
-            code_data.push(ppr_data.join(" "));
+            lines.push(ppr_data.join(" "));
         }
-        s.push_str(code_data.join("\n").as_str());
-        s
+        lines.join("\n")
     }
 }
 
+// Errors come wrapped in ParseError::At, with the (1-based) number of the line
+// parsing stopped at.
 pub fn parse_object_file(file_contents: String) -> Result<ObjectIn, ParseError> {
+    parse_object_file_with_magic(file_contents, MAGIC_NUMBER)
+}
+
+// For objects of tools (or forks) that put another magic number in place of
+// MAGIC_NUMBER. The rest of the format is the same.
+pub fn parse_object_file_with_magic(
+    file_contents: String,
+    expected: &str,
+) -> Result<ObjectIn, ParseError> {
     let mut input: Peekable<Lines> = file_contents.lines().peekable();
+    parse_object_lines(&mut input, expected).map_err(|kind| ParseError::At {
+        line: file_contents.lines().count() - input.count(),
+        kind: Box::new(kind),
+    })
+}
 
-    // magic number check
-    match input.next() {
+fn parse_object_lines(
+    input: &mut Peekable<Lines>,
+    magic_number: &str,
+) -> Result<ObjectIn, ParseError> {
+    // magic number check, optionally followed by the format version
+    let version = match input.next() {
         None => return Err(ParseError::MissingMagicNumber),
-        Some(mn) => {
-            if mn != MAGIC_NUMBER {
-                return Err(ParseError::InvalidMagicNumber);
-            } else {
-            }
-        }
-    }
+        Some(mn) => parse_magic_number(mn, magic_number)?,
+    };
+
+    // optional target architecture
+    skip_ignorable(input);
+    let arch = parse_arch(input)?;
 
     // nsegs nsyms nrels
     let nsegs: i32;
     let nsyms: i32;
     let nrels: i32;
-    match parse_nsegs_nsyms_nrels(&mut input) {
+    skip_ignorable(input);
+    match parse_nsegs_nsyms_nrels(input) {
         Err(e) => return Err(e),
         Ok((segs, syms, rels)) => {
             nsegs = segs;
@@ -122,6 +215,7 @@ pub fn parse_object_file(file_contents: String) -> Result<ObjectIn, ParseError>
     // parse segments
     let mut segs: Vec<Segment> = vec![];
     for _ in 0..nsegs {
+        skip_ignorable(input);
         match input.next() {
             Some(s) => match parse_segment(s) {
                 Ok(seg) => segs.push(seg),
@@ -132,18 +226,34 @@ pub fn parse_object_file(file_contents: String) -> Result<ObjectIn, ParseError>
     }
     let segments: Vec<Segment> = segs;
     // more segments than nsegs - error out
+    skip_ignorable(input);
     if let Some(&l) = input.peek() {
         if parse_segment(l).is_ok() {
+            input.next();
             return Err(ParseError::InvalidNumOfSegments);
         }
     }
 
+    // the last symbol or relocation line, see reads_as_first_data_line
+    let mut last_line: Option<&str> = None;
+
     // parse symbol table
     let mut stes: Vec<SymbolTableEntry> = vec![];
+    // checked as each entry is read, so the error is on the duplicate's line
+    let mut defined: HashSet<SymbolName> = HashSet::new();
     for _ in 0..nsyms {
+        skip_ignorable(input);
         match input.next() {
             Some(s) => match parse_symbol_table_entry(nsegs, s) {
-                Ok(ste) => stes.push(ste),
+                Ok(ste) => {
+                    // references (and common blocks) may come with a definition
+                    // of the same name, but only one definition
+                    if ste.is_defined() && !defined.insert(ste.st_name.clone()) {
+                        return Err(ParseError::DuplicateSymbolInObject(ste.st_name.to_string()));
+                    }
+                    stes.push(ste);
+                    last_line = Some(s);
+                }
                 Err(e) => return Err(e),
             },
             None => return Err(ParseError::InvalidNumOfSTEs),
@@ -151,18 +261,35 @@ pub fn parse_object_file(file_contents: String) -> Result<ObjectIn, ParseError>
     }
     let symbol_table: Vec<SymbolTableEntry> = stes;
     // more segments than nsegs - error out
+    skip_ignorable(input);
     if let Some(&l) = input.peek() {
         if parse_symbol_table_entry(nsegs, l).is_ok() {
+            input.next();
             return Err(ParseError::InvalidNumOfSTEs);
         }
     }
+    // nor too many, see reads_as_first_data_line
+    if let Some(l) = last_line {
+        let ambiguous = if nrels > 0 {
+            parse_relocation(&segments, &symbol_table, l).is_ok()
+        } else {
+            reads_as_first_data_line(&segments, version, l)
+        };
+        if ambiguous {
+            return Err(ParseError::SectionCountMismatch);
+        }
+    }
 
     // parse relocation
     let mut rels: Vec<Relocation> = vec![];
     for _ in 0..nrels {
+        skip_ignorable(input);
         match input.next() {
             Some(s) => match parse_relocation(&segments, &symbol_table, s) {
-                Ok(rel) => rels.push(rel),
+                Ok(rel) => {
+                    rels.push(rel);
+                    last_line = Some(s);
+                }
                 Err(e) => return Err(e),
             },
             None => return Err(ParseError::InvalidNumOfRelocations),
@@ -170,34 +297,42 @@ pub fn parse_object_file(file_contents: String) -> Result<ObjectIn, ParseError>
     }
     let relocations: Vec<Relocation> = rels;
     // more relocs than nrels - error out
+    skip_ignorable(input);
     if let Some(&l) = input.peek() {
         if parse_relocation(&segments, &symbol_table, l).is_ok() {
+            input.next();
             return Err(ParseError::InvalidNumOfRelocations);
         }
     }
+    if nrels > 0 && last_line.is_some_and(|l| reads_as_first_data_line(&segments, version, l)) {
+        return Err(ParseError::SectionCountMismatch);
+    }
 
     // parse object_data
-    let mut seg_data: Vec<SegmentData> = vec![];
-    for i in 0..nsegs {
-        match input.next() {
-            Some(s) => {
-                // println!("{:?}", segments[i as usize]);
-                let seg_len = segments[i as usize].segment_len as usize;
-                match parse_segment_data(seg_len, s) {
-                    Ok(sd) => seg_data.push(sd),
-                    Err(e) => return Err(e),
-                }
-            }
-            None => return Err(ParseError::InvalidObjectData),
+    // Nothing is skipped inside the data section: a blank line there is the data
+    // of a zero-length segment. For the same reason blank lines right before it
+    // are only skipped when the first segment has data.
+    let first_seg_has_data = segments.first().is_some_and(|s| s.segment_len > 0);
+    while let Some(&l) = input.peek() {
+        if is_comment(l) || (first_seg_has_data && l.trim().is_empty()) {
+            input.next();
+        } else {
+            break;
         }
     }
-    let object_data: Vec<SegmentData> = seg_data;
-    // more data than nsegs - error out
-    if input.next().is_some() {
-        return Err(ParseError::SegmentDataOutOfBounds);
+    let object_data = parse_object_data(&segments, version, input)?;
+
+    // exactly the declared counts were read
+    if segments.len() != nsegs as usize
+        || symbol_table.len() != nsyms as usize
+        || relocations.len() != nrels as usize
+    {
+        return Err(ParseError::SectionCountMismatch);
     }
 
     Ok(ObjectIn {
+        arch,
+        version,
         nsegs,
         nsyms,
         nrels,
@@ -208,6 +343,101 @@ pub fn parse_object_file(file_contents: String) -> Result<ObjectIn, ParseError>
     })
 }
 
+// Only the counts tell where a section ends. One that is too small is caught
+// by peeking at the line after the section, but one that is too big makes the
+// section read on into the next, as long as those lines also read as entries
+// (e.g. "00 01 01 A4" is data as well as a relocation). So the last line of a
+// section must not also read as the first line of the next one.
+fn reads_as_first_data_line(segments: &[Segment], version: u32, l: &str) -> bool {
+    segments
+        .iter()
+        .find(|seg| has_data_line(seg, version))
+        .is_some_and(|seg| parse_segment_data(seg.segment_len as usize, l).is_ok())
+}
+
+fn parse_magic_number(line: &str, magic_number: &str) -> Result<u32, ParseError> {
+    match line
+        .split_ascii_whitespace()
+        .collect::<Vec<&str>>()
+        .as_slice()
+    {
+        [mn] if *mn == magic_number => Ok(1),
+        [mn, v] if *mn == magic_number => match v.parse::<u32>() {
+            Ok(version) if SUPPORTED_VERSIONS.contains(&version) => Ok(version),
+            Ok(version) => Err(ParseError::UnsupportedVersion(version)),
+            Err(_) => Err(ParseError::InvalidMagicNumber),
+        },
+        _ => Err(ParseError::InvalidMagicNumber),
+    }
+}
+
+// Version 1 objects give every segment a data line. From version 2 on,
+// segments without P (like .bss) are not present in the file: they have no
+// data line and are zero-filled.
+fn has_data_line(segment: &Segment, version: u32) -> bool {
+    version < 2 || segment.segment_descr.contains(&SegmentDescr::P)
+}
+
+fn parse_object_data(
+    segments: &[Segment],
+    version: u32,
+    input: &mut Peekable<Lines>,
+) -> Result<Vec<SegmentData>, ParseError> {
+    let mut seg_data: Vec<SegmentData> = vec![];
+    for segment in segments.iter() {
+        let seg_len = segment.segment_len as usize;
+        if !has_data_line(segment, version) {
+            seg_data.push(SegmentData::new(seg_len));
+            continue;
+        }
+        match input.next() {
+            Some(s) => match parse_segment_data(seg_len, s) {
+                Ok(sd) => seg_data.push(sd),
+                Err(e) => return Err(e),
+            },
+            // trailing empty data lines get lost when the text is split
+            // into lines, so a zero-length segment may have none at all
+            None if seg_len == 0 => seg_data.push(SegmentData::new(0)),
+            None => return Err(ParseError::InvalidObjectData),
+        }
+    }
+    // more data than nsegs - error out
+    if input.next().is_some() {
+        return Err(ParseError::SegmentDataOutOfBounds);
+    }
+    Ok(seg_data)
+}
+
+// Lines starting with '#' or ';' are comments
+fn is_comment(l: &str) -> bool {
+    let l = l.trim_start();
+    l.starts_with('#') || l.starts_with(';')
+}
+
+// Skip comments and blank lines. Only valid outside the data section.
+fn skip_ignorable(input: &mut Peekable<Lines>) {
+    while input
+        .peek()
+        .is_some_and(|l| is_comment(l) || l.trim().is_empty())
+    {
+        input.next();
+    }
+}
+
+fn parse_arch(input: &mut Peekable<Lines>) -> Result<Option<String>, ParseError> {
+    match input.peek() {
+        Some(l) if l.split_whitespace().next() == Some(ARCH_TAG) => {
+            let vs: Vec<&str> = l.split_whitespace().collect();
+            input.next();
+            match vs.as_slice() {
+                [_, arch] => Ok(Some(arch.to_string())),
+                _ => Err(ParseError::InvalidArch),
+            }
+        }
+        _ => Ok(None),
+    }
+}
+
 fn parse_nsegs_nsyms_nrels(input: &mut Peekable<Lines>) -> Result<(i32, i32, i32), ParseError> {
     let nsegs: i32;
     let nsyms: i32;
@@ -1,12 +1,49 @@
 use std::collections::BTreeMap;
 use std::ops::Deref;
 
+use crate::linker::editor::LinkerInfo;
+use crate::types::errors::LinkError;
 use crate::types::object::MAGIC_NUMBER;
-use crate::types::relocation::Relocation;
+use crate::types::relocation::{relocation_table, RelType, Relocation};
 use crate::types::segment::*;
 
 use super::symbol_table::{SymbolName, SymbolTableEntry, SymbolTableEntryType};
 
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum SymbolSort {
+    Name,
+    Address,
+}
+
+// What ObjectOut::strip removes from the symbol table, like ld -S (Locals)
+// and ld -s (All)
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum StripLevel {
+    None,
+    Locals,
+    All,
+}
+
+// A difference between a segment of one image and the same segment of
+// another, see ObjectOut::diff
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SegmentDiff {
+    Added(SegmentName),   // only in the other image
+    Removed(SegmentName), // only in this one
+    Layout {
+        segment_name: SegmentName,
+        old: (i32, i32), // start and len
+        new: (i32, i32),
+    },
+    // None past the end of the segment data
+    Byte {
+        segment_name: SegmentName,
+        offset: usize,
+        old: Option<u8>,
+        new: Option<u8>,
+    },
+}
+
 #[derive(Debug)]
 pub struct ObjectOut {
     pub nsegs: i32,
@@ -16,6 +53,7 @@ pub struct ObjectOut {
     pub symbol_table: Vec<SymbolTableEntry>,
     pub relocations: Vec<Relocation>,
     pub object_data: BTreeMap<SegmentName, SegmentData>,
+    pub endianness: Endianness, // of the words in object_data, as linked
 }
 
 impl Default for ObjectOut {
@@ -34,14 +72,339 @@ impl ObjectOut {
             symbol_table: Vec::new(),
             object_data: BTreeMap::new(),
             relocations: Vec::new(),
+            endianness: Endianness::BigEndian,
+        }
+    }
+
+    // Check that a linked image hangs together: every segment has its data,
+    // segments don't overlap and relocations are in existing segments. BSS
+    // data may be short of (or missing) the common blocks appended to BSS.
+    pub fn verify(&self) -> Result<(), LinkError> {
+        if self.nsegs as usize != self.segments.len() {
+            return Err(LinkError::SegmentCountMismatch);
+        }
+        if let Some(name) = self
+            .object_data
+            .keys()
+            .find(|name| !self.segments.contains_key(name))
+        {
+            return Err(LinkError::SegmentDataMismatch(name.clone()));
+        }
+        for (segment_name, seg) in self.segments.iter() {
+            let len_ok = match (self.object_data.get(segment_name), segment_name) {
+                (None, SegmentName::BSS) => true,
+                (None, _) => false,
+                (Some(data), SegmentName::BSS) => data.len() <= seg.segment_len as usize,
+                (Some(data), _) => data.len() == seg.segment_len as usize,
+            };
+            if !len_ok {
+                return Err(LinkError::SegmentDataMismatch(segment_name.clone()));
+            }
+            let start = seg.segment_start;
+            let end = start + seg.segment_len;
+            let overlaps = self.segments.iter().any(|(name, s)| {
+                name != segment_name
+                    && s.segment_len > 0
+                    && seg.segment_len > 0
+                    && start < s.segment_start + s.segment_len
+                    && s.segment_start < end
+            });
+            if overlaps {
+                return Err(LinkError::OverlappingSegments);
+            }
+        }
+        if self
+            .relocations
+            .iter()
+            .any(|r| !self.segments.contains_key(&r.rel_seg))
+        {
+            return Err(LinkError::RelocationOutOfRange);
+        }
+        Ok(())
+    }
+
+    // Add another linked image, moved up by offset_adjust, to this one (e.g.
+    // a payload behind a bootloader). A segment of other goes into the
+    // segment of the same name here, with any gap zero-filled, and its
+    // relocations follow it. ER4 locations hold link time addresses, so they
+    // are moved too. Symbols are not merged. Segments may not overlap, and
+    // both images must have the same byte order.
+    pub fn merge(&mut self, mut other: ObjectOut, offset_adjust: i32) -> Result<(), LinkError> {
+        if other.endianness != self.endianness {
+            return Err(LinkError::EndiannessMismatch);
+        }
+        let endian = self.endianness;
+        for r in other
+            .relocations
+            .iter()
+            .filter(|r| r.rel_type == RelType::ER4)
+        {
+            let sd = other
+                .object_data
+                .get_mut(&r.rel_seg)
+                .ok_or(LinkError::RelocationOutOfRange)?;
+            let off = r.rel_loc as usize;
+            let val = sd
+                .read_i32(off, endian)
+                .ok_or(LinkError::RelocationOutOfRange)?
+                .checked_add(offset_adjust)
+                .ok_or(LinkError::IntOverflowError)?;
+            sd.write_i32(off, val, endian)
+                .ok_or(LinkError::RelocationOutOfRange)?;
+        }
+
+        let mut moved = BTreeMap::new();
+        for (segment_name, seg) in other.segments.iter() {
+            let start = seg
+                .segment_start
+                .checked_add(offset_adjust)
+                .ok_or(LinkError::IntOverflowError)?;
+            let end = start
+                .checked_add(seg.segment_len)
+                .ok_or(LinkError::IntOverflowError)?;
+            let overlaps = self.segments.values().any(|s| {
+                s.segment_len > 0
+                    && seg.segment_len > 0
+                    && start < s.segment_start + s.segment_len
+                    && s.segment_start < end
+            });
+            if overlaps {
+                return Err(LinkError::OverlappingSegments);
+            }
+            moved.insert(segment_name.clone(), start);
+        }
+
+        // segment relative relocation offsets, for both images
+        let mut self_shift = BTreeMap::new();
+        let mut other_shift = BTreeMap::new();
+        for (segment_name, seg) in other.segments.into_iter() {
+            let start = moved[&segment_name];
+            let data = other
+                .object_data
+                .remove(&segment_name)
+                .unwrap_or_else(|| SegmentData::new(seg.segment_len as usize));
+            match self.segments.get_mut(&segment_name) {
+                None => {
+                    other_shift.insert(segment_name.clone(), 0);
+                    let mut s = seg;
+                    s.segment_start = start;
+                    self.segments.insert(segment_name.clone(), s);
+                    self.object_data.insert(segment_name, data);
+                }
+                Some(self_seg) => {
+                    let merged_start = self_seg.segment_start.min(start);
+                    let merged_end = (self_seg.segment_start + self_seg.segment_len)
+                        .max(start + seg.segment_len);
+                    let mut merged = SegmentData::new((merged_end - merged_start) as usize);
+                    if let Some(self_data) = self.object_data.get(&segment_name) {
+                        merged.write_bytes(
+                            (self_seg.segment_start - merged_start) as usize,
+                            self_data,
+                        );
+                    }
+                    merged.write_bytes((start - merged_start) as usize, &data);
+                    self_shift.insert(segment_name.clone(), self_seg.segment_start - merged_start);
+                    other_shift.insert(segment_name.clone(), start - merged_start);
+                    self_seg.segment_start = merged_start;
+                    self_seg.segment_len = merged_end - merged_start;
+                    self_seg.segment_align = self_seg.segment_align.max(seg.segment_align);
+                    self.object_data.insert(segment_name, merged);
+                }
+            }
+        }
+
+        for r in self.relocations.iter_mut() {
+            r.rel_loc += self_shift.get(&r.rel_seg).copied().unwrap_or(0);
+        }
+        for mut r in other.relocations.into_iter() {
+            r.rel_loc += other_shift.get(&r.rel_seg).copied().unwrap_or(0);
+            self.relocations.push(r);
+        }
+        self.nsegs = self.segments.len() as i32;
+        self.nrels += other.nrels;
+        Ok(())
+    }
+
+    // Wrapped symbols are written out as real_/wrap_ names
+    fn displayed_name(ste: &SymbolTableEntry) -> String {
+        match &ste.st_name {
+            SymbolName::SName(s) | SymbolName::Local(s, _) => s.to_owned(),
+            SymbolName::WrappedSName(s) => match &ste.st_type {
+                SymbolTableEntryType::D => format!("real_{s}"),
+                SymbolTableEntryType::U => format!("wrap_{s}"),
+            },
+        }
+    }
+
+    // How other differs from this image, segment by segment (in output
+    // order): where they are and what is in them. Empty for identical images.
+    pub fn diff(&self, other: &ObjectOut) -> Vec<SegmentDiff> {
+        let mut diffs = vec![];
+        for segment_name in SegmentName::order().iter() {
+            let (old, new) = match (
+                self.segments.get(segment_name),
+                other.segments.get(segment_name),
+            ) {
+                (None, None) => continue,
+                (Some(_), None) => {
+                    diffs.push(SegmentDiff::Removed(segment_name.clone()));
+                    continue;
+                }
+                (None, Some(_)) => {
+                    diffs.push(SegmentDiff::Added(segment_name.clone()));
+                    continue;
+                }
+                (Some(old), Some(new)) => (old, new),
+            };
+            if (old.segment_start, old.segment_len) != (new.segment_start, new.segment_len) {
+                diffs.push(SegmentDiff::Layout {
+                    segment_name: segment_name.clone(),
+                    old: (old.segment_start, old.segment_len),
+                    new: (new.segment_start, new.segment_len),
+                });
+            }
+            let empty = SegmentData::new(0);
+            let old_data = self.object_data.get(segment_name).unwrap_or(&empty);
+            let new_data = other.object_data.get(segment_name).unwrap_or(&empty);
+            for offset in 0..old_data.len().max(new_data.len()) {
+                let old = old_data.get_at(offset, 1).map(|b| b[0]);
+                let new = new_data.get_at(offset, 1).map(|b| b[0]);
+                if old != new {
+                    diffs.push(SegmentDiff::Byte {
+                        segment_name: segment_name.clone(),
+                        offset,
+                        old,
+                        new,
+                    });
+                }
+            }
+        }
+        diffs
+    }
+
+    // Order the symbol table for reproducible output. Address ties (and
+    // Name) go by the name the symbol is printed under.
+    pub fn sort_symbols(&mut self, by: SymbolSort) {
+        match by {
+            SymbolSort::Name => self
+                .symbol_table
+                .sort_by_cached_key(ObjectOut::displayed_name),
+            SymbolSort::Address => self
+                .symbol_table
+                .sort_by_cached_key(|ste| (ste.st_value, ObjectOut::displayed_name(ste))),
+        }
+    }
+
+    // Drop symbol table entries before writing the output out. Segment data
+    // and relocations are left as they are.
+    pub fn strip(&mut self, level: StripLevel) {
+        match level {
+            StripLevel::None => {}
+            StripLevel::Locals => self.symbol_table.retain(|ste| !ste.is_local()),
+            StripLevel::All => self.symbol_table.clear(),
+        }
+        self.nsyms = self.symbol_table.len() as i32;
+    }
+
+    // The image as a minimal static ELF32 executable, one PT_LOAD program
+    // header per (non-empty) segment, at its link address. There are no
+    // section headers and e_machine is EM_NONE: the objects don't say what
+    // they were built for in ELF terms. Text is executable, BSS and common
+    // blocks past the segment data are zero-filled by the loader.
+    #[cfg(feature = "elf")]
+    pub fn write_elf(
+        &self,
+        path: impl AsRef<std::path::Path>,
+        entry: i32,
+        endian: Endianness,
+    ) -> std::io::Result<()> {
+        const EHDR_SIZE: usize = 52;
+        const PHDR_SIZE: usize = 32;
+        const PAGE: usize = 0x1000;
+        let u16_bytes = |v: u16| match endian {
+            Endianness::BigEndian => v.to_be_bytes(),
+            Endianness::LittleEndian => v.to_le_bytes(),
+        };
+        let u32_bytes = |v: u32| match endian {
+            Endianness::BigEndian => v.to_be_bytes(),
+            Endianness::LittleEndian => v.to_le_bytes(),
+        };
+
+        let segs: Vec<&Segment> = SegmentName::order()
+            .iter()
+            .filter_map(|name| self.segments.get(name))
+            .filter(|seg| seg.segment_len > 0)
+            .collect();
+
+        let mut ehdr = vec![0x7F, b'E', b'L', b'F', 1]; // ELFCLASS32
+        ehdr.push(match endian {
+            Endianness::LittleEndian => 1, // ELFDATA2LSB
+            Endianness::BigEndian => 2,    // ELFDATA2MSB
+        });
+        ehdr.push(1); // EV_CURRENT
+        ehdr.resize(16, 0);
+        ehdr.extend(u16_bytes(2)); // ET_EXEC
+        ehdr.extend(u16_bytes(0)); // EM_NONE
+        ehdr.extend(u32_bytes(1)); // EV_CURRENT
+        ehdr.extend(u32_bytes(entry as u32));
+        ehdr.extend(u32_bytes(EHDR_SIZE as u32)); // e_phoff
+        ehdr.extend(u32_bytes(0)); // e_shoff
+        ehdr.extend(u32_bytes(0)); // e_flags
+        ehdr.extend(u16_bytes(EHDR_SIZE as u16));
+        ehdr.extend(u16_bytes(PHDR_SIZE as u16));
+        ehdr.extend(u16_bytes(segs.len() as u16));
+        ehdr.extend(u16_bytes(0)); // e_shentsize
+        ehdr.extend(u16_bytes(0)); // e_shnum
+        ehdr.extend(u16_bytes(0)); // e_shstrndx
+
+        let mut phdrs = vec![];
+        let mut contents = vec![];
+        let mut offset = EHDR_SIZE + PHDR_SIZE * segs.len();
+        for seg in segs {
+            let vaddr = seg.segment_start as usize;
+            let data = self
+                .object_data
+                .get(&seg.segment_name)
+                .filter(|_| seg.segment_descr.contains(&SegmentDescr::P))
+                .map_or(&[][..], |sd| sd.as_slice());
+            // file offset and address must agree modulo the page size
+            offset += (vaddr % PAGE + PAGE - offset % PAGE) % PAGE;
+            let mut flags = 0;
+            if seg.segment_name == SegmentName::TEXT {
+                flags |= 1; // PF_X
+            }
+            if seg.segment_descr.contains(&SegmentDescr::W) {
+                flags |= 2; // PF_W
+            }
+            if seg.segment_descr.contains(&SegmentDescr::R) {
+                flags |= 4; // PF_R
+            }
+            phdrs.extend(u32_bytes(1)); // PT_LOAD
+            phdrs.extend(u32_bytes(offset as u32));
+            phdrs.extend(u32_bytes(vaddr as u32)); // p_vaddr
+            phdrs.extend(u32_bytes(vaddr as u32)); // p_paddr
+            phdrs.extend(u32_bytes(data.len() as u32)); // p_filesz
+            phdrs.extend(u32_bytes(seg.segment_len as u32)); // p_memsz
+            phdrs.extend(u32_bytes(flags));
+            phdrs.extend(u32_bytes(PAGE as u32));
+            contents.push((offset, data));
+            offset += data.len();
         }
+
+        let mut bytes = ehdr;
+        bytes.extend(phdrs);
+        for (offset, data) in contents {
+            bytes.resize(offset, 0);
+            bytes.extend_from_slice(data);
+        }
+        std::fs::write(path, bytes)
     }
 
     pub fn ppr(&self, debug: bool) -> String {
         let mut s = String::new();
         s.push_str(MAGIC_NUMBER);
         s.push('\n');
-        s.push_str(format!("{:X} {:X} {:X}", self.nsegs, self.nsyms, self.nrels).as_str());
+        s.push_str(format!("{:X} {:X} {:X}\n", self.nsegs, self.nsyms, self.nrels).as_str());
         let mut segs = vec![];
         let mut code_and_data = vec![];
         for segment_name in SegmentName::order().iter() {
@@ -70,20 +433,19 @@ impl ObjectOut {
 
         let mut stes = vec![];
         for ste in self.symbol_table.iter() {
-            let name = match &ste.st_name {
-                SymbolName::SName(s) => s.to_owned(),
-                SymbolName::WrappedSName(s) => match &ste.st_type {
-                    SymbolTableEntryType::D => format!("real_{s}"),
-                    SymbolTableEntryType::U => format!("wrap_{s}"),
-                },
-            };
+            let name = ObjectOut::displayed_name(ste);
+            // global is the default and not printed
+            let vis = if ste.is_local() { " L" } else { "" };
+            let size = ste.st_size.map_or(String::new(), |sz| format!(" {sz:X}"));
             stes.push(format!(
-                "{name} {:X} {:X} {}",
+                "{name} {:X} {:X} {}{vis}{size}",
                 ste.st_value, ste.st_seg, ste.st_type
             ))
         }
-        s.push_str(stes.join("\n").as_str());
-        s.push('\n');
+        if !stes.is_empty() {
+            s.push_str(stes.join("\n").as_str());
+            s.push('\n');
+        }
 
         s.push_str(segs.join("\n").as_str());
         s.push('\n');
@@ -91,4 +453,81 @@ impl ObjectOut {
         s.push_str(code_and_data.join("\n").as_str());
         s
     }
+
+    // Relocations left for the loader, as in ObjectIn::relocation_table.
+    // Segment refs count the segments in output order, as ppr writes them.
+    pub fn relocation_table(&self) -> String {
+        let segs: Vec<&SegmentName> = SegmentName::order()
+            .iter()
+            .filter_map(|name| self.segments.get_key_value(name).map(|(k, _)| k))
+            .collect();
+        let rows: Vec<(&Relocation, String)> = self
+            .relocations
+            .iter()
+            .map(|r| (r, r.describe_in(&segs, &self.symbol_table)))
+            .collect();
+        relocation_table(&rows)
+    }
+
+    // Header and segments as in ppr, but then each segment's data as a
+    // hexdump at its output address. For reading, not for parsing back.
+    pub fn ppr_hexdump(&self) -> String {
+        let mut lines = vec![MAGIC_NUMBER.to_owned()];
+        lines.push(format!(
+            "{:X} {:X} {:X}",
+            self.nsegs, self.nsyms, self.nrels
+        ));
+        for segment_name in SegmentName::order().iter() {
+            if let Some(seg) = self.segments.get(segment_name) {
+                lines.push(format!(
+                    "{} {:X} {:X} {}",
+                    segment_name,
+                    seg.segment_start,
+                    seg.segment_len,
+                    seg.ppr_seg_descr()
+                ));
+            }
+        }
+        for segment_name in SegmentName::order().iter() {
+            if let (Some(seg), Some(segment_data)) = (
+                self.segments.get(segment_name),
+                self.object_data.get(segment_name),
+            ) {
+                lines.push(format!("{segment_name}:"));
+                if !segment_data.is_empty() {
+                    lines.push(segment_data.hexdump(seg.segment_start));
+                }
+            }
+        }
+        lines.join("\n")
+    }
+
+    // Same as ppr, followed by '#' comment lines telling which input module
+    // contributed each byte range of every output segment:
+    //   # .text
+    //   #   [10..2E] <- mod_1
+    pub fn ppr_annotated(&self, info: &LinkerInfo) -> String {
+        let mut s = self.ppr(false);
+        for segment_name in SegmentName::order().iter() {
+            if !self.segments.contains_key(segment_name) {
+                continue;
+            }
+            let mut ranges = vec![];
+            for (obj_id, seg_addrs) in info.segment_mapping.iter() {
+                let seg_len = info
+                    .segment_lengths
+                    .get(obj_id)
+                    .and_then(|lens| lens.get(segment_name));
+                if let (Some(start), Some(len)) = (seg_addrs.get(segment_name), seg_len) {
+                    ranges.push((*start, *start + *len, obj_id));
+                }
+            }
+            ranges.sort();
+            s.push_str(format!("\n# {segment_name}").as_str());
+            for (start, end, obj_id) in ranges {
+                s.push_str(format!("\n#   [{start:X}..{end:X}] <- {obj_id}").as_str());
+            }
+        }
+        s
+    }
 }
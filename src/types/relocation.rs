@@ -1,6 +1,7 @@
 use std::fmt;
 
 use crate::types::errors::ParseError;
+use crate::types::object::{segment_by_ordinal, ObjectIn};
 use crate::types::segment::{Segment, SegmentName};
 use crate::types::symbol_table::SymbolTableEntry;
 
@@ -10,15 +11,78 @@ use crate::types::symbol_table::SymbolTableEntry;
 // location is found, ref is the segment or symbol number to be relocated there,
 // and type is an architecture-dependent relocation type. Common types are
 // A4 for a four-byte absolute address, or R4 for a four-byte relative address.
-// Some relocation types may have extra fields after the type. (TODO)
-#[derive(Debug, Clone)]
+// Some relocation types may have extra fields after the type, hex numbers
+// like the rest (see RelType::extra_fields).
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Relocation {
     pub rel_loc: i32, // relocation address
     pub rel_seg: SegmentName,
     pub rel_ref: RelRef,
     pub rel_type: RelType,
+    pub rel_extra: Vec<usize>,
 }
 
+impl Relocation {
+    // GOT slot (by index, not offset) a GP4 relocation is pinned to
+    pub fn got_slot(&self) -> Option<usize> {
+        match (&self.rel_type, self.rel_extra.first()) {
+            (RelType::GP4, Some(slot)) => Some(*slot),
+            _ => None,
+        }
+    }
+
+    // What the relocation refers to, e.g. "symbol 'foo'" or "segment .text",
+    // looked up in the object the relocation was read from
+    pub fn describe(&self, obj: &ObjectIn) -> String {
+        let segs: Vec<&SegmentName> = obj.segments.iter().map(|s| &s.segment_name).collect();
+        self.describe_in(&segs, &obj.symbol_table)
+    }
+
+    // Same as describe, for refs into the given segments and symbols. A ref
+    // that is out of range is shown by its (1-based) number.
+    pub fn describe_in(&self, segs: &[&SegmentName], st: &[SymbolTableEntry]) -> String {
+        match self.rel_ref {
+            RelRef::SegmentRef(seg_i) => match segs.get(seg_i) {
+                Some(name) => format!("segment {name}"),
+                None => format!("segment #{:X}", seg_i + 1),
+            },
+            RelRef::SymbolRef(sym_i) => match st.get(sym_i) {
+                Some(ste) => format!("symbol '{}'", ste.st_name),
+                None => format!("symbol #{:X}", sym_i + 1),
+            },
+            RelRef::NoRef => String::from("none"),
+        }
+    }
+}
+
+// readelf -r style listing, one relocation per row:
+//   Offset    Segment  Reference     Type
+//   00000014  .text    symbol 'foo'  RS4
+pub fn relocation_table(rows: &[(&Relocation, String)]) -> String {
+    let ref_width = rows
+        .iter()
+        .map(|(_, descr)| descr.len())
+        .chain(std::iter::once("Reference".len()))
+        .max()
+        .unwrap();
+    let mut lines = vec![format!(
+        "{:<8}  {:<7}  {:<ref_width$}  Type",
+        "Offset", "Segment", "Reference"
+    )];
+    for (r, descr) in rows.iter() {
+        lines.push(format!(
+            "{:08X}  {:<7}  {descr:<ref_width$}  {}",
+            r.rel_loc,
+            r.rel_seg.to_string(),
+            r.rel_type
+        ));
+    }
+    lines.join("\n")
+}
+
+// In front of the segment refs of U2 and L2, which refer to symbols otherwise
+pub const SEGMENT_REF_MARK: char = 'S';
+
 #[derive(Debug, Eq, PartialEq, Clone)]
 // Note that we store 0-based indexes !!!
 pub enum RelRef {
@@ -38,7 +102,8 @@ impl fmt::Display for RelRef {
     }
 }
 
-// * A4 Absolute reference. The four bytes at loc are an absolute reference to segment ref.
+// * A4 Absolute reference. The four bytes at loc are an absolute reference to segment ref,
+//   with the addend being the value already stored at loc. (The addend is usually zero.)
 // * R4 Relative reference. The four bytes at loc are a relative reference to segment ref.
 //   That is, the bytes at loc contain the difference between the address after loc (loc+4)
 //   and the target address. (This is the x86 relative jump instruction format.)
@@ -47,18 +112,25 @@ impl fmt::Display for RelRef {
 // * RS4 Relative symbol reference. The four bytes at loc are a relative reference to symbol ref,
 //   with the addend being the value already stored at loc. (The addend is usually zero.)
 // * U2 Upper half reference. The two bytes at loc are the most significant two bytes of a
-//   reference to symbol ref.
+//   reference to symbol ref, or to segment ref when written with an S in front (S2).
 // * L2 Lower half reference. The two bytes at loc are the least significant two bytes of a
-//   reference to symbol ref.
+//   reference to symbol ref, or to segment ref when written with an S in front (S2).
 // * GA4: (GOT address) At location loc, store the distance to the GOT.
 // * GP4: (GOT pointer) Put a pointer to symbol ref in the GOT, and at
-//   location loc, store the GOT-relative offset of that pointer.
+//   location loc, store the GOT-relative offset of that pointer. An extra
+//   field pins the pointer to that GOT slot, which relocations pinned to the
+//   same slot must point at the same symbol to share.
+// * GPS4: (GOT pointer to segment) Put the address of segment ref in the GOT, and
+//   at location loc, store the GOT-relative offset of that pointer.
 // * GR4: (GOT relative) Location loc contains an address in segment
 //   ref. Replace that with the offset from the beginning of the GOT to
 //   that address.
 // * ER4: (Executable relative) Location loc contains an address relative to the beginning of
 //   the executable. The ref field is ignored.
-#[derive(Debug, Eq, PartialEq, Clone)]
+// * SEGN: (Segment number) The four bytes at loc are the number of the output segment symbol
+//   ref is defined in, counting from 1 the segments the output has in SegmentName::order().
+//   Absolute symbols are in segment 0.
+#[derive(Debug, Eq, PartialEq, Clone, Ord, PartialOrd)]
 pub enum RelType {
     A4,
     R4,
@@ -68,18 +140,66 @@ pub enum RelType {
     L2,
     GA4,
     GP4,
+    GPS4,
     GR4,
     ER4,
+    SEGN,
 }
 
 impl RelType {
     fn is_segment_rel(&self) -> bool {
-        matches!(self, RelType::A4 | RelType::R4 | RelType::GR4)
+        matches!(
+            self,
+            RelType::A4 | RelType::R4 | RelType::GPS4 | RelType::GR4
+        )
     }
 
     fn is_no_rel(&self) -> bool {
         matches!(self, RelType::GA4 | RelType::ER4)
     }
+
+    // whether the relocation needs a GOT, with or without slots in it
+    pub fn uses_got(&self) -> bool {
+        matches!(
+            self,
+            RelType::GA4 | RelType::GP4 | RelType::GPS4 | RelType::GR4
+        )
+    }
+
+    // how many extra fields may follow the type
+    pub fn extra_fields(&self) -> usize {
+        match self {
+            RelType::GP4 => 1,
+            _ => 0,
+        }
+    }
+
+    // symbol refs by default, segment refs are marked, see U2 and L2
+    pub fn is_segment_or_symbol_rel(&self) -> bool {
+        matches!(self, RelType::U2 | RelType::L2)
+    }
+}
+
+// Symbol relocations that point at common blocks and are likely a mistake.
+// A common block has no address until the link allocates it in BSS, so a
+// relative reference (RS4) to one is suspicious, while absolute ones (AS4,
+// U2, L2, GP4) are fine. Parsing doesn't check this, it is up to the caller.
+pub fn common_block_ref_warnings(
+    relocations: &[Relocation],
+    st: &[SymbolTableEntry],
+) -> Vec<String> {
+    let mut warnings = vec![];
+    for r in relocations.iter() {
+        if let (RelType::RS4, RelRef::SymbolRef(sym_i)) = (&r.rel_type, &r.rel_ref) {
+            if let Some(ste) = st.get(*sym_i).filter(|ste| ste.is_common_block()) {
+                warnings.push(format!(
+                    "{} at {}+0x{:X} refers to common block '{}'",
+                    r.rel_type, r.rel_seg, r.rel_loc, ste.st_name
+                ));
+            }
+        }
+    }
+    warnings
 }
 
 impl fmt::Display for RelType {
@@ -93,8 +213,10 @@ impl fmt::Display for RelType {
             RelType::L2 => "L2".to_string(),
             RelType::GA4 => "GA4".to_string(),
             RelType::GP4 => "GP4".to_string(),
+            RelType::GPS4 => "GPS4".to_string(),
             RelType::GR4 => "GR4".to_string(),
             RelType::ER4 => "ER4".to_string(),
+            RelType::SEGN => "SEGN".to_string(),
         };
         write!(f, "{rel_type_str}")
     }
@@ -109,17 +231,18 @@ pub fn parse_relocation(
     let rel_seg;
     let rel_ref;
     let rel_type;
+    let mut rel_extra = vec![];
 
     let vs: Vec<&str> = s.split_ascii_whitespace().collect();
     match vs.as_slice() {
-        [loc, seg, _ref, ty] => {
+        [loc, seg, _ref, ty, extra @ ..] => {
             match i32::from_str_radix(loc, 16) {
                 Err(_) => return Err(ParseError::InvalidRelRef),
                 Ok(i) => rel_loc = i,
             }
             match i32::from_str_radix(seg, 16) {
                 Err(_) => return Err(ParseError::InvalidRelSegment),
-                Ok(i) => match segs.get((i - 1) as usize) {
+                Ok(i) => match segment_by_ordinal(segs, i) {
                     None => return Err(ParseError::RelSegmentOutOfRange),
                     Some(s) => rel_seg = s.segment_name.clone(),
                 },
@@ -133,14 +256,29 @@ pub fn parse_relocation(
                 "L2" => RelType::L2,
                 "GA4" => RelType::GA4,
                 "GP4" => RelType::GP4,
+                "GPS4" => RelType::GPS4,
                 "GR4" => RelType::GR4,
                 "ER4" => RelType::ER4,
+                "SEGN" => RelType::SEGN,
                 _ => return Err(ParseError::InvalidRelType),
             };
+            if extra.len() > rel_type.extra_fields() {
+                return Err(ParseError::InvalidRelocationEntry);
+            }
+            for field in extra {
+                match usize::from_str_radix(field, 16) {
+                    Err(_) => return Err(ParseError::InvalidRelocationEntry),
+                    Ok(i) => rel_extra.push(i),
+                }
+            }
+            let (_ref, marked_seg_ref) = match _ref.strip_prefix(SEGMENT_REF_MARK) {
+                Some(i) if rel_type.is_segment_or_symbol_rel() => (i, true),
+                _ => (*_ref, false),
+            };
             match usize::from_str_radix(_ref, 16) {
                 Err(_) => return Err(ParseError::InvalidRelRef),
                 Ok(i) => {
-                    if rel_type.is_segment_rel() {
+                    if rel_type.is_segment_rel() || marked_seg_ref {
                         match segs.get(i - 1) {
                             None => return Err(ParseError::RelSegmentOutOfRange),
                             Some(_) => rel_ref = RelRef::SegmentRef(i - 1),
@@ -164,5 +302,6 @@ pub fn parse_relocation(
         rel_seg,
         rel_ref,
         rel_type,
+        rel_extra,
     })
 }
@@ -1,6 +1,12 @@
-use crate::types::errors::ParseError;
+use crate::types::errors::{LinkError, ParseError};
+use crate::utils::crc32;
+use std::borrow::Cow;
 use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufWriter, Read, Seek, SeekFrom, Write};
 use std::ops::Deref;
+use std::path::Path;
+use std::str::FromStr;
 
 // Each segment definition contains the
 // segment name, the address where the segment logically starts, the length
@@ -11,14 +17,21 @@ use std::ops::Deref;
 //   .text 1000 2500 RP
 //   .data 4000 C00 RWP
 //   .bss 5000 1900 RW
+// In version 2 objects ("LINK 2") only segments with P have a data line;
+// the others (like .bss above) are zero-filled. Version 1 objects (a bare
+// "LINK") have a data line for every segment.
 // Segments are numbered in the order their definitions appear, with the first
-// segment being number 1.
-#[derive(Debug, Clone)]
+// segment being number 1. An optional fifth field gives the (power of two)
+// alignment this module's part of the segment needs in the output:
+//   .data 4000 C00 RWP 10
+
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Segment {
     pub segment_name: SegmentName,
     pub segment_start: i32,
     pub segment_len: i32,                 // bytes
     pub segment_descr: Vec<SegmentDescr>, // TODO: ensure uniqueness when parsing
+    pub segment_align: i32,               // 0 if not specified
 }
 
 impl Segment {
@@ -28,6 +41,7 @@ impl Segment {
             segment_start: 0,
             segment_len: 0,
             segment_descr: vec![],
+            segment_align: 0,
         }
     }
 
@@ -47,6 +61,7 @@ impl Segment {
 #[derive(Debug, Eq, PartialEq, Hash, Clone, Ord, PartialOrd)]
 pub enum SegmentName {
     TEXT,
+    RODATA, // read-only data, see LinkerEditor::set_split_segment_permissions
     GOT,
     DATA,
     BSS,
@@ -57,6 +72,7 @@ impl SegmentName {
     pub fn order() -> Vec<SegmentName> {
         vec![
             SegmentName::TEXT,
+            SegmentName::RODATA,
             SegmentName::DATA,
             SegmentName::BSS,
             SegmentName::GOT,
@@ -69,6 +85,7 @@ impl fmt::Display for SegmentName {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         let segment_name_str = match self {
             SegmentName::TEXT => ".text",
+            SegmentName::RODATA => ".rodata",
             SegmentName::GOT => ".got",
             SegmentName::DATA => ".data",
             SegmentName::BSS => ".bss",
@@ -78,6 +95,23 @@ impl fmt::Display for SegmentName {
     }
 }
 
+// Inverse of Display
+impl FromStr for SegmentName {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            ".text" => Ok(SegmentName::TEXT),
+            ".rodata" => Ok(SegmentName::RODATA),
+            ".got" => Ok(SegmentName::GOT),
+            ".data" => Ok(SegmentName::DATA),
+            ".bss" => Ok(SegmentName::BSS),
+            ".lib" => Ok(SegmentName::LIB),
+            _ => Err(ParseError::InvalidSegmentName),
+        }
+    }
+}
+
 #[derive(Debug, Eq, PartialEq, Clone)]
 pub enum SegmentDescr {
     R, // readable
@@ -85,7 +119,62 @@ pub enum SegmentDescr {
     P, // present in the object file
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Endianness {
+    BigEndian,
+    LittleEndian,
+}
+
+// How much of a segment is decoded or moved at a time when it is not all
+// in memory, see parse_segment_data_into and FileSegmentData
+const SEGMENT_WINDOW: usize = 64 * 1024;
+
+// Access to the bytes of a segment, wherever they are kept: SegmentData
+// holds them in memory, FileSegmentData in a file. Parsing and relocation
+// only read and patch small windows, through this.
+pub trait SegmentBytes {
+    fn len(&self) -> usize;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // The len bytes at start. None if they are out of bounds.
+    fn get_at(&self, start: usize, len: usize) -> Option<Cow<'_, [u8]>>;
+
+    // Replace len bytes at start with patch, which need not be as long: the
+    // data grows or shrinks by the difference.
+    fn update(&mut self, start: usize, len: usize, patch: Vec<u8>) -> Result<(), LinkError>;
+
+    // Read the four bytes at off as an i32. None if they are out of bounds.
+    fn read_i32(&self, off: usize, endian: Endianness) -> Option<i32> {
+        let bytes: [u8; 4] = self.get_at(off, 4)?.as_ref().try_into().ok()?;
+        match endian {
+            Endianness::BigEndian => Some(i32::from_be_bytes(bytes)),
+            Endianness::LittleEndian => Some(i32::from_le_bytes(bytes)),
+        }
+    }
+
+    // Store val in the four bytes at off. None (and no change) if out of bounds.
+    fn write_i32(&mut self, off: usize, val: i32, endian: Endianness) -> Option<()> {
+        let bytes = match endian {
+            Endianness::BigEndian => val.to_be_bytes(),
+            Endianness::LittleEndian => val.to_le_bytes(),
+        };
+        self.update(off, bytes.len(), bytes.to_vec()).ok()
+    }
+
+    // Store val in the two bytes at off. None (and no change) if out of bounds.
+    fn write_u16(&mut self, off: usize, val: u16, endian: Endianness) -> Option<()> {
+        let bytes = match endian {
+            Endianness::BigEndian => val.to_be_bytes(),
+            Endianness::LittleEndian => val.to_le_bytes(),
+        };
+        self.update(off, bytes.len(), bytes.to_vec()).ok()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct SegmentData(Vec<u8>);
 impl Deref for SegmentData {
     type Target = Vec<u8>;
@@ -113,26 +202,210 @@ impl SegmentData {
         self.0.is_empty()
     }
 
-    pub fn update(&mut self, start: usize, len: usize, patch: Vec<u8>) {
-        let end = start + len;
-        let data_len = self.len();
-        if end > data_len {
-            panic!("Index out of bounds: end {end} is greater than data length {data_len}");
+    // Overwrite bytes starting at off. None (and no change) if out of bounds.
+    pub fn write_bytes(&mut self, off: usize, bytes: &[u8]) -> Option<()> {
+        let end = off.checked_add(bytes.len())?;
+        self.0.get_mut(off..end)?.copy_from_slice(bytes);
+        Some(())
+    }
+
+    // Set len bytes starting at start to byte
+    pub fn fill(&mut self, start: usize, len: usize, byte: u8) -> Result<(), LinkError> {
+        self.range(start, len)?.fill(byte);
+        Ok(())
+    }
+
+    // Replace remove_len bytes at start with insert, which need not be as
+    // long: the data grows or shrinks by the difference.
+    pub fn splice(
+        &mut self,
+        start: usize,
+        remove_len: usize,
+        insert: &[u8],
+    ) -> Result<(), LinkError> {
+        self.range(start, remove_len)?;
+        self.0
+            .splice(start..start + remove_len, insert.iter().copied());
+        Ok(())
+    }
+
+    fn range(&mut self, start: usize, len: usize) -> Result<&mut [u8], LinkError> {
+        start
+            .checked_add(len)
+            .and_then(|end| self.0.get_mut(start..end))
+            .ok_or(LinkError::SegmentDataOutOfRange { start, len })
+    }
+
+    // CRC-32 (IEEE, as in zlib and cksum -a crc32b) of the contents, to tell
+    // whether two links produced the same segment
+    pub fn crc32(&self) -> u32 {
+        crc32(&self.0)
+    }
+
+    // xxd style dump, 16 bytes per line, addresses starting at base_addr:
+    //   00000010: DEAD BEEF 4865 6C6C 6F00 0000 0000 0000  ....Hello.......
+    pub fn hexdump(&self, base_addr: i32) -> String {
+        let mut lines = vec![];
+        for (i, chunk) in self.0.chunks(16).enumerate() {
+            let mut hex = String::new();
+            for (j, b) in chunk.iter().enumerate() {
+                if j > 0 && j % 2 == 0 {
+                    hex.push(' ');
+                }
+                hex.push_str(&format!("{b:02X}"));
+            }
+            let ascii: String = chunk
+                .iter()
+                .map(|&b| {
+                    if b.is_ascii_graphic() || b == b' ' {
+                        b as char
+                    } else {
+                        '.'
+                    }
+                })
+                .collect();
+            let addr = base_addr as i64 + (i * 16) as i64;
+            // 16 bytes take 39 columns
+            lines.push(format!("{addr:08X}: {hex:<39}  {ascii}"));
+        }
+        lines.join("\n")
+    }
+}
+
+impl SegmentBytes for SegmentData {
+    fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    fn get_at(&self, start: usize, len: usize) -> Option<Cow<'_, [u8]>> {
+        let end = start.checked_add(len)?;
+        self.0.get(start..end).map(Cow::Borrowed)
+    }
+
+    fn update(&mut self, start: usize, len: usize, patch: Vec<u8>) -> Result<(), LinkError> {
+        self.splice(start, len, &patch)
+    }
+}
+
+// Segment data kept in a file rather than in memory, for segments too big
+// to hold in a Vec. Only the windows asked for are read or written, which is
+// all relocation needs; get_at returns a copy of the window. Opt-in: fill
+// one with parse_segment_data_into or from_hex.
+#[derive(Debug)]
+pub struct FileSegmentData {
+    file: File,
+    len: usize,
+}
+
+impl FileSegmentData {
+    // Zero-filled segment of len bytes, backed by the file at path
+    pub fn create(path: &Path, len: usize) -> io::Result<Self> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)?;
+        file.set_len(len as u64)?;
+        Ok(FileSegmentData { file, len })
+    }
+
+    // Stream one hex data line (as in an object file) into the file at path
+    // without holding the decoded bytes in memory. Stops at newline or EOF.
+    pub fn from_hex(path: &Path, seg_len: usize, hex: impl BufRead) -> Result<Self, ParseError> {
+        let io_err = |e: io::Error| ParseError::IOError(e.kind());
+        let mut sd = FileSegmentData::create(path, 0).map_err(io_err)?;
+        let mut out = BufWriter::new(&sd.file);
+        let mut token = String::new();
+        let mut n = 0;
+        for b in hex.bytes() {
+            let b = b.map_err(io_err)?;
+            if !b.is_ascii_whitespace() {
+                token.push(b as char);
+                continue;
+            }
+            if !token.is_empty() {
+                let byte =
+                    u8::from_str_radix(&token, 16).map_err(|_| ParseError::InvalidObjectData)?;
+                out.write_all(&[byte]).map_err(io_err)?;
+                n += 1;
+                token.clear();
+            }
+            if b == b'\n' {
+                break;
+            }
+        }
+        if !token.is_empty() {
+            let byte = u8::from_str_radix(&token, 16).map_err(|_| ParseError::InvalidObjectData)?;
+            out.write_all(&[byte]).map_err(io_err)?;
+            n += 1;
+        }
+        out.flush().map_err(io_err)?;
+        drop(out);
+        if n != seg_len {
+            return Err(ParseError::SegmentDataLengthMismatch);
+        }
+        sd.len = n;
+        Ok(sd)
+    }
+
+    fn read_at(&self, off: usize, buf: &mut [u8]) -> io::Result<()> {
+        let mut file = &self.file;
+        file.seek(SeekFrom::Start(off as u64))?;
+        file.read_exact(buf)
+    }
+
+    fn write_at(&mut self, off: usize, bytes: &[u8]) -> io::Result<()> {
+        self.file.seek(SeekFrom::Start(off as u64))?;
+        self.file.write_all(bytes)
+    }
+
+    // Move the len bytes at from to to, a window at a time. Back to front
+    // when moving up, so nothing is overwritten before it is read.
+    fn move_bytes(&mut self, from: usize, to: usize, len: usize) -> io::Result<()> {
+        let mut buf = vec![0; len.min(SEGMENT_WINDOW)];
+        let mut done = 0;
+        while done < len {
+            let n = buf.len().min(len - done);
+            let off = if to > from { len - done - n } else { done };
+            self.read_at(from + off, &mut buf[..n])?;
+            self.write_at(to + off, &buf[..n])?;
+            done += n;
         }
-        let mut new_data = Vec::with_capacity(data_len + patch.len());
-        new_data.extend_from_slice(&self.0[0..start]);
-        new_data.extend_from_slice(&patch);
-        new_data.extend_from_slice(&self.0[end..data_len]);
+        Ok(())
+    }
+}
 
-        self.0 = new_data;
+impl SegmentBytes for FileSegmentData {
+    fn len(&self) -> usize {
+        self.len
     }
 
-    pub fn get_at(&self, start: usize, len: usize) -> Option<&[u8]> {
-        let end = start + len;
-        if end > self.0.len() {
+    fn get_at(&self, start: usize, len: usize) -> Option<Cow<'_, [u8]>> {
+        let end = start.checked_add(len)?;
+        if end > self.len {
             return None;
         }
-        Some(&self.0[start..end])
+        let mut buf = vec![0; len];
+        self.read_at(start, &mut buf).ok()?;
+        Some(Cow::Owned(buf))
+    }
+
+    fn update(&mut self, start: usize, len: usize, patch: Vec<u8>) -> Result<(), LinkError> {
+        let end = start
+            .checked_add(len)
+            .filter(|&end| end <= self.len)
+            .ok_or(LinkError::SegmentDataOutOfRange { start, len })?;
+        let io_err = |e: io::Error| LinkError::IOError(e.kind());
+        if patch.len() != len {
+            let new_end = start + patch.len();
+            let new_len = new_end + (self.len - end);
+            self.move_bytes(end, new_end, self.len - end)
+                .map_err(io_err)?;
+            self.file.set_len(new_len as u64).map_err(io_err)?;
+            self.len = new_len;
+        }
+        self.write_at(start, &patch).map_err(io_err)
     }
 }
 
@@ -141,24 +414,24 @@ pub fn parse_segment(s: &str) -> Result<Segment, ParseError> {
     let segment_start;
     let segment_len;
     let segment_descr;
+    let mut segment_align = 0;
     let vs: Vec<&str> = s.split_ascii_whitespace().collect();
     match vs.as_slice() {
-        [name, start, len, descr] => {
-            match *name {
-                ".text" => segment_name = SegmentName::TEXT,
-                ".data" => segment_name = SegmentName::DATA,
-                ".bss" => segment_name = SegmentName::BSS,
-                ".got" => segment_name = SegmentName::GOT,
-                ".lib" => segment_name = SegmentName::LIB,
-                _ => return Err(ParseError::InvalidSegmentName),
-            }
-            match i32::from_str_radix(start, 16) {
+        [name, start, len, descr, align @ ..] if align.len() <= 1 => {
+            segment_name = name.parse::<SegmentName>()?;
+            // addresses are unsigned, but have to fit the i32 address model
+            match u32::from_str_radix(start, 16) {
                 Err(_) => return Err(ParseError::InvalidSegmentStart),
-                Ok(i) => segment_start = i,
+                Ok(u) => {
+                    segment_start =
+                        i32::try_from(u).map_err(|_| ParseError::SegmentStartOutOfRange)?
+                }
             }
-            match i32::from_str_radix(len, 16) {
+            match u32::from_str_radix(len, 16) {
                 Err(_) => return Err(ParseError::InvalidSegmentLen),
-                Ok(i) => segment_len = i,
+                Ok(u) => {
+                    segment_len = i32::try_from(u).map_err(|_| ParseError::SegmentLenOutOfRange)?
+                }
             }
             let mut descrs: Vec<SegmentDescr> = vec![];
             for c in descr.chars() {
@@ -168,6 +441,12 @@ pub fn parse_segment(s: &str) -> Result<Segment, ParseError> {
                 }
             }
             segment_descr = descrs;
+            if let [align] = align {
+                match i32::from_str_radix(align, 16) {
+                    Ok(i) if i > 0 && i.count_ones() == 1 => segment_align = i,
+                    _ => return Err(ParseError::InvalidSegmentAlign),
+                }
+            }
         }
         _otherwise => return Err(ParseError::InvalidSegment),
     }
@@ -177,6 +456,7 @@ pub fn parse_segment(s: &str) -> Result<Segment, ParseError> {
         segment_start,
         segment_len,
         segment_descr,
+        segment_align,
     })
 }
 
@@ -189,14 +469,67 @@ fn segment_descr_from_chr(c: char) -> Result<SegmentDescr, ParseError> {
     }
 }
 
+// Data is either space separated bytes ("DE AD BE EF") or one long hex
+// string ("DEADBEEF"). A lone 1-2 digit token reads the same either way.
 pub fn parse_segment_data(seg_len: usize, s: &str) -> Result<SegmentData, ParseError> {
-    let x: Vec<u8> = s
-        .split_whitespace()
-        .map(|s| u8::from_str_radix(s, 16).unwrap())
-        .collect();
-    if x.len() != seg_len {
-        Err(ParseError::SegmentDataLengthMismatch)
-    } else {
-        Ok(SegmentData(x))
+    let mut sd = SegmentData::new(seg_len);
+    parse_segment_data_into(&mut sd, s)?;
+    Ok(sd)
+}
+
+// Same as parse_segment_data, decoded into sd (which must be exactly as long
+// as the data) a window at a time, so a FileSegmentData never has all of it
+// in memory
+pub fn parse_segment_data_into(sd: &mut impl SegmentBytes, s: &str) -> Result<(), ParseError> {
+    // a line of one long token is the contiguous form, otherwise every
+    // token is a byte of at most 2 hex digits
+    let mut words = s.split_whitespace();
+    let tokens: Box<dyn Iterator<Item = &str>> = match (words.next(), words.next()) {
+        (Some(w), None) if w.len() > 2 => {
+            if !w.len().is_multiple_of(2) || !w.is_ascii() {
+                return Err(ParseError::InvalidObjectData);
+            }
+            Box::new((0..w.len()).step_by(2).map(move |i| &w[i..i + 2]))
+        }
+        _ => Box::new(s.split_whitespace()),
+    };
+    let seg_len = sd.len();
+    let mut window = Vec::with_capacity(SEGMENT_WINDOW.min(seg_len));
+    let mut written = 0;
+    let mut n = 0;
+    for token in tokens {
+        if token.len() > 2 {
+            return Err(ParseError::InvalidObjectData);
+        }
+        let byte = u8::from_str_radix(token, 16).map_err(|_| ParseError::InvalidObjectData)?;
+        n += 1;
+        // too long, but bad hex further on is reported first
+        if n > seg_len {
+            continue;
+        }
+        window.push(byte);
+        if window.len() == SEGMENT_WINDOW {
+            written += write_window(sd, written, &mut window)?;
+        }
+    }
+    if n != seg_len {
+        return Err(ParseError::SegmentDataLengthMismatch);
     }
+    write_window(sd, written, &mut window)?;
+    Ok(())
+}
+
+// Store the window at off and empty it, returning how many bytes it had
+fn write_window(
+    sd: &mut impl SegmentBytes,
+    off: usize,
+    window: &mut Vec<u8>,
+) -> Result<usize, ParseError> {
+    let n = window.len();
+    let bytes = std::mem::replace(window, Vec::with_capacity(n));
+    sd.update(off, n, bytes).map_err(|e| match e {
+        LinkError::IOError(kind) => ParseError::IOError(kind),
+        _ => ParseError::SegmentDataLengthMismatch,
+    })?;
+    Ok(n)
 }
@@ -14,7 +14,7 @@ use std::{
     path::{Path, PathBuf},
 };
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq, Eq)]
 pub struct StubMember {
     pub libname: LibName,
     pub name: StubMemberName,
@@ -47,7 +47,7 @@ impl StubMember {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq, Eq)]
 pub struct StubLib {
     pub libname: LibName,
     pub members: BTreeMap<StubMemberName, StubMember>,
@@ -73,10 +73,12 @@ impl StubLib {
         let mut defs = BTreeMap::new();
         let mut deps = Vec::new();
 
-        let entries = fs::read_dir(libpath)
+        let mut entries = fs::read_dir(libpath)
             .unwrap()
             .filter_map(|entry| entry.ok())
             .collect::<Vec<_>>();
+        // LIBRARY NAME goes first: members are tagged with the library name
+        entries.sort_by_key(|entry| entry.file_name() != LIB_NAME_FILE);
         for entry in entries {
             let path = entry.path();
             if path.is_file() {
@@ -90,17 +92,19 @@ impl StubLib {
                     .eq(MAP_FILE_NAME)
                 {
                     println!("reading MAP file");
-                    for l in file_contents.lines() {
-                        let toks: Vec<String> = l.split(' ').map(|s| s.trim().to_owned()).collect();
+                    // first line is the MAP header
+                    for l in file_contents.lines().skip(1) {
+                        let toks: Vec<&str> = l.split_whitespace().collect();
                         match toks.as_slice() {
                             [mod_name, syms @ ..] => {
                                 let mod_symbols = syms
                                     .iter()
-                                    .map(|s| SymbolName::SName(s.to_owned()))
+                                    .map(|s| SymbolName::SName(s.to_string()))
                                     .collect();
                                 defs.insert(mod_name.to_string(), mod_symbols);
                             }
-                            _ => panic!("StubLib::parse: empty MAP entry"),
+                            // blank line
+                            [] => continue,
                         }
                     }
                 } else if path
@@ -198,7 +202,7 @@ impl StubLib {
             Ok(_) => (),
             Err(e) => {
                 if e.kind() != std::io::ErrorKind::AlreadyExists {
-                    panic!("Error creating stub lib: {e}");
+                    return Err(e.into());
                 } else {
                     panic!("stub lib at {basepath:?} already exists, deal with it first!");
                 }
@@ -216,14 +220,13 @@ impl StubLib {
         Ok(())
     }
 
+    // MAP lists the symbols each member defines (see `defs`)
     fn make_map_file(&self) -> String {
         let mut map_file = vec![MAP_FILE_NAME.to_owned()];
-        for (modname, member) in self.members.iter() {
+        for (modname, syms) in self.defs.iter() {
             let mut entry = vec![modname.to_owned()];
-            for (k, sym) in member.syms.iter() {
-                if sym.is_left() {
-                    entry.push(k.to_string());
-                }
+            for sym in syms.iter() {
+                entry.push(sym.to_string());
             }
             map_file.push(entry.join(" "));
         }
@@ -231,7 +234,7 @@ impl StubLib {
     }
 
     pub fn find_defn_addr(&self, sym: &SymbolName) -> Option<Address> {
-        match self.members.values().find(|&m| m.syms.get(sym).is_some()) {
+        match self.members.values().find(|&m| m.syms.contains_key(sym)) {
             Some(member) => match member.syms.get(sym) {
                 Some(Either::Left(addr)) => Some(*addr),
                 _ => None,
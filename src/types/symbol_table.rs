@@ -1,11 +1,15 @@
 use std::{fmt, ops::Deref};
 
+use crate::common::ObjectID;
 use crate::types::errors::ParseError;
 
 #[derive(Eq, Ord, PartialEq, PartialOrd, Clone, Hash, Debug)]
 pub enum SymbolName {
     SName(String),
     WrappedSName(String),
+    // a local symbol in the global symbol table, namespaced by the module
+    // defining it: locals of the same name in different modules don't clash
+    Local(String, ObjectID),
 }
 
 impl fmt::Display for SymbolName {
@@ -13,6 +17,7 @@ impl fmt::Display for SymbolName {
         match self {
             SymbolName::SName(s) => write!(f, "{s}"),
             SymbolName::WrappedSName(s) => write!(f, "wrap_{s}"),
+            SymbolName::Local(s, _) => write!(f, "{s}"),
         }
     }
 }
@@ -24,10 +29,25 @@ impl Deref for SymbolName {
         match self {
             SymbolName::SName(s) => s,
             SymbolName::WrappedSName(s) => s,
+            SymbolName::Local(s, _) => s,
         }
     }
 }
 
+// wrap_foo (as WrappedSName) and foo are different symbols, so == tells them
+// apart, but both are spelled around the same base name foo. Whether a name
+// is a wrapped or real version of another is answered by same_base; compare
+// with == everywhere else.
+impl SymbolName {
+    pub fn base(&self) -> &str {
+        self.deref()
+    }
+
+    pub fn same_base(&self, other: &SymbolName) -> bool {
+        self.base() == other.base()
+    }
+}
+
 #[macro_export]
 macro_rules! symbol {
     ($name:expr) => {
@@ -48,8 +68,10 @@ macro_rules! wrapped_symbol {
 // Seg is the segment number relative to which the symbol is defined, or 0
 // for absolute or undefined symbols. The type is a string of letters including
 // D for defined or U for undefined. Symbols are also numbered in the order
-// they are listed, starting at 1.
-#[derive(Debug, Clone)]
+// they are listed, starting at 1. A defined symbol may be followed by its
+// visibility: G for global (the default) or L for local to the module. Last
+// comes the size of the symbol in hex, if known.
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct SymbolTableEntry {
     pub st_name: SymbolName,
     pub st_value: i32, // for local defined symbols - segment offset
@@ -57,6 +79,8 @@ pub struct SymbolTableEntry {
     // for global undefined symbols - always zero
     pub st_seg: i32,
     pub st_type: SymbolTableEntryType,
+    pub st_vis: SymbolVisibility,
+    pub st_size: Option<i32>,
 }
 
 impl SymbolTableEntry {
@@ -70,6 +94,10 @@ impl SymbolTableEntry {
     pub fn is_defined(&self) -> bool {
         self.st_type == SymbolTableEntryType::D
     }
+
+    pub fn is_local(&self) -> bool {
+        self.st_vis == SymbolVisibility::L
+    }
 }
 
 #[derive(Debug, Eq, PartialEq, Clone)]
@@ -88,15 +116,33 @@ impl fmt::Display for SymbolTableEntryType {
     }
 }
 
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub enum SymbolVisibility {
+    G, // global
+    L, // local, never enters the global symbol table
+}
+
+impl fmt::Display for SymbolVisibility {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let visibility_str = match self {
+            SymbolVisibility::G => "G",
+            SymbolVisibility::L => "L",
+        };
+        write!(f, "{visibility_str}")
+    }
+}
+
 pub fn parse_symbol_table_entry(nsegs: i32, s: &str) -> Result<SymbolTableEntry, ParseError> {
     let st_name;
     let st_value;
     let st_seg;
     let st_type;
+    let mut st_vis = SymbolVisibility::G;
+    let mut st_size = None;
 
     let vs: Vec<&str> = s.split_ascii_whitespace().collect();
     match vs.as_slice() {
-        [name, value, seg, ty] => {
+        [name, value, seg, ty, rest @ ..] if rest.len() <= 2 => {
             st_name = SymbolName::SName(String::from(*name));
             match i32::from_str_radix(value, 16) {
                 Err(_) => return Err(ParseError::InvalidSTEValue),
@@ -116,6 +162,25 @@ pub fn parse_symbol_table_entry(nsegs: i32, s: &str) -> Result<SymbolTableEntry,
                 "U" => st_type = SymbolTableEntryType::U,
                 _ => return Err(ParseError::InvalidSTEType),
             }
+            // G and L are not hex, so a lone token tells which one it is
+            let (vis, size) = match rest {
+                [vis @ ("G" | "L")] => (Some(*vis), None),
+                [size] => (None, Some(*size)),
+                [vis, size] => (Some(*vis), Some(*size)),
+                _ => (None, None),
+            };
+            match vis {
+                None | Some("G") => (),
+                // only definitions can be local
+                Some("L") if st_type == SymbolTableEntryType::D => st_vis = SymbolVisibility::L,
+                _ => return Err(ParseError::InvalidSTEVisibility),
+            }
+            if let Some(size) = size {
+                match i32::from_str_radix(size, 16) {
+                    Ok(i) if i >= 0 => st_size = Some(i),
+                    _ => return Err(ParseError::InvalidSTESize),
+                }
+            }
         }
         _otherwise => return Err(ParseError::InvalidSymbolTableEntry),
     }
@@ -125,5 +190,7 @@ pub fn parse_symbol_table_entry(nsegs: i32, s: &str) -> Result<SymbolTableEntry,
         st_value,
         st_seg,
         st_type,
+        st_vis,
+        st_size,
     })
 }
@@ -1,7 +1,41 @@
 use std::fs;
 
+use crate::types::errors::ParseError;
+
 pub fn read_object_file(file_path: &str) -> String {
-    fs::read_to_string(file_path).expect("Failed to read object file")
+    try_read_object_file(file_path).expect("Failed to read object file")
+}
+
+#[cfg(not(feature = "gzip"))]
+pub fn try_read_object_file(file_path: &str) -> Result<String, ParseError> {
+    fs::read_to_string(file_path).map_err(|_| ParseError::FileReadError(file_path.to_owned()))
+}
+
+// gzip compressed objects (e.g. mod_1.gz) are decompressed first
+#[cfg(feature = "gzip")]
+pub fn try_read_object_file(file_path: &str) -> Result<String, ParseError> {
+    use crate::gzip::{gunzip, is_gzip};
+    let read_error = || ParseError::FileReadError(file_path.to_owned());
+    let bytes = fs::read(file_path).map_err(|_| read_error())?;
+    let bytes = if is_gzip(&bytes) {
+        gunzip(&bytes)?
+    } else {
+        bytes
+    };
+    String::from_utf8(bytes).map_err(|_| read_error())
+}
+
+// CRC-32 (IEEE, as in zlib and cksum -a crc32b)
+pub fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for b in bytes.iter() {
+        crc ^= *b as u32;
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xEDB8_8320 & mask);
+        }
+    }
+    !crc
 }
 
 pub fn find_seg_start(i: i32, n: i32) -> i32 {
@@ -1,21 +1,46 @@
+use either::Either::{Left, Right};
+use std::cell::RefCell;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 use std::fs;
 use std::ops::Deref;
 use std::path::PathBuf;
+use std::rc::Rc;
+use std::time::Instant;
 // use linkerloader::gen::gen_obj_data;
-use linkerloader::lib::{parse_object, read_lib, read_objects, read_objects_from_dir};
-use linkerloader::librarian::Librarian;
-use linkerloader::linker::editor::LinkerEditor;
-use linkerloader::types::errors::{LinkError, ParseError};
+use linkerloader::common::DefnProvenance;
+#[cfg(feature = "archive")]
+use linkerloader::lib::read_objects_from_archive;
+use linkerloader::lib::{
+    link_directory, link_from_sources, parse_object, read_lib, read_objects, read_objects_from_dir,
+    read_objects_from_dir_filtered, LinkConfig,
+};
+use linkerloader::librarian::{out_file_name, Librarian, OutputFormat};
+use linkerloader::linker::editor::{
+    GotMode, GotPlacement, LibSearch, LinkPhase, LinkerEditor, LinkerInfo, SymbolResolution,
+    UndefinedPolicy,
+};
+use linkerloader::linker::memory::{MemoryRegion, MemoryRegions};
+use linkerloader::linker::script::LinkScript;
+use linkerloader::loader::load;
+use linkerloader::logger::{LogLevel, Logger};
+use linkerloader::types::errors::{LibError, LinkError, ParseError};
 use linkerloader::types::library::StaticLib;
-use linkerloader::types::object::MAGIC_NUMBER;
-use linkerloader::types::relocation::{RelRef, RelType, Relocation};
-use linkerloader::types::segment::{SegmentDescr, SegmentName};
-use linkerloader::types::stub::StubLib;
-use linkerloader::types::symbol_table::{SymbolName, SymbolTableEntry, SymbolTableEntryType};
+use linkerloader::types::object::{
+    parse_object_file, parse_object_file_with_magic, ObjectIn, MAGIC_NUMBER,
+};
+use linkerloader::types::out::{ObjectOut, SegmentDiff, StripLevel, SymbolSort};
+use linkerloader::types::relocation::{common_block_ref_warnings, RelRef, RelType, Relocation};
+use linkerloader::types::segment::{
+    Endianness, Segment, SegmentBytes, SegmentData, SegmentDescr, SegmentName,
+};
+use linkerloader::types::stub::{StubLib, StubMember};
+use linkerloader::types::symbol_table::{
+    SymbolName, SymbolTableEntry, SymbolTableEntryType, SymbolVisibility,
+};
 use linkerloader::utils::{read_object_file, x_to_i2, x_to_i4};
 use linkerloader::{symbol, wrapped_symbol};
 
-const TESTS_DIR: &'static str = "tests/input/";
+const TESTS_DIR: &str = "tests/input/";
 const NO_STATIC_LIBS: Vec<StaticLib> = vec![];
 const NO_WRAP_ROUTINES: Vec<SymbolName> = vec![];
 
@@ -78,7 +103,7 @@ fn test_failure(e0: ParseError, fp: &str) {
         Ok(_) => {
             panic!("unexpected");
         }
-        Err(e) => assert_eq!(e0, e),
+        Err(e) => assert_eq!(&e0, e.kind()),
     }
 }
 
@@ -87,35 +112,11 @@ fn multi_object_test(dirname: &str) {
     let mut editor = LinkerEditor::new(0x100, 0x100, 0x4, false);
     match editor.link(objects, NO_STATIC_LIBS, NO_WRAP_ROUTINES) {
         Ok((out, _info)) => {
-            assert_eq!(out.nsegs as usize, out.segments.len());
-            assert_eq!(out.object_data.len(), out.segments.len());
-            let text_seg = out
-                .segments
-                .get(&SegmentName::TEXT)
-                .unwrap_or_else(|| panic!("failed to get text segment"));
-            let text_seg_data = out
-                .object_data
-                .get(&SegmentName::TEXT)
-                .unwrap_or_else(|| panic!("failed to get text code / data"));
-            assert_eq!(text_seg.segment_len as usize, text_seg_data.len());
-            let data_seg = out
-                .segments
-                .get(&SegmentName::DATA)
-                .unwrap_or_else(|| panic!("failed to get data segment"));
-            let data_seg_data = out
-                .object_data
-                .get(&SegmentName::DATA)
-                .unwrap_or_else(|| panic!("failed to get data code / data"));
-            assert_eq!(data_seg.segment_len as usize, data_seg_data.len());
-            let bss_seg = out
-                .segments
-                .get(&SegmentName::BSS)
-                .unwrap_or_else(|| panic!("failed to get bss segment"));
-            let bss_seg_data = out
-                .object_data
-                .get(&SegmentName::BSS)
-                .unwrap_or_else(|| panic!("failed to get bss code / data"));
-            assert_eq!(bss_seg.segment_len as usize, bss_seg_data.len());
+            assert_eq!(Ok(()), out.verify());
+            for segment_name in [SegmentName::TEXT, SegmentName::DATA, SegmentName::BSS] {
+                assert!(out.segments.contains_key(&segment_name));
+                assert!(out.object_data.contains_key(&segment_name));
+            }
         }
         Err(_e) => panic!("{}", dirname),
     }
@@ -141,6 +142,21 @@ fn invalid_magic_number() {
     );
 }
 
+#[test]
+fn custom_magic_number() {
+    let contents = read_object_file(&tests_base_loc("custom_magic_number"));
+    match parse_object_file_with_magic(contents.clone(), "OBJX") {
+        Ok(obj) => {
+            assert_eq!(2, obj.version);
+            assert_eq!(1, obj.segments.len());
+        }
+        Err(e) => panic!("custom_magic_number: {e:?}"),
+    }
+    // still not a LINK object
+    let e = parse_object_file(contents).unwrap_err();
+    assert_eq!(&ParseError::InvalidMagicNumber, e.kind());
+}
+
 #[test]
 fn missing_nsegs_nsums_nrels() {
     test_failure(
@@ -205,6 +221,22 @@ fn invalid_segment_len() {
     );
 }
 
+#[test]
+fn segment_start_out_of_range() {
+    test_failure(
+        ParseError::SegmentStartOutOfRange,
+        &tests_base_loc("segment_start_out_of_range"),
+    );
+}
+
+#[test]
+fn segment_len_out_of_range() {
+    test_failure(
+        ParseError::SegmentLenOutOfRange,
+        &tests_base_loc("segment_len_out_of_range"),
+    );
+}
+
 #[test]
 fn invalid_segment_descr() {
     test_failure(
@@ -298,6 +330,19 @@ fn invalid_symbol_table_value() {
     );
 }
 
+#[test]
+fn duplicate_symbol_in_object() {
+    test_failure(
+        ParseError::DuplicateSymbolInObject("foo".to_owned()),
+        &tests_base_loc("duplicate_symbol_in_object"),
+    );
+    // reported on the second definition
+    match parse_object(&tests_base_loc("duplicate_symbol_in_object")) {
+        Err(ParseError::At { line, .. }) => assert_eq!(7, line),
+        res => panic!("expected ParseError::At, got {res:?}"),
+    }
+}
+
 #[test]
 fn invalid_symbol_table_segment_out_of_range() {
     test_failure(
@@ -330,6 +375,98 @@ fn symbol_table() {
     }
 }
 
+fn large_object(nsyms: usize) -> ObjectIn {
+    let mut lines = vec![
+        MAGIC_NUMBER.to_string(),
+        format!("1 {nsyms:X} 0"),
+        ".text 0 4 RP".to_string(),
+    ];
+    for i in 0..nsyms {
+        lines.push(format!("sym_{i} {:X} 1 D", i % 4));
+    }
+    lines.push("00 00 00 00".to_string());
+    let mut sources = BTreeMap::new();
+    sources.insert("mod_1".to_string(), lines.join("\n"));
+    link_from_sources(sources).unwrap().remove("mod_1").unwrap()
+}
+
+#[test]
+fn symbol_index_large_object() {
+    let nsyms = 5000;
+    let obj = large_object(nsyms);
+    let index = obj.build_symbol_index();
+    assert_eq!(nsyms, index.len());
+    for i in [0, 1, 2499, 4999] {
+        let ix = &index[&symbol!(format!("sym_{i}"))];
+        assert_eq!(&vec![i], ix);
+        assert_eq!(format!("sym_{i}"), *obj.symbol_table[ix[0]].st_name);
+    }
+    assert!(!index.contains_key(&symbol!("sym_5000")));
+    // every name gives the same entries as scanning symbol_table
+    for (i, ste) in obj.symbol_table.iter().enumerate() {
+        let scanned: Vec<usize> = obj
+            .symbol_table
+            .iter()
+            .enumerate()
+            .filter(|(_, x)| x.st_name == ste.st_name)
+            .map(|(ix, _)| ix)
+            .collect();
+        assert_eq!(vec![i], scanned);
+        assert_eq!(scanned, index[&ste.st_name]);
+    }
+}
+
+// cargo test --release -- --ignored symbol_index_large_object_timing --nocapture
+#[test]
+#[ignore]
+fn symbol_index_large_object_timing() {
+    let nsyms = 5000;
+    let obj = large_object(nsyms);
+    let names: Vec<SymbolName> = (0..nsyms).map(|i| symbol!(format!("sym_{i}"))).collect();
+    let start = Instant::now();
+    let scanned: Vec<usize> = names
+        .iter()
+        .map(|name| {
+            obj.symbol_table
+                .iter()
+                .position(|ste| ste.st_name == *name)
+                .unwrap()
+        })
+        .collect();
+    let scan_time = start.elapsed();
+    let start = Instant::now();
+    let index = obj.build_symbol_index();
+    let build_time = start.elapsed();
+    let start = Instant::now();
+    let indexed: Vec<usize> = names.iter().map(|name| index[name][0]).collect();
+    let index_time = start.elapsed();
+    println!("{nsyms} lookups: scan {scan_time:?}, index {index_time:?} (built in {build_time:?})");
+    assert_eq!(scanned, indexed);
+}
+
+#[test]
+fn wrap_routine_repeated_name() {
+    let testdir = tests_base_loc("wrap_routine");
+    let mut objects = read_objects_from_dir(&testdir);
+    // mod_1 refers to foo as well as defining it
+    let st = &mut objects.get_mut("mod_1").unwrap().symbol_table;
+    let mut foo_ref = st[0].clone();
+    foo_ref.st_type = SymbolTableEntryType::U;
+    foo_ref.st_seg = 0;
+    st.insert(0, foo_ref);
+    let mut editor = LinkerEditor::new(0x0, 0x0, 0x0, false);
+    match editor.link(objects, NO_STATIC_LIBS, vec![symbol!("foo")]) {
+        Ok((_, info)) => {
+            assert!(info.global_symtable[&wrapped_symbol!("foo")].is_defined());
+            assert!(!info.global_symtable.contains_key(&symbol!("foo")));
+            let mod_1 = &editor.session_objects["mod_1"];
+            assert_eq!(wrapped_symbol!("foo"), mod_1.symbol_table[0].st_name);
+            assert_eq!(wrapped_symbol!("foo"), mod_1.symbol_table[1].st_name);
+        }
+        Err(e) => panic!("{testdir} {e:?}"),
+    }
+}
+
 #[test]
 fn invalid_relocation_entry() {
     test_failure(
@@ -418,6 +555,176 @@ fn relocations() {
     }
 }
 
+#[test]
+fn relocation_table() {
+    let obj = parse_object(&tests_base_loc("relocations_1")).unwrap();
+    assert_eq!("symbol 'foo'", obj.relocations[0].describe(&obj));
+    assert_eq!(
+        "Offset    Segment  Reference     Type\n\
+         00000014  .text    symbol 'foo'  RS4\n\
+         0000001A  .text    symbol 'bas'  RS4",
+        obj.relocation_table()
+    );
+
+    let testdir = tests_base_loc("position_independent_code");
+    let mut editor = LinkerEditor::new(0x0, 0x0, 0x0, false);
+    let (out, _) = editor
+        .link(
+            read_objects_from_dir(&testdir),
+            NO_STATIC_LIBS,
+            NO_WRAP_ROUTINES,
+        )
+        .unwrap();
+    assert_eq!(
+        "Offset    Segment  Reference  Type\n\
+         00000004  .text    none       ER4\n\
+         00000008  .text    none       ER4",
+        out.relocation_table()
+    );
+}
+
+#[test]
+fn comments_and_blank_lines() {
+    let with_comments = parse_object(&tests_base_loc("comments/mod_1")).unwrap();
+    let without_comments =
+        parse_object(&tests_base_loc("position_independent_code/mod_1")).unwrap();
+    assert_eq!(3, with_comments.segments.len());
+    assert_eq!(4, with_comments.symbol_table.len());
+    assert_eq!(6, with_comments.relocations.len());
+    assert_eq!(without_comments.ppr(true), with_comments.ppr(true));
+    assert!(!with_comments.ppr(true).contains('#'));
+}
+
+#[test]
+fn comments_inside_data_section() {
+    test_failure(
+        ParseError::InvalidObjectData,
+        &tests_base_loc("comments_data_section/mod_1"),
+    );
+}
+
+#[test]
+fn object_version() {
+    let v1 = parse_object(&tests_base_loc("object_version/v1")).unwrap();
+    assert_eq!(1, v1.version);
+    assert!(v1.ppr(true).starts_with("LINK\n"));
+    let v2 = parse_object(&tests_base_loc("object_version/v2")).unwrap();
+    assert_eq!(2, v2.version);
+    let printed = v2.ppr(true);
+    assert!(printed.starts_with("LINK 2\n"));
+    assert_eq!(2, parse_object_file(printed).unwrap().version);
+    test_failure(
+        ParseError::UnsupportedVersion(99),
+        &tests_base_loc("object_version/v99"),
+    );
+}
+
+#[test]
+fn symbol_size() {
+    let obj = parse_object(&tests_base_loc("symbol_size/mod_1")).unwrap();
+    let sizes: Vec<Option<i32>> = obj.symbol_table.iter().map(|ste| ste.st_size).collect();
+    assert_eq!(vec![Some(4), Some(4), Some(4), None], sizes);
+    assert!(obj.symbol_table[1].is_local());
+    let printed = obj.ppr(true);
+    assert!(printed.contains("\nhelper 4 1 D L 4\n"));
+    assert!(printed.contains("\nputs 0 0 U\n"));
+    assert_eq!(obj, parse_object_file(printed).unwrap());
+    // no size is the old form
+    let obj = parse_object(&tests_base_loc("object_version/v1")).unwrap();
+    assert!(obj.symbol_table.iter().all(|ste| ste.st_size.is_none()));
+    test_failure(
+        ParseError::InvalidSTESize,
+        &tests_base_loc("invalid_ste_size/mod_1"),
+    );
+}
+
+#[test]
+fn zero_length_segment_roundtrip() {
+    let obj = parse_object(&tests_base_loc("empty_bss/mod_1")).unwrap();
+    assert_eq!(0, obj.segments[2].segment_len);
+    assert_eq!(0, obj.object_data[2].len());
+    // the empty .bss data line is the last one, so the printed text ends in it
+    let printed = obj.ppr(true);
+    let reparsed = parse_object_file(printed.clone()).unwrap();
+    assert_eq!(3, reparsed.object_data.len());
+    assert_eq!(0, reparsed.object_data[2].len());
+    assert_eq!(
+        &[0xDE, 0xAD, 0xBE, 0xEF, 0, 0, 0, 0],
+        reparsed.object_data[0].deref().as_slice()
+    );
+    assert_eq!(1, reparsed.relocations.len());
+    assert_eq!(printed, reparsed.ppr(true));
+}
+
+#[test]
+fn invalid_ste_visibility() {
+    test_failure(
+        ParseError::InvalidSTEVisibility,
+        &tests_base_loc("invalid_ste_visibility/mod_1"),
+    );
+}
+
+#[test]
+fn segment_not_present() {
+    let obj = parse_object(&tests_base_loc("bss_not_present/mod_1")).unwrap();
+    assert_eq!(3, obj.nsegs);
+    assert_eq!(3, obj.segments.len());
+    assert_eq!(3, obj.object_data.len());
+    assert_eq!(&[0x0, 0x0, 0x0, 0x1], obj.object_data[1].deref().as_slice());
+    // .bss has no data line and is zero-filled
+    assert_eq!(&[0x0; 8], obj.object_data[2].deref().as_slice());
+    let printed = obj.ppr(true);
+    // printed without the .bss data line as well
+    assert_eq!(8, printed.lines().count());
+    assert_eq!(obj, parse_object_file(printed).unwrap());
+    // in version 1 every segment has a data line
+    test_failure(
+        ParseError::InvalidObjectData,
+        &tests_base_loc("bss_not_present/v1"),
+    );
+}
+
+#[test]
+fn section_count_mismatch() {
+    test_failure(
+        ParseError::SectionCountMismatch,
+        &tests_base_loc("section_count_mismatch/mod_1"),
+    );
+    // nsyms is 2 too many: the .text and .data lines read as symbols
+    test_failure(
+        ParseError::SectionCountMismatch,
+        &tests_base_loc("section_count_mismatch/mod_2"),
+    );
+}
+
+#[test]
+fn parse_error_line() {
+    // baz, the third symbol, is on line 10 after two comment lines
+    assert_eq!(
+        Some(ParseError::At {
+            line: 10,
+            kind: Box::new(ParseError::InvalidSTESegment),
+        }),
+        parse_object(&tests_base_loc("parse_error_line")).err()
+    );
+}
+
+#[test]
+fn contiguous_object_data() {
+    // same bytes, contiguous and spaced the other way around
+    let mod_1 = parse_object(&tests_base_loc("contiguous_data/mod_1")).unwrap();
+    let mod_2 = parse_object(&tests_base_loc("contiguous_data/mod_2")).unwrap();
+    assert_eq!(
+        &[0xDE, 0xAD, 0xBE, 0xEF, 0x01, 0x02],
+        mod_1.object_data[0].deref().as_slice()
+    );
+    assert_eq!(mod_1.object_data, mod_2.object_data);
+    test_failure(
+        ParseError::InvalidObjectData,
+        &tests_base_loc("contiguous_data_odd"),
+    );
+}
+
 #[test]
 fn invalid_object_data() {
     test_failure(
@@ -447,6 +754,202 @@ fn link_1() {
     multi_object_test("link_1");
 }
 
+#[test]
+fn segment_declaration_order() {
+    // mod_2 declares .bss .data .text, mod_1 the usual .text .data .bss
+    let testdir = tests_base_loc("segment_order");
+    let mut editor = LinkerEditor::new(0x10, 0x0, 0x0, false);
+    match editor.link(
+        read_objects_from_dir(&testdir),
+        NO_STATIC_LIBS,
+        NO_WRAP_ROUTINES,
+    ) {
+        Ok((out, info)) => {
+            assert_eq!(Ok(()), out.verify());
+            let mod_2_addrs = info.segment_mapping.get("mod_2").unwrap();
+            assert_eq!(Some(&0x18), mod_2_addrs.get(&SegmentName::TEXT));
+            assert_eq!(Some(&0x24), mod_2_addrs.get(&SegmentName::DATA));
+            assert_eq!(Some(&0x30), mod_2_addrs.get(&SegmentName::BSS));
+            // st_seg is the position in the object's own segment list
+            for (name, addr) in [
+                ("foo", 0x14),
+                ("bar", 0x20),
+                ("baz", 0x2C),
+                ("main", 0x18),
+                ("val", 0x24),
+                ("buf", 0x34),
+            ] {
+                let gsym = info.global_symtable.get(&symbol!(name)).unwrap();
+                assert_eq!(Some(addr), gsym.address(), "{name}");
+            }
+            let text = out.object_data.get(&SegmentName::TEXT).unwrap();
+            // mod_1: baz, mod_2: val and baz
+            assert_eq!(0x2C, x_to_i4(&text.get_at(0x0, 0x4).unwrap()).unwrap());
+            assert_eq!(0x24, x_to_i4(&text.get_at(0x8, 0x4).unwrap()).unwrap());
+            assert_eq!(0x2C, x_to_i4(&text.get_at(0xC, 0x4).unwrap()).unwrap());
+            let data = out.object_data.get(&SegmentName::DATA).unwrap();
+            assert_eq!(
+                &[0xAA, 0xAA, 0xAA, 0xAA, 0xBB, 0xBB, 0xBB, 0xBB],
+                &*data.get_at(0x0, 0x8).unwrap()
+            );
+        }
+        Err(e) => panic!("{testdir} {e:?}"),
+    }
+}
+
+#[test]
+fn memory_regions() {
+    let testdir = tests_base_loc("link_1");
+    let regions = |flash_len| {
+        MemoryRegions::new(vec![
+            MemoryRegion::new("RAM", 0x8000, 0x100, vec![SegmentDescr::R, SegmentDescr::W]),
+            MemoryRegion::new("FLASH", 0x0, flash_len, vec![SegmentDescr::R]),
+        ])
+    };
+    let mut editor = LinkerEditor::new(0x0, 0x0, 0x4, false);
+    editor.set_memory_regions(regions(0x1000));
+    match editor.link(
+        read_objects_from_dir(&testdir),
+        NO_STATIC_LIBS,
+        NO_WRAP_ROUTINES,
+    ) {
+        Ok((out, _)) => {
+            assert_eq!(Ok(()), out.verify());
+            // text stays in FLASH, data and bss go to RAM
+            let starts: Vec<i32> = out.segments.values().map(|s| s.segment_start).collect();
+            assert_eq!(vec![0x0, 0x8000, 0x8020], starts);
+        }
+        Err(e) => panic!("{testdir} {e:?}"),
+    }
+    let mut editor = LinkerEditor::new(0x0, 0x0, 0x4, false);
+    editor.set_memory_regions(regions(0x10));
+    assert_eq!(
+        Some(LinkError::RegionOverflow {
+            region: "FLASH".to_owned(),
+            needed: 0x3C,
+            available: 0x10
+        }),
+        editor
+            .link(
+                read_objects_from_dir(&testdir),
+                NO_STATIC_LIBS,
+                NO_WRAP_ROUTINES,
+            )
+            .err()
+    );
+}
+
+#[test]
+fn link_dry_run() {
+    let testdir = tests_base_loc("link_1");
+    let mut editor = LinkerEditor::new(0x100, 0x100, 0x4, false);
+    let (_, expected) = editor
+        .link(
+            read_objects_from_dir(&testdir),
+            NO_STATIC_LIBS,
+            NO_WRAP_ROUTINES,
+        )
+        .unwrap();
+    let mut editor = LinkerEditor::new(0x100, 0x100, 0x4, false);
+    let info = editor
+        .link_dry_run(
+            read_objects_from_dir(&testdir),
+            NO_STATIC_LIBS,
+            NO_WRAP_ROUTINES,
+        )
+        .unwrap();
+    let (expected_stats, stats) = (expected.stats(), info.stats());
+    assert_eq!(3, stats.segment_sizes.len());
+    assert_eq!(expected_stats.segment_sizes, stats.segment_sizes);
+    assert!(stats.relocations.is_empty());
+    assert!(stats.segment_checksums.is_empty());
+    assert_eq!(expected.segment_mapping, info.segment_mapping);
+    let sym_addrs = |info: &LinkerInfo| -> Vec<(SymbolName, Option<i32>)> {
+        info.global_symtable
+            .iter()
+            .map(|(name, gsym)| (name.clone(), gsym.address()))
+            .collect()
+    };
+    assert_eq!(sym_addrs(&expected), sym_addrs(&info));
+
+    let mut editor = LinkerEditor::new(0x10, 0x10, 0x4, false);
+    assert_eq!(
+        Some(LinkError::UndefinedSymbolError),
+        editor
+            .link_dry_run(
+                read_objects_from_dir(&tests_base_loc("undefined_symbol")),
+                NO_STATIC_LIBS,
+                NO_WRAP_ROUTINES,
+            )
+            .err()
+    );
+}
+
+#[test]
+fn link_directory_link_1() {
+    let cfg = LinkConfig {
+        text_start: 0x100,
+        data_start_boundary: 0x100,
+        bss_start_boundary: 0x4,
+        ..LinkConfig::default()
+    };
+    let (out, info) = link_directory(&tests_base_loc("link_1"), cfg).unwrap();
+    let objects = read_objects_from_dir(&tests_base_loc("link_1"));
+    let mut editor = LinkerEditor::new(0x100, 0x100, 0x4, false);
+    let (expected, _) = editor
+        .link(objects, NO_STATIC_LIBS, NO_WRAP_ROUTINES)
+        .unwrap();
+    assert_eq!(expected.ppr(false), out.ppr(false));
+    assert_eq!(3, info.segment_mapping.len());
+    let cfg = LinkConfig {
+        bss_start_boundary: -0x4,
+        ..LinkConfig::default()
+    };
+    assert_eq!(
+        Some(LinkError::InvalidAlignment(-0x4)),
+        link_directory(&tests_base_loc("link_1"), cfg).err()
+    );
+}
+
+#[test]
+fn link_directory_parse_failure() {
+    let dir = std::env::temp_dir().join(format!("linkerloader_bad_dir_{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("mod_1"), "NOTLINK\n").unwrap();
+    let res = link_directory(dir.to_str().unwrap(), LinkConfig::default());
+    fs::remove_dir_all(&dir).unwrap();
+    match res {
+        Err(LinkError::ObjectParseFailure(e)) => {
+            assert_eq!(&ParseError::InvalidMagicNumber, e.kind())
+        }
+        Err(e) => panic!("expected ObjectParseFailure, got {e:?}"),
+        Ok(_) => panic!("mod_1 is not an object"),
+    }
+}
+
+#[test]
+fn link_in_batches() {
+    let objects = read_objects_from_dir(&tests_base_loc("link_1"));
+    let mut editor = LinkerEditor::new(0x100, 0x100, 0x4, false);
+    let (expected, _) = editor
+        .link(objects.clone(), NO_STATIC_LIBS, NO_WRAP_ROUTINES)
+        .unwrap();
+
+    let mut editor = LinkerEditor::new(0x100, 0x100, 0x4, false);
+    let (first, second): (BTreeMap<_, _>, BTreeMap<_, _>) = objects
+        .into_iter()
+        .partition(|(name, _)| name == "object_2");
+    editor.add_objects(first.clone()).unwrap();
+    editor.add_objects(second).unwrap();
+    assert_eq!(
+        Err(LinkError::DuplicateObjectError),
+        editor.add_objects(first)
+    );
+    let (out, info) = editor.finalize(NO_STATIC_LIBS, NO_WRAP_ROUTINES).unwrap();
+    assert_eq!(3, info.segment_mapping.len());
+    assert_eq!(expected.ppr(false), out.ppr(false));
+}
+
 #[test]
 fn link_2() {
     multi_object_test("link_2");
@@ -459,6 +962,7 @@ fn common_block_1() {
     let mut editor = LinkerEditor::new(0x10, 0x10, 0x4, false);
     match editor.link(objects, NO_STATIC_LIBS, NO_WRAP_ROUTINES) {
         Ok((out, info)) => {
+            assert_eq!(Ok(()), out.verify());
             assert_eq!(3, info.common_block_mapping.len());
             assert_eq!(out.object_data.len(), out.segments.len());
             let bss_seg = out
@@ -486,6 +990,7 @@ fn common_block_bigger_size() {
     let mut editor = LinkerEditor::new(0x10, 0x10, 0x4, false);
     match editor.link(objects, NO_STATIC_LIBS, NO_WRAP_ROUTINES) {
         Ok((out, info)) => {
+            assert_eq!(Ok(()), out.verify());
             assert_eq!(1, info.common_block_mapping.len());
             assert_eq!(out.nsegs as usize, out.segments.len());
             let bss_seg = out
@@ -500,6 +1005,83 @@ fn common_block_bigger_size() {
     }
 }
 
+#[test]
+fn common_block_relocation() {
+    let dirname = "common_block_reloc";
+    let objects = read_objects_from_dir(&tests_base_loc(dirname));
+    let mut editor = LinkerEditor::new(0x0, 0x0, 0x0, false);
+    match editor.link(objects, NO_STATIC_LIBS, NO_WRAP_ROUTINES) {
+        Ok((out, info)) => {
+            assert_eq!(Ok(()), out.verify());
+            // BSS at 10 holds 8 bytes of module data, then buf (10) and cnt (4)
+            let bss_seg = out.segments.get(&SegmentName::BSS).unwrap();
+            assert_eq!(0x10, bss_seg.segment_start);
+            assert_eq!(0x1C, bss_seg.segment_len);
+            let defn_addr = |name: &str| info.global_symtable[&symbol!(name)].address();
+            assert_eq!(Some(0x18), defn_addr("buf"));
+            assert_eq!(Some(0x28), defn_addr("cnt"));
+            let obj_code_text = out.object_data.get(&SegmentName::TEXT).unwrap();
+            assert_eq!(
+                0x18,
+                x_to_i4(&obj_code_text.get_at(0x0, 0x4).unwrap()).unwrap()
+            );
+            // cnt + addend 4
+            assert_eq!(
+                0x2C,
+                x_to_i4(&obj_code_text.get_at(0x8, 0x4).unwrap()).unwrap()
+            );
+        }
+        Err(e) => panic!("{}: {:?}", dirname, e),
+    }
+}
+
+#[test]
+fn common_block_defined() {
+    let dirname = "common_block_defined";
+    let objects = read_objects_from_dir(&tests_base_loc(dirname));
+    let mut editor = LinkerEditor::new(0x0, 0x0, 0x0, false);
+    match editor.link(objects, NO_STATIC_LIBS, NO_WRAP_ROUTINES) {
+        Ok((out, info)) => {
+            assert_eq!(Ok(()), out.verify());
+            // buf is mod_2's, so BSS is just the modules' 4 + 10 bytes
+            assert!(info.common_block_mapping.is_empty());
+            let bss_seg = out.segments.get(&SegmentName::BSS).unwrap();
+            assert_eq!(0x10, bss_seg.segment_start);
+            assert_eq!(0x14, bss_seg.segment_len);
+            let defn = info.global_symtable[&symbol!("buf")].defn.as_ref().unwrap();
+            assert_eq!(Some(0x14), defn.defn_addr);
+            let obj_code_text = out.object_data.get(&SegmentName::TEXT).unwrap();
+            assert_eq!(
+                0x14,
+                x_to_i4(&obj_code_text.get_at(0x0, 0x4).unwrap()).unwrap()
+            );
+        }
+        Err(e) => panic!("{}: {:?}", dirname, e),
+    }
+}
+
+#[test]
+fn common_block_defined_first() {
+    // common_block_defined with the modules swapped: buf is defined before
+    // the object with the common block is seen
+    let dirname = "common_block_defined_first";
+    let objects = read_objects_from_dir(&tests_base_loc(dirname));
+    let mut editor = LinkerEditor::new(0x0, 0x0, 0x0, false);
+    match editor.link(objects, NO_STATIC_LIBS, NO_WRAP_ROUTINES) {
+        Ok((out, info)) => {
+            assert_eq!(Ok(()), out.verify());
+            assert!(info.common_block_mapping.is_empty());
+            // just the modules' 10 + 4 bytes
+            let bss_seg = out.segments.get(&SegmentName::BSS).unwrap();
+            assert_eq!(0x14, bss_seg.segment_len);
+            let defn = info.global_symtable[&symbol!("buf")].defn.as_ref().unwrap();
+            assert_eq!("mod_1", defn.defn_mod_id);
+            assert_eq!(Some(bss_seg.segment_start), defn.defn_addr);
+        }
+        Err(e) => panic!("{}: {:?}", dirname, e),
+    }
+}
+
 #[test]
 fn symbol_name_resolution_1() {
     let dirname = "symbol_name_resolution_1";
@@ -511,48 +1093,436 @@ fn symbol_name_resolution_1() {
             assert!(info.global_symtable.contains_key(&symbol!("foo")));
             assert!(info.global_symtable.contains_key(&symbol!("bar")));
             let foo_ste = info.global_symtable.get(&symbol!("foo")).unwrap().clone();
-            assert_eq!("mod_2", foo_ste.0.as_ref().unwrap().defn_mod_id);
-            assert_eq!(Some(0), foo_ste.0.as_ref().unwrap().defn_ste_ix);
-            assert!(foo_ste.1.contains_key("mod_1"));
-            assert_eq!(0, *foo_ste.1.get("mod_1").unwrap());
+            assert_eq!("mod_2", foo_ste.defn.as_ref().unwrap().defn_mod_id);
+            assert_eq!(Some(0), foo_ste.defn.as_ref().unwrap().defn_ste_ix);
+            assert!(foo_ste.refs.contains_key("mod_1"));
+            assert_eq!(0, *foo_ste.refs.get("mod_1").unwrap());
             let bar_ste = info.global_symtable.get(&symbol!("bar")).unwrap().clone();
-            assert_eq!("mod_1", bar_ste.0.as_ref().unwrap().defn_mod_id);
-            assert_eq!(Some(1), bar_ste.0.as_ref().unwrap().defn_ste_ix);
-            assert!(bar_ste.1.contains_key("mod_2"));
-            assert_eq!(1, *bar_ste.1.get("mod_2").unwrap());
+            assert_eq!("mod_1", bar_ste.defn.as_ref().unwrap().defn_mod_id);
+            assert_eq!(Some(1), bar_ste.defn.as_ref().unwrap().defn_ste_ix);
+            assert!(bar_ste.refs.contains_key("mod_2"));
+            assert_eq!(1, *bar_ste.refs.get("mod_2").unwrap());
         }
         Err(e) => panic!("{}: {:?}", dirname, e),
     }
 }
 
 #[test]
-fn multiple_symbol_defns() {
-    let dirname = "multiple_symbol_defns";
-    let objects = read_objects_from_dir(&tests_base_loc(dirname));
+fn link_in_memory_objects() {
+    let mut sources = BTreeMap::new();
+    sources.insert(
+        "mod_1".to_string(),
+        [
+            "LINK",
+            "2 2 0",
+            ".text 0 8 RP",
+            ".data 8 4 RWP",
+            "foo 0 1 U",
+            "bar 4 1 D",
+            "00 00 00 00 00 00 00 00",
+            "00 00 00 00",
+        ]
+        .join("\n"),
+    );
+    sources.insert(
+        "mod_2".to_string(),
+        [
+            "LINK",
+            "1 2 0",
+            ".text 0 4 RP",
+            "foo 0 1 D",
+            "bar 0 1 U",
+            "00 00 00 00",
+        ]
+        .join("\n"),
+    );
+    let objects = link_from_sources(sources).unwrap();
     let mut editor = LinkerEditor::new(0x10, 0x10, 0x4, false);
     match editor.link(objects, NO_STATIC_LIBS, NO_WRAP_ROUTINES) {
-        Err(e) => assert_eq!(LinkError::MultipleSymbolDefinitions, e),
-        _ => panic!("{}", dirname),
+        Ok((_out, info)) => {
+            assert_eq!(2, info.global_symtable.len());
+            let foo_ste = info.global_symtable.get(&symbol!("foo")).unwrap();
+            assert_eq!("mod_2", foo_ste.defn.as_ref().unwrap().defn_mod_id);
+            assert_eq!(Some(0x18), foo_ste.defn.as_ref().unwrap().defn_addr);
+            assert!(foo_ste.refs.contains_key("mod_1"));
+            let bar_ste = info.global_symtable.get(&symbol!("bar")).unwrap();
+            assert_eq!("mod_1", bar_ste.defn.as_ref().unwrap().defn_mod_id);
+            assert_eq!(Some(0x14), bar_ste.defn.as_ref().unwrap().defn_addr);
+            assert!(bar_ste.refs.contains_key("mod_2"));
+        }
+        Err(e) => panic!("link_in_memory_objects: {e:?}"),
+    }
+}
+
+// Minimal ustar writer, just enough for read_objects_from_archive
+#[cfg(feature = "archive")]
+fn write_tar(path: &std::path::Path, entries: &[(&str, String)]) {
+    let mut tar = vec![];
+    for (name, contents) in entries {
+        let mut hdr = [0u8; 512];
+        hdr[..name.len()].copy_from_slice(name.as_bytes());
+        hdr[100..108].copy_from_slice(b"0000644\0");
+        hdr[124..136].copy_from_slice(format!("{:011o}\0", contents.len()).as_bytes());
+        hdr[156] = b'0';
+        hdr[257..265].copy_from_slice(b"ustar\x0000");
+        hdr[148..156].copy_from_slice(b"        ");
+        let chksum: u32 = hdr.iter().map(|&b| b as u32).sum();
+        hdr[148..156].copy_from_slice(format!("{chksum:06o}\0 ").as_bytes());
+        tar.extend_from_slice(&hdr);
+        tar.extend_from_slice(contents.as_bytes());
+        tar.resize(tar.len().div_ceil(512) * 512, 0);
     }
+    tar.resize(tar.len() + 1024, 0);
+    fs::write(path, tar).unwrap();
 }
 
+#[cfg(feature = "archive")]
 #[test]
-fn undefined_symbol() {
-    let dirname = "undefined_symbol";
+fn read_objects_archive() {
+    let testdir = tests_base_loc("position_independent_code");
+    let entries = [
+        ("objs/mod_1", read_object_file(&format!("{testdir}/mod_1"))),
+        ("objs/mod_2", read_object_file(&format!("{testdir}/mod_2"))),
+        ("objs/mod_1_out", String::from("not an object")),
+    ];
+    let tar_path =
+        std::env::temp_dir().join(format!("linkerloader_archive_{}.tar", std::process::id()));
+    write_tar(&tar_path, &entries);
+    let objects = read_objects_from_archive(tar_path.to_str().unwrap());
+    fs::remove_file(&tar_path).unwrap();
+    let objects = objects.unwrap();
+    assert_eq!(
+        vec!["mod_1", "mod_2"],
+        objects.keys().map(|k| k.as_str()).collect::<Vec<_>>()
+    );
+    let mut editor = LinkerEditor::new(0x0, 0x0, 0x0, false);
+    match editor.link(objects, NO_STATIC_LIBS, NO_WRAP_ROUTINES) {
+        Ok((out, _)) => assert_eq!(8, out.object_data.get(&SegmentName::GOT).unwrap().len()),
+        Err(e) => panic!("{e:?}"),
+    }
+}
+
+#[cfg(feature = "archive")]
+#[test]
+fn read_objects_archive_missing() {
+    let path = tests_base_loc("no_such_archive.tar");
+    assert_eq!(
+        Some(ParseError::FileReadError(path.clone())),
+        read_objects_from_archive(&path).err()
+    );
+}
+
+#[cfg(feature = "gzip")]
+#[test]
+fn read_gzip_objects() {
+    let testdir = tests_base_loc("link_1");
+    let gzdir = std::env::temp_dir().join(format!("linkerloader_gzip_{}", std::process::id()));
+    fs::create_dir_all(&gzdir).unwrap();
+    for obj in ["object_1", "object_2", "object_3"] {
+        fs::copy(format!("{testdir}/{obj}"), gzdir.join(obj)).unwrap();
+    }
+    // object_3 stays uncompressed
+    let status = std::process::Command::new("gzip")
+        .arg("-9")
+        .arg(gzdir.join("object_1"))
+        .arg(gzdir.join("object_2"))
+        .status()
+        .unwrap();
+    assert!(status.success());
+    let gz_path = gzdir.join("object_1.gz");
+    assert_eq!(
+        parse_object(&format!("{testdir}/object_1")),
+        parse_object(gz_path.to_str().unwrap())
+    );
+    let mut corrupt = fs::read(&gz_path).unwrap();
+    corrupt.truncate(corrupt.len() - 4);
+    assert_eq!(
+        Err(ParseError::InvalidGzip),
+        linkerloader::gzip::gunzip(&corrupt)
+    );
+    let corrupt_path = gzdir.join("corrupt.gz");
+    fs::write(&corrupt_path, &corrupt).unwrap();
+    assert_eq!(
+        Err(ParseError::InvalidGzip),
+        parse_object(corrupt_path.to_str().unwrap())
+    );
+    fs::remove_file(&corrupt_path).unwrap();
+    // read_objects takes the file names as they are
+    let named = read_objects(gzdir.to_str().unwrap(), ["object_1.gz", "object_3"]).unwrap();
+    assert_eq!(
+        parse_object(&format!("{testdir}/object_1")).unwrap(),
+        named["object_1.gz"]
+    );
+    let objects = read_objects_from_dir(gzdir.to_str().unwrap());
+    fs::remove_dir_all(&gzdir).unwrap();
+    assert_eq!(
+        vec!["object_1.gz", "object_2.gz", "object_3"],
+        objects.keys().map(|k| k.as_str()).collect::<Vec<_>>()
+    );
+    let mut editor = LinkerEditor::new(0x0, 0x0, 0x4, false);
+    let (out, _) = editor
+        .link(objects, NO_STATIC_LIBS, NO_WRAP_ROUTINES)
+        .unwrap();
+    let mut editor = LinkerEditor::new(0x0, 0x0, 0x4, false);
+    let (expected, _) = editor
+        .link(
+            read_objects_from_dir(&testdir),
+            NO_STATIC_LIBS,
+            NO_WRAP_ROUTINES,
+        )
+        .unwrap();
+    assert_eq!(expected.object_data, out.object_data);
+}
+
+#[test]
+fn read_objects_filtered() {
+    let dirname = "read_objects_filtered";
+    let objects = read_objects_from_dir_filtered(&tests_base_loc(dirname), |path| {
+        path.extension().is_none_or(|ext| ext != "txt")
+    })
+    .unwrap();
+    assert_eq!(2, objects.len());
+    assert!(objects.contains_key("mod_1"));
+    assert!(objects.contains_key("mod_2"));
+    assert!(!objects.contains_key("README.txt"));
+}
+
+#[test]
+fn ppr_annotated_text_merge() {
+    let dirname = "symbol_name_resolution_1";
     let objects = read_objects_from_dir(&tests_base_loc(dirname));
     let mut editor = LinkerEditor::new(0x10, 0x10, 0x4, false);
     match editor.link(objects, NO_STATIC_LIBS, NO_WRAP_ROUTINES) {
-        Err(e) => assert_eq!(LinkError::UndefinedSymbolError, e),
-        _ => panic!("{}", dirname),
+        Ok((out, info)) => {
+            assert_eq!(Ok(()), out.verify());
+            let annotated = out.ppr_annotated(&info);
+            assert!(annotated.starts_with(&out.ppr(false)));
+            assert!(annotated.contains("# .text\n#   [10..2E] <- mod_1\n#   [2E..42] <- mod_2"));
+        }
+        Err(e) => panic!("{}: {:?}", dirname, e),
     }
 }
 
 #[test]
-fn symbol_value_resolution() {
-    let dirname = "symbol_value_resolution";
-    let objects = read_objects_from_dir(&tests_base_loc(dirname));
-    let text_start = 0x10;
-    let mut editor = LinkerEditor::new(text_start, 0x10, 0x4, false);
+fn segment_len_overflow() {
+    // segment data is deliberately tiny: the overflow has to be caught
+    // before anything gets allocated for the second object
+    let mk_obj = |segment_len| ObjectIn {
+        arch: None,
+        version: 1,
+        nsegs: 1,
+        nsyms: 0,
+        nrels: 0,
+        segments: vec![Segment {
+            segment_name: SegmentName::TEXT,
+            segment_start: 0,
+            segment_len,
+            segment_descr: vec![SegmentDescr::R, SegmentDescr::P],
+            segment_align: 0,
+        }],
+        symbol_table: vec![],
+        relocations: vec![],
+        object_data: vec![SegmentData::new(4)],
+    };
+    let mut objects = BTreeMap::new();
+    objects.insert("mod_1".to_string(), mk_obj(0x7FFFFFF0));
+    objects.insert("mod_2".to_string(), mk_obj(0x20));
+    let mut editor = LinkerEditor::new(0x0, 0x0, 0x0, false);
+    match editor.link(objects, NO_STATIC_LIBS, NO_WRAP_ROUTINES) {
+        Err(e) => assert_eq!(LinkError::IntOverflowError, e),
+        Ok(_) => panic!("segment_len_overflow: unexpected Ok"),
+    }
+}
+
+#[test]
+fn arch_mismatch() {
+    let dirname = "arch_mismatch";
+    let objects = read_objects_from_dir(&tests_base_loc(dirname));
+    assert_eq!(Some("x86".to_string()), objects.get("mod_1").unwrap().arch);
+    assert_eq!(Some("arm".to_string()), objects.get("mod_2").unwrap().arch);
+    let mut editor = LinkerEditor::new(0x10, 0x10, 0x4, false);
+    match editor.link(objects, NO_STATIC_LIBS, NO_WRAP_ROUTINES) {
+        Err(e) => assert_eq!(LinkError::ArchMismatch, e),
+        Ok(_) => panic!("{}: unexpected Ok", dirname),
+    }
+}
+
+#[test]
+fn arch_match() {
+    let dirname = "arch_match";
+    let objects = read_objects_from_dir(&tests_base_loc(dirname));
+    assert!(objects.get("mod_3").unwrap().arch.is_none());
+    let mut editor = LinkerEditor::new(0x10, 0x10, 0x4, false);
+    match editor.link(objects, NO_STATIC_LIBS, NO_WRAP_ROUTINES) {
+        Ok((_out, info)) => assert_eq!(Some("x86".to_string()), info.arch),
+        Err(e) => panic!("{}: {:?}", dirname, e),
+    }
+}
+
+#[test]
+fn multiple_symbol_defns() {
+    let dirname = "multiple_symbol_defns";
+    let objects = read_objects_from_dir(&tests_base_loc(dirname));
+    let mut editor = LinkerEditor::new(0x10, 0x10, 0x4, false);
+    match editor.link(objects, NO_STATIC_LIBS, NO_WRAP_ROUTINES) {
+        Err(e) => assert_eq!(LinkError::MultipleSymbolDefinitions, e),
+        _ => panic!("{}", dirname),
+    }
+}
+
+#[test]
+fn undefined_symbol() {
+    let dirname = "undefined_symbol";
+    let objects = read_objects_from_dir(&tests_base_loc(dirname));
+    let mut editor = LinkerEditor::new(0x10, 0x10, 0x4, false);
+    match editor.link(objects, NO_STATIC_LIBS, NO_WRAP_ROUTINES) {
+        Err(e) => assert_eq!(LinkError::UndefinedSymbolError, e),
+        _ => panic!("{}", dirname),
+    }
+}
+
+#[test]
+fn symbol_provenance() {
+    let dirname = "symbol_value_resolution";
+    let objects = read_objects_from_dir(&tests_base_loc(dirname));
+    let mut editor = LinkerEditor::new(0x10, 0x10, 0x4, false);
+    match editor.link(objects, NO_STATIC_LIBS, NO_WRAP_ROUTINES) {
+        Ok((_out, info)) => {
+            for name in ["foo", "bar", "baz"] {
+                assert_eq!(
+                    Some(&DefnProvenance::FromObjectIn),
+                    info.symbol_provenance(&symbol!(name))
+                );
+            }
+            assert_eq!(None, info.symbol_provenance(&symbol!("qux")));
+            assert!(info.ppr().contains("  foo => 20 (object_1, object)"));
+        }
+        Err(e) => panic!("{}: {:?}", dirname, e),
+    }
+}
+
+#[test]
+fn sort_output_symbols() {
+    let dirname = "symbol_value_resolution";
+    let objects = read_objects_from_dir(&tests_base_loc(dirname));
+    let mut editor = LinkerEditor::new(0x10, 0x10, 0x4, false);
+    let (mut out, info) = editor
+        .link(objects, NO_STATIC_LIBS, NO_WRAP_ROUTINES)
+        .unwrap();
+    // the linker leaves the output symbol table empty, fill it in from the
+    // global symbols, in reverse name order
+    for (name, gsym) in info.global_symtable.iter().rev() {
+        out.symbol_table.push(SymbolTableEntry {
+            st_name: name.clone(),
+            st_value: gsym.address().unwrap(),
+            st_seg: 0,
+            st_type: SymbolTableEntryType::D,
+            st_vis: SymbolVisibility::G,
+            st_size: None,
+        });
+    }
+    let names = |out: &ObjectOut| -> Vec<String> {
+        out.symbol_table
+            .iter()
+            .map(|ste| ste.st_name.to_string())
+            .collect()
+    };
+    out.sort_symbols(SymbolSort::Address);
+    assert_eq!(vec!["foo", "bar", "baz"], names(&out));
+    out.sort_symbols(SymbolSort::Name);
+    assert_eq!(vec!["bar", "baz", "foo"], names(&out));
+}
+
+#[test]
+fn strip_symbols() {
+    let testdir = tests_base_loc("symbol_size");
+    let mut editor = LinkerEditor::new(0x10, 0x10, 0x4, false);
+    editor.define_symbol(symbol!("puts"), 0x8000);
+    let (mut out, info) = editor
+        .link(
+            read_objects_from_dir(&testdir),
+            NO_STATIC_LIBS,
+            NO_WRAP_ROUTINES,
+        )
+        .unwrap();
+    // the linker leaves the output symbol table empty, fill it in from the
+    // definitions in mod_1, the local helper included
+    let fill = |out: &mut ObjectOut| {
+        out.symbol_table = info.symbol_tables["mod_1"]
+            .iter()
+            .filter(|ste| ste.is_defined())
+            .cloned()
+            .collect();
+        out.nsyms = out.symbol_table.len() as i32;
+    };
+    fill(&mut out);
+    let unstripped = out.ppr(false);
+    out.strip(StripLevel::None);
+    assert_eq!(unstripped, out.ppr(false));
+    assert!(unstripped.contains("\nhelper 4 1 D L 4\n"));
+
+    out.strip(StripLevel::Locals);
+    let printed = out.ppr(false);
+    assert!(printed.starts_with("LINK\n2 2 0\n"));
+    assert!(printed.contains("\nmain 0 1 D 4\n"));
+    assert!(printed.contains("\ncounter 0 2 D 4\n"));
+    assert!(!printed.contains("helper"));
+    // stripping doesn't touch the relocated code and data
+    assert_eq!(
+        unstripped.lines().skip(5).collect::<Vec<_>>(),
+        printed.lines().skip(4).collect::<Vec<_>>()
+    );
+
+    fill(&mut out);
+    out.strip(StripLevel::All);
+    assert!(out.symbol_table.is_empty());
+    assert!(out.ppr(false).starts_with("LINK\n2 0 0\n.text"));
+}
+
+#[test]
+fn define_symbol() {
+    let dirname = "define_symbol";
+    let objects = read_objects_from_dir(&tests_base_loc(dirname));
+    let mut editor = LinkerEditor::new(0x10, 0x10, 0x4, false);
+    editor.define_symbol(symbol!("__bss_end"), 0x8000);
+    match editor.link(objects, NO_STATIC_LIBS, NO_WRAP_ROUTINES) {
+        Ok((out, info)) => {
+            assert_eq!(Ok(()), out.verify());
+            let gsym = info.global_symtable.get(&symbol!("__bss_end")).unwrap();
+            assert_eq!(Some(0x8000), gsym.address());
+            assert_eq!(vec!["mod_1"], gsym.referencing_modules());
+            assert_eq!(
+                Some(&DefnProvenance::FromDefSym),
+                info.symbol_provenance(&symbol!("__bss_end"))
+            );
+            let obj_code_text = out.object_data.get(&SegmentName::TEXT).unwrap();
+            assert_eq!(
+                0x8000,
+                x_to_i4(&obj_code_text.get_at(0x4, 0x4).unwrap()).unwrap()
+            );
+        }
+        Err(e) => panic!("{}: {:?}", dirname, e),
+    }
+}
+
+#[test]
+fn define_symbol_conflict() {
+    let dirname = "define_symbol";
+    let objects = read_objects_from_dir(&tests_base_loc(dirname));
+    let mut editor = LinkerEditor::new(0x10, 0x10, 0x4, false);
+    editor.define_symbol(symbol!("main"), 0x8000);
+    match editor.link(objects, NO_STATIC_LIBS, NO_WRAP_ROUTINES) {
+        Err(e) => assert_eq!(LinkError::MultipleSymbolDefinitions, e),
+        Ok(_) => panic!("{}: unexpected Ok", dirname),
+    }
+}
+
+#[test]
+fn symbol_value_resolution() {
+    let dirname = "symbol_value_resolution";
+    let objects = read_objects_from_dir(&tests_base_loc(dirname));
+    let text_start = 0x10;
+    let mut editor = LinkerEditor::new(text_start, 0x10, 0x4, false);
     match editor.link(objects, NO_STATIC_LIBS, NO_WRAP_ROUTINES) {
         Ok((_out, info)) => {
             println!("{:?}", info);
@@ -561,30 +1531,21 @@ fn symbol_value_resolution() {
                 .global_symtable
                 .get(&symbol!("foo"))
                 .unwrap()
-                .0
-                .clone()
-                .unwrap()
-                .defn_addr
+                .address()
                 .unwrap();
             assert_eq!(0x20, foo_abs_addr);
             let bar_abs_addr = info
                 .global_symtable
                 .get(&symbol!("bar"))
                 .unwrap()
-                .0
-                .clone()
-                .unwrap()
-                .defn_addr
+                .address()
                 .unwrap();
             assert_eq!(0x5A + 0x5, bar_abs_addr);
             let baz_abs_addr = info
                 .global_symtable
                 .get(&symbol!("baz"))
                 .unwrap()
-                .0
-                .clone()
-                .unwrap()
-                .defn_addr
+                .address()
                 .unwrap();
             assert_eq!(0x78 + 0x2, baz_abs_addr);
         }
@@ -615,6 +1576,45 @@ fn static_lib_dir() {
     }
 }
 
+#[test]
+fn map_file_whitespace() {
+    let testdir = tests_base_loc("map_whitespace");
+    match read_lib(&format!("{testdir}/dirlib")) {
+        Ok(StaticLib::DirLib { symbols, .. }) => {
+            assert_eq!(
+                BTreeMap::from([
+                    (
+                        "libmod_1".to_owned(),
+                        [symbol!("foo"), symbol!("another_foo")].into()
+                    ),
+                    ("libmod_2".to_owned(), [symbol!("bar")].into()),
+                ]),
+                symbols
+            );
+        }
+        Ok(_) => panic!("{testdir}/dirlib: expected StaticLib::DirLib"),
+        Err(e) => panic!("{testdir}/dirlib: {e:?}"),
+    }
+    match StubLib::parse(&format!("{testdir}/stublib")) {
+        Ok(stub_lib) => {
+            assert_eq!(
+                BTreeMap::from([
+                    (
+                        "libmod_1_stub".to_owned(),
+                        vec![symbol!("foo"), symbol!("bar")]
+                    ),
+                    (
+                        "libmod_2_stub".to_owned(),
+                        vec![symbol!("free"), symbol!("malloc"), symbol!("printf")]
+                    ),
+                ]),
+                stub_lib.defs
+            );
+        }
+        Err(e) => panic!("{testdir}/stublib: {e:?}"),
+    }
+}
+
 #[test]
 fn static_lib_file() {
     let dirname = "static_lib_file";
@@ -632,6 +1632,53 @@ fn static_lib_file() {
     }
 }
 
+#[test]
+fn build_static_lib_dir_duplicate_names() {
+    let base_loc = tests_base_loc("dirlib_duplicate_names");
+    ensure_clean_state(&base_loc);
+    let objs = vec!["a/libmod_1", "b/libmod_1"];
+    assert_eq!(
+        Err(LibError::DuplicateModuleName(String::from("libmod_1"))),
+        StaticLib::build_static_dirlib(objs, Some(&base_loc), None)
+    );
+    // rejected before anything is written
+    assert!(!PathBuf::from(&base_loc).join("staticlib").exists());
+}
+
+#[test]
+fn build_static_lib_dir_missing_basepath() {
+    let base_loc = tests_base_loc("no_such_dir");
+    assert!(!PathBuf::from(&base_loc).exists());
+    assert_eq!(
+        Err(LibError::IOError(std::io::ErrorKind::NotFound)),
+        StaticLib::build_static_dirlib(vec!["libmod_1"], Some(&base_loc), None)
+    );
+}
+
+#[test]
+fn build_static_lib_from_dir() {
+    // objs has libmod_1, libmod_2, libmod_3, and a libmod_1_out to skip
+    let base_loc = tests_base_loc("build_static_lib_from_dir");
+    ensure_clean_state(&base_loc);
+    let mut librarian = Librarian::new(false);
+    librarian
+        .build_libdir_from_dir(Some(&base_loc), None, "objs")
+        .unwrap();
+    let lib_loc = PathBuf::from(&base_loc).join(PathBuf::from("staticlib"));
+    match read_lib(lib_loc.to_str().unwrap()) {
+        Ok(StaticLib::DirLib { symbols, .. }) => {
+            assert_eq!(
+                vec!["libmod_1", "libmod_2", "libmod_3"],
+                symbols.keys().map(|k| k.as_str()).collect::<Vec<_>>()
+            );
+            assert!(symbols["libmod_2"].contains(&symbol!("bar")));
+        }
+        Ok(_) => panic!("unexpected StaticLib kind"),
+        Err(e) => panic!("build_static_lib_from_dir: {e:?}"),
+    }
+    ensure_clean_state(&base_loc);
+}
+
 #[test]
 fn build_static_lib_dir() {
     let base_loc = tests_base_loc("build_static_lib_dir");
@@ -666,6 +1713,26 @@ fn build_static_lib_dir() {
     ensure_clean_state(&base_loc);
 }
 
+#[test]
+fn static_lib_file_bad_offset() {
+    // header claims the directory at line 2B, but the file is 1D lines long
+    let dirname = "static_lib_file_bad_offset";
+    match read_lib(&tests_base_loc(dirname)) {
+        Err(e) => assert_eq!(LibError::LibOffsetOutOfRange(0x2B), e),
+        Ok(_) => panic!("{dirname}: expected LibOffsetOutOfRange"),
+    }
+}
+
+#[test]
+fn static_lib_file_bad_header() {
+    // module count is not hex
+    let dirname = "static_lib_file_bad_header";
+    match read_lib(&tests_base_loc(dirname)) {
+        Err(e) => assert_eq!(LibError::ParseLibError, e),
+        Ok(_) => panic!("{dirname}: expected ParseLibError"),
+    }
+}
+
 #[test]
 fn build_static_lib_file() {
     let base_loc = tests_base_loc("build_static_lib_file");
@@ -700,6 +1767,40 @@ fn build_static_lib_file() {
     ensure_clean_state(&base_loc);
 }
 
+#[test]
+fn filelib_from_objects() {
+    let base_loc = tests_base_loc("filelib_from_objects");
+    ensure_clean_state(&base_loc);
+    let objects = read_objects(&base_loc, ["libmod_1", "libmod_2", "libmod_3"]).unwrap();
+    let lib = StaticLib::from_objects("libfoo", objects.clone());
+    assert_eq!("libfoo", lib.get_name());
+    let lib_loc = format!("{base_loc}/staticlibfile");
+    lib.write(&lib_loc).unwrap();
+    let stub = StaticLib::Stub(StubLib::new("libstub".to_string()));
+    assert_eq!(
+        Err(LibError::NotAFileLib("libstub".to_string())),
+        stub.write(&lib_loc)
+    );
+    match read_lib(&lib_loc) {
+        Ok(StaticLib::FileLib {
+            symbols,
+            objects: lib_objects,
+            ..
+        }) => {
+            assert_eq!(4, symbols.len());
+            assert_eq!(0, *symbols.get(&symbol!("foo")).unwrap());
+            assert_eq!(0, *symbols.get(&symbol!("another_foo")).unwrap());
+            assert_eq!(1, *symbols.get(&symbol!("bar")).unwrap());
+            assert_eq!(2, *symbols.get(&symbol!("baz")).unwrap());
+            let expected: Vec<ObjectIn> = objects.into_values().collect();
+            assert_eq!(expected, lib_objects);
+        }
+        Ok(_) => panic!("{lib_loc}: expected StaticLib::FileLib"),
+        Err(e) => panic!("{lib_loc}: {e:?}"),
+    }
+    ensure_clean_state(&base_loc);
+}
+
 #[test]
 fn link_with_static_libs() {
     let base_loc = tests_base_loc("link_with_static_libs");
@@ -719,15 +1820,34 @@ fn link_with_static_libs() {
     let staticlib = read_lib(lib_loc.to_str().unwrap()).unwrap();
     let mut editor = LinkerEditor::new(text_start, 0x10, 0x4, false);
     let mod_names = vec!["mod_1", "mod_2", "mod_3"];
-    let objects = read_objects(&base_loc, mod_names);
+    let objects = read_objects(&base_loc, mod_names).unwrap();
     match editor.link(objects, vec![staticlib], NO_WRAP_ROUTINES) {
         Ok((out, info)) => {
+            assert_eq!(Ok(()), out.verify());
             println!("{info:?}");
             assert_eq!(5, info.symbol_tables.len());
             assert_eq!(7, info.global_symtable.len());
-            assert!(info.global_symtable.get(&symbol!("malloc")).is_some());
-            assert!(info.global_symtable.get(&symbol!("printf")).is_some());
-            assert!(info.global_symtable.get(&symbol!("noway")).is_none());
+            let stats = info.stats();
+            println!("{}", stats.ppr());
+            assert_eq!(5, stats.objects);
+            assert_eq!(2, stats.lib_objects);
+            assert_eq!(7, stats.symbols);
+            // library members are linked in as they are in the library
+            for lib_mod in ["libmod_1", "libmod_3"] {
+                let lib_obj = parse_object(&format!("{base_loc}/{lib_mod}")).unwrap();
+                assert_eq!(lib_obj, editor.session_objects[lib_mod]);
+            }
+            assert_eq!(
+                out.segments.get(&SegmentName::TEXT).unwrap().segment_len,
+                stats.segment_sizes[&SegmentName::TEXT]
+            );
+            assert_eq!(
+                out.object_data[&SegmentName::TEXT].crc32(),
+                stats.segment_checksums[&SegmentName::TEXT]
+            );
+            assert!(info.global_symtable.contains_key(&symbol!("malloc")));
+            assert!(info.global_symtable.contains_key(&symbol!("printf")));
+            assert!(!info.global_symtable.contains_key(&symbol!("noway")));
             let text_seg_len = out.segments.get(&SegmentName::TEXT).unwrap().segment_len;
             let data_seg_len = out.segments.get(&SegmentName::DATA).unwrap().segment_len;
             let bss_seg_len = out.segments.get(&SegmentName::BSS).unwrap().segment_len;
@@ -752,12 +1872,129 @@ fn link_with_static_libs() {
                     .get(&SegmentName::TEXT)
                     .unwrap()
             );
+            let ranges = info.address_ranges();
+            assert_eq!(5, ranges.len());
+            assert_eq!(
+                (
+                    text_start + text_seg_len - 0xA - 0x1E,
+                    text_start + text_seg_len - 0xA
+                ),
+                ranges["libmod_1"][&SegmentName::TEXT]
+            );
+            assert_eq!(
+                (text_start + text_seg_len - 0xA, text_start + text_seg_len),
+                ranges["libmod_3"][&SegmentName::TEXT]
+            );
+            let bss_start = out.segments[&SegmentName::BSS].segment_start;
+            assert_eq!(bss_start + bss_seg_len, info.image_size(&out));
             ensure_clean_state(&base_loc);
         }
         Err(e) => panic!("link_with_static_libs: {e:?}"),
     }
 }
 
+#[test]
+fn force_include() {
+    // same as link_with_static_libs, where nothing refers to noway in libmod_2
+    let base_loc = tests_base_loc("force_include");
+    ensure_clean_state(&base_loc);
+    let mut librarian = Librarian::new(false);
+    let lib_objs = vec!["libmod_1", "libmod_2", "libmod_3"];
+    let _ = librarian.build_libdir(Some(&base_loc), None, lib_objs);
+    let lib_loc = PathBuf::from(&base_loc).join(PathBuf::from("staticlib"));
+    let staticlib = read_lib(lib_loc.to_str().unwrap()).unwrap();
+    let mut editor = LinkerEditor::new(0x10, 0x10, 0x4, false);
+    editor.set_force_include(vec![symbol!("noway"), symbol!("nowhere")]);
+    let objects = read_objects(&base_loc, vec!["mod_1", "mod_2", "mod_3"]).unwrap();
+    match editor.link(objects, vec![staticlib], NO_WRAP_ROUTINES) {
+        Ok((out, info)) => {
+            assert_eq!(Ok(()), out.verify());
+            assert_eq!(3, info.stats().lib_objects);
+            assert!(editor.session_objects.contains_key("libmod_2"));
+            assert_eq!(
+                Some(&DefnProvenance::FromObjectIn),
+                info.symbol_provenance(&symbol!("noway"))
+            );
+            // not defined anywhere, and not referred to either
+            assert!(!info.global_symtable.contains_key(&symbol!("nowhere")));
+            ensure_clean_state(&base_loc);
+        }
+        Err(e) => panic!("force_include: {e:?}"),
+    }
+}
+
+#[test]
+fn would_include() {
+    // same as link_with_static_libs: mod_1 and mod_2 need printf from
+    // libmod_1, mod_3 malloc from libmod_3
+    let base_loc = tests_base_loc("would_include");
+    ensure_clean_state_extra(&base_loc, vec!["partial"]);
+    let mut librarian = Librarian::new(false);
+    let _ = librarian.build_libdir(
+        Some(&base_loc),
+        None,
+        vec!["libmod_1", "libmod_2", "libmod_3"],
+    );
+    let _ = librarian.build_libdir(Some(&base_loc), Some("partial"), vec!["libmod_1"]);
+    let lib_loc = PathBuf::from(&base_loc).join(PathBuf::from("staticlib"));
+    let staticlib = read_lib(lib_loc.to_str().unwrap()).unwrap();
+    let partial_loc = PathBuf::from(&base_loc).join(PathBuf::from("partial"));
+    let partial = read_lib(partial_loc.to_str().unwrap()).unwrap();
+    // printf is resolved by libmod_1, malloc is left undefined
+    let mut editor = LinkerEditor::new(0x10, 0x10, 0x4, false);
+    editor.set_undefined_policy(UndefinedPolicy::Permissive);
+    let objects = read_objects(&base_loc, vec!["mod_1", "mod_2", "mod_3"]).unwrap();
+    match editor.link(objects, vec![partial], NO_WRAP_ROUTINES) {
+        Ok((_, info)) => {
+            assert_eq!(1, info.stats().lib_objects);
+            for (lib_mod, included) in
+                [("libmod_1", false), ("libmod_2", false), ("libmod_3", true)]
+            {
+                assert_eq!(
+                    included,
+                    editor.would_include(&info, &staticlib, lib_mod),
+                    "{lib_mod}"
+                );
+            }
+            assert!(!editor.would_include(&info, &staticlib, "nosuchmod"));
+        }
+        Err(e) => panic!("would_include: {e:?}"),
+    }
+    // a member defining MALLOC only satisfies malloc case-insensitively, and
+    // one defining it weakly counts too
+    let mut libmod_3 = read_objects(&base_loc, vec!["libmod_3"]).unwrap();
+    let weak = StaticLib::DirLib {
+        libname: "weaklib".to_string(),
+        symbols: BTreeMap::from([("libmod_3".to_string(), BTreeSet::from([symbol!("malloc")]))]),
+        weak_symbols: BTreeMap::from([(
+            "libmod_3".to_string(),
+            BTreeSet::from([symbol!("malloc")]),
+        )]),
+        objects: libmod_3.clone().into_iter().collect(),
+    };
+    libmod_3.get_mut("libmod_3").unwrap().symbol_table[0].st_name = symbol!("MALLOC");
+    let upper = StaticLib::from_objects("upperlib", libmod_3);
+    for (resolution, included) in [
+        (SymbolResolution::CaseSensitive, false),
+        (SymbolResolution::CaseInsensitive, true),
+    ] {
+        let mut editor = LinkerEditor::new(0x10, 0x10, 0x4, false);
+        editor.set_undefined_policy(UndefinedPolicy::Permissive);
+        editor.set_symbol_resolution(resolution);
+        let objects = read_objects(&base_loc, vec!["mod_3"]).unwrap();
+        let (_, info) = editor
+            .link(objects, NO_STATIC_LIBS, NO_WRAP_ROUTINES)
+            .unwrap();
+        assert_eq!(
+            included,
+            editor.would_include(&info, &upper, "upperlib_mod_0"),
+            "{resolution:?}"
+        );
+        assert!(editor.would_include(&info, &weak, "libmod_3"));
+    }
+    ensure_clean_state_extra(&base_loc, vec!["partial"]);
+}
+
 #[test]
 fn link_with_static_libs_duplicate_symbol() {
     let base_loc = tests_base_loc("link_with_static_libs_duplicate_symbol");
@@ -777,7 +2014,7 @@ fn link_with_static_libs_duplicate_symbol() {
     let staticlib = read_lib(lib_loc.to_str().unwrap()).unwrap();
     let mut editor = LinkerEditor::new(text_start, 0x10, 0x4, false);
     let mod_names = vec!["mod_1"];
-    let objects = read_objects(&base_loc, mod_names);
+    let objects = read_objects(&base_loc, mod_names).unwrap();
     match editor.link(objects, vec![staticlib], NO_WRAP_ROUTINES) {
         Err(e) => assert_eq!(LinkError::MultipleSymbolDefinitions, e),
         Ok(_) => {
@@ -810,7 +2047,7 @@ fn link_with_static_libs_lib_deps() {
     let staticlib2_dir = read_lib(lib2_loc.to_str().unwrap()).unwrap();
     let mut editor = LinkerEditor::new(text_start, 0x10, 0x4, false);
     let mod_names = vec!["mod_1"];
-    let objects = read_objects(&base_loc, mod_names);
+    let objects = read_objects(&base_loc, mod_names).unwrap();
     match editor.link(
         objects,
         vec![staticlib1_dir, staticlib2_dir],
@@ -820,9 +2057,9 @@ fn link_with_static_libs_lib_deps() {
             println!("{info:?}");
             assert_eq!(3, info.symbol_tables.len());
             assert_eq!(3, info.global_symtable.len());
-            assert!(info.global_symtable.get(&symbol!("exec")).is_some());
-            assert!(info.global_symtable.get(&symbol!("printf")).is_some());
-            assert!(info.global_symtable.get(&symbol!("nope")).is_none());
+            assert!(info.global_symtable.contains_key(&symbol!("exec")));
+            assert!(info.global_symtable.contains_key(&symbol!("printf")));
+            assert!(!info.global_symtable.contains_key(&symbol!("nope")));
         }
         Err(e) => panic!("link_with_static_libs_lib_deps: {e:?}"),
     }
@@ -853,7 +2090,7 @@ fn link_with_static_libs_lib_deps_undef() {
     let staticlib2_dir = read_lib(lib2_loc.to_str().unwrap()).unwrap();
     let mut editor = LinkerEditor::new(text_start, 0x10, 0x4, false);
     let mod_names = vec!["mod_1"];
-    let objects = read_objects(&base_loc, mod_names);
+    let objects = read_objects(&base_loc, mod_names).unwrap();
     match editor.link(
         objects,
         vec![staticlib1_dir, staticlib2_dir],
@@ -867,6 +2104,28 @@ fn link_with_static_libs_lib_deps_undef() {
     ensure_clean_state_extra(&base_loc, vec!["staticlib1", "staticlib2"]);
 }
 
+#[test]
+fn used_libraries() {
+    let base_loc = tests_base_loc("used_libraries");
+    ensure_clean_state_extra(&base_loc, vec!["staticlib1", "staticlib2"]);
+
+    let mut librarian = Librarian::new(false);
+    let _ = librarian.build_libdir(Some(&base_loc), Some("staticlib1"), vec!["libmod_1"]);
+    let _ = librarian.build_libdir(Some(&base_loc), Some("staticlib2"), vec!["libmod_2"]);
+    let lib1_loc = PathBuf::from(&base_loc).join(PathBuf::from("staticlib1"));
+    let lib2_loc = PathBuf::from(&base_loc).join(PathBuf::from("staticlib2"));
+    let staticlib1 = read_lib(lib1_loc.to_str().unwrap()).unwrap();
+    let staticlib2 = read_lib(lib2_loc.to_str().unwrap()).unwrap();
+
+    let mut editor = LinkerEditor::new(0x10, 0x10, 0x4, false);
+    let objects = read_objects(&base_loc, vec!["mod_1"]).unwrap();
+    match editor.link(objects, vec![staticlib1, staticlib2], NO_WRAP_ROUTINES) {
+        Ok((_out, info)) => assert_eq!(vec!["staticlib1".to_string()], info.used_libraries()),
+        Err(e) => panic!("used_libraries: {e:?}"),
+    }
+    ensure_clean_state_extra(&base_loc, vec!["staticlib1", "staticlib2"]);
+}
+
 #[test]
 fn link_with_static_libs_single_file() {
     let base_loc = tests_base_loc("link_with_static_libs_single_file");
@@ -886,15 +2145,16 @@ fn link_with_static_libs_single_file() {
     let staticlib = read_lib(lib_loc.to_str().unwrap()).unwrap();
     let mut editor = LinkerEditor::new(text_start, 0x10, 0x4, false);
     let mod_names = vec!["mod_1", "mod_2", "mod_3"];
-    let objects = read_objects(&base_loc, mod_names);
+    let objects = read_objects(&base_loc, mod_names).unwrap();
     match editor.link(objects, vec![staticlib], NO_WRAP_ROUTINES) {
         Ok((out, info)) => {
+            assert_eq!(Ok(()), out.verify());
             println!("{info:?}");
             assert_eq!(5, info.symbol_tables.len());
             assert_eq!(7, info.global_symtable.len());
-            assert!(info.global_symtable.get(&symbol!("malloc")).is_some());
-            assert!(info.global_symtable.get(&symbol!("printf")).is_some());
-            assert!(info.global_symtable.get(&symbol!("noway")).is_none());
+            assert!(info.global_symtable.contains_key(&symbol!("malloc")));
+            assert!(info.global_symtable.contains_key(&symbol!("printf")));
+            assert!(!info.global_symtable.contains_key(&symbol!("noway")));
             let text_seg_len = out.segments.get(&SegmentName::TEXT).unwrap().segment_len;
             let data_seg_len = out.segments.get(&SegmentName::DATA).unwrap().segment_len;
             let bss_seg_len = out.segments.get(&SegmentName::BSS).unwrap().segment_len;
@@ -932,12 +2192,38 @@ fn run_relocations_a4() {
     let mut editor = LinkerEditor::new(0xFF, 0x0, 0x0, false);
     match editor.link(objects, NO_STATIC_LIBS, NO_WRAP_ROUTINES) {
         Ok((out, info)) => {
+            assert_eq!(Ok(()), out.verify());
             println!("{out:?}");
             println!("{info:?}");
             let obj_code_text = out.object_data.get(&SegmentName::TEXT).unwrap();
             assert_eq!(
                 0x14B,
-                x_to_i4(obj_code_text.get_at(0x4, 0x4).unwrap()).unwrap()
+                x_to_i4(&obj_code_text.get_at(0x4, 0x4).unwrap()).unwrap()
+            );
+        }
+        Err(e) => panic!("{testdir} {e:?}"),
+    }
+}
+
+#[test]
+fn run_relocations_a4_addend() {
+    let testdir = tests_base_loc("run_relocations_A4_addend");
+    let objects = read_objects_from_dir(&testdir);
+    let mut editor = LinkerEditor::new(0x10, 0x0, 0x0, false);
+    match editor.link(objects, NO_STATIC_LIBS, NO_WRAP_ROUTINES) {
+        Ok((out, _)) => {
+            assert_eq!(Ok(()), out.verify());
+            let data_start = out.segments[&SegmentName::DATA].segment_start;
+            let obj_code_text = out.object_data.get(&SegmentName::TEXT).unwrap();
+            // .data + 0x10, where DEADBEEF is
+            assert_eq!(
+                data_start + 0x10,
+                x_to_i4(&obj_code_text.get_at(0x4, 0x4).unwrap()).unwrap()
+            );
+            let obj_data = out.object_data.get(&SegmentName::DATA).unwrap();
+            assert_eq!(
+                &[0xDE, 0xAD, 0xBE, 0xEF],
+                &*obj_data.get_at(0x10, 0x4).unwrap()
             );
         }
         Err(e) => panic!("{testdir} {e:?}"),
@@ -951,12 +2237,13 @@ fn run_relocations_r4() {
     let mut editor = LinkerEditor::new(0xFF, 0x0, 0x0, false);
     match editor.link(objects, NO_STATIC_LIBS, NO_WRAP_ROUTINES) {
         Ok((out, info)) => {
+            assert_eq!(Ok(()), out.verify());
             println!("{out:?}");
             println!("{info:?}");
             let obj_code_text = out.object_data.get(&SegmentName::TEXT).unwrap();
             assert_eq!(
                 -36,
-                x_to_i4(obj_code_text.get_at(0xA, 0x4).unwrap()).unwrap()
+                x_to_i4(&obj_code_text.get_at(0xA, 0x4).unwrap()).unwrap()
             );
         }
         Err(e) => panic!("{testdir} {e:?}"),
@@ -970,12 +2257,13 @@ fn run_relocations_as4() {
     let mut editor = LinkerEditor::new(0xFF, 0x0, 0x0, false);
     match editor.link(objects, NO_STATIC_LIBS, NO_WRAP_ROUTINES) {
         Ok((out, info)) => {
+            assert_eq!(Ok(()), out.verify());
             println!("{out:?}");
             println!("{info:?}");
             let obj_code_text = out.object_data.get(&SegmentName::TEXT).unwrap();
             assert_eq!(
                 0xFF,
-                x_to_i4(obj_code_text.get_at(0x10, 0x4).unwrap()).unwrap()
+                x_to_i4(&obj_code_text.get_at(0x10, 0x4).unwrap()).unwrap()
             );
         }
         Err(e) => panic!("{testdir} {e:?}"),
@@ -989,16 +2277,17 @@ fn run_relocation_rs4() {
     let mut editor = LinkerEditor::new(0xFF, 0x0, 0x0, false);
     match editor.link(objects, NO_STATIC_LIBS, NO_WRAP_ROUTINES) {
         Ok((out, info)) => {
+            assert_eq!(Ok(()), out.verify());
             println!("{out:?}");
             println!("{info:?}");
             let obj_code_text = out.object_data.get(&SegmentName::TEXT).unwrap();
             assert_eq!(
                 -34,
-                x_to_i4(obj_code_text.get_at(0x16, 0x4).unwrap()).unwrap()
+                x_to_i4(&obj_code_text.get_at(0x16, 0x4).unwrap()).unwrap()
             );
             assert_eq!(
                 34,
-                x_to_i4(obj_code_text.get_at(0x28, 0x4).unwrap()).unwrap()
+                x_to_i4(&obj_code_text.get_at(0x28, 0x4).unwrap()).unwrap()
             );
         }
         Err(e) => panic!("{testdir} {e:?}"),
@@ -1012,10 +2301,14 @@ fn run_relocation_u2() {
     let mut editor = LinkerEditor::new(0xFF, 0x0, 0x0, false);
     match editor.link(objects, NO_STATIC_LIBS, NO_WRAP_ROUTINES) {
         Ok((out, info)) => {
+            assert_eq!(Ok(()), out.verify());
             println!("{out:?}");
             println!("{info:?}");
             let obj_code_data = out.object_data.get(&SegmentName::DATA).unwrap();
-            assert_eq!(0, x_to_i2(obj_code_data.get_at(0x0, 0x2).unwrap()).unwrap());
+            assert_eq!(
+                0,
+                x_to_i2(&obj_code_data.get_at(0x0, 0x2).unwrap()).unwrap()
+            );
         }
         Err(e) => panic!("{testdir} {e:?}"),
     }
@@ -1028,18 +2321,79 @@ fn run_relocation_l2() {
     let mut editor = LinkerEditor::new(0xFF, 0x0, 0x0, false);
     match editor.link(objects, NO_STATIC_LIBS, NO_WRAP_ROUTINES) {
         Ok((out, info)) => {
+            assert_eq!(Ok(()), out.verify());
             println!("{out:?}");
             println!("{info:?}");
             let obj_code_data = out.object_data.get(&SegmentName::DATA).unwrap();
             assert_eq!(
                 0x011D,
-                x_to_i2(obj_code_data.get_at(0x8, 0x2).unwrap()).unwrap()
+                x_to_i2(&obj_code_data.get_at(0x8, 0x2).unwrap()).unwrap()
             );
         }
         Err(e) => panic!("{testdir} {e:?}"),
     }
 }
 
+#[test]
+fn run_relocation_u2_l2_segment_ref() {
+    let testdir = tests_base_loc("u2_l2_segment_ref");
+    let objects = read_objects_from_dir(&testdir);
+    let obj = &objects["mod_1"];
+    assert_eq!(RelRef::SegmentRef(1), obj.relocations[0].rel_ref);
+    assert_eq!(RelRef::SegmentRef(1), obj.relocations[1].rel_ref);
+    // segment refs keep their S when written back
+    assert!(obj.ppr(true).contains("0 1 S2 U2\n2 1 S2 L2"));
+    assert_eq!(Ok(obj), parse_object_file(obj.ppr(true)).as_ref());
+    let mut editor = LinkerEditor::new(0x12340, 0x0, 0x0, false);
+    match editor.link(objects, NO_STATIC_LIBS, NO_WRAP_ROUTINES) {
+        Ok((out, info)) => {
+            assert_eq!(Ok(()), out.verify());
+            let data_addr = info.segment_mapping["mod_1"][&SegmentName::DATA];
+            assert_eq!(0x12344, data_addr);
+            let obj_code_text = out.object_data.get(&SegmentName::TEXT).unwrap();
+            let upper = x_to_i2(&obj_code_text.get_at(0x0, 0x2).unwrap()).unwrap();
+            let lower = x_to_i2(&obj_code_text.get_at(0x2, 0x2).unwrap()).unwrap();
+            assert_eq!(data_addr, (upper << 16) | lower);
+        }
+        Err(e) => panic!("{testdir} {e:?}"),
+    }
+}
+
+#[test]
+fn run_relocation_segn() {
+    // tbl is in mod_2's .data, cnt in its .bss, here in mod_1's .text
+    let testdir = tests_base_loc("segn");
+    let objects = read_objects_from_dir(&testdir);
+    let mut editor = LinkerEditor::new(0x10, 0x0, 0x0, false);
+    match editor.link(objects, NO_STATIC_LIBS, NO_WRAP_ROUTINES) {
+        Ok((out, info)) => {
+            assert_eq!(Ok(()), out.verify());
+            assert_eq!(Some(&3), info.stats().relocations.get(&RelType::SEGN));
+            let obj_code_text = out.object_data.get(&SegmentName::TEXT).unwrap();
+            for (loc, segn) in [(0x0, 2), (0x4, 3), (0x8, 1)] {
+                assert_eq!(
+                    segn,
+                    x_to_i4(&obj_code_text.get_at(loc, 0x4).unwrap()).unwrap()
+                );
+            }
+            // nothing for the loader to do
+            assert!(out.relocations.is_empty());
+        }
+        Err(e) => panic!("{testdir} {e:?}"),
+    }
+}
+
+#[test]
+fn run_relocation_out_of_range() {
+    let testdir = tests_base_loc("relocation_out_of_range");
+    let objects = read_objects_from_dir(&testdir);
+    let mut editor = LinkerEditor::new(0xFF, 0x0, 0x0, false);
+    match editor.link(objects, NO_STATIC_LIBS, NO_WRAP_ROUTINES) {
+        Err(e) => assert_eq!(LinkError::RelocationOutOfRange, e),
+        Ok(_) => panic!("{testdir}: unexpected Ok"),
+    }
+}
+
 #[test]
 fn wrap_routine() {
     let testdir = tests_base_loc("wrap_routine");
@@ -1048,6 +2402,7 @@ fn wrap_routine() {
     let wrap_routines = vec![symbol!("foo")];
     match editor.link(objects, NO_STATIC_LIBS, wrap_routines) {
         Ok((out, info)) => {
+            assert_eq!(Ok(()), out.verify());
             println!("{out:?}");
             println!("{info:?}");
             assert!(info.global_symtable.contains_key(&wrapped_symbol!("foo")));
@@ -1108,42 +2463,704 @@ fn wrap_routine_error() {
 }
 
 #[test]
-fn position_independent_code() {
-    let testdir = tests_base_loc("position_independent_code");
+fn wrap_routine_error_order() {
+    // the object with wrap_foo comes before the one defining foo
+    let testdir = tests_base_loc("wrap_routine_error_order");
     let objects = read_objects_from_dir(&testdir);
     let mut editor = LinkerEditor::new(0x0, 0x0, 0x0, false);
-    match editor.link(objects, NO_STATIC_LIBS, NO_WRAP_ROUTINES) {
-        Ok((out, info)) => {
-            println!("{out:?}");
-            println!("{info:?}");
-            let obj_code_text = out.object_data.get(&SegmentName::TEXT).unwrap();
+    let wrap_routines = vec![symbol!("foo")];
+    match editor.link(objects, NO_STATIC_LIBS, wrap_routines) {
+        Err(e) => assert_eq!(LinkError::WrappedSymbolNameAlreadyExists, e),
+        Ok(_) => panic!("wrap_routine_error_order unexpected OK"),
+    }
+}
+
+#[test]
+fn empty_text_at_zero() {
+    // mod_1 has an empty .text and a 4 byte .data
+    let testdir = tests_base_loc("data_only");
+    let mut editor = LinkerEditor::new(0x0, 0x0, 0x0, false);
+    let mut logger = Logger::new_test_logger(false);
+    logger.color = false;
+    editor.set_logger(logger);
+    match editor.link(
+        read_objects_from_dir(&testdir),
+        NO_STATIC_LIBS,
+        NO_WRAP_ROUTINES,
+    ) {
+        Ok((out, info)) => {
+            assert_eq!(Ok(()), out.verify());
+            assert_eq!(0, out.segments[&SegmentName::TEXT].segment_len);
+            assert_eq!(0, out.segments[&SegmentName::DATA].segment_start);
+            assert_eq!(0, info.segment_mapping["mod_1"][&SegmentName::DATA]);
+            let warnings: Vec<&str> = editor
+                .logger()
+                .entries()
+                .iter()
+                .filter(|(lvl, _)| matches!(lvl, LogLevel::Warn))
+                .map(|(_, l)| l.as_str())
+                .collect();
+            assert_eq!(
+                vec!["[WARN]: Empty .text shares address 0 with .data"],
+                warnings
+            );
+        }
+        Err(e) => panic!("{testdir} {e:?}"),
+    }
+}
+
+#[test]
+fn wrap_routine_not_found() {
+    let testdir = tests_base_loc("wrap_routine");
+    let mut editor = LinkerEditor::new(0x0, 0x0, 0x0, false);
+    let mut logger = Logger::new_test_logger(false);
+    logger.color = false;
+    editor.set_logger(logger);
+    match editor.link(
+        read_objects_from_dir(&testdir),
+        NO_STATIC_LIBS,
+        vec![symbol!("foo"), symbol!("nonexistent")],
+    ) {
+        Ok((out, info)) => {
+            assert_eq!(Ok(()), out.verify());
+            assert!(info.global_symtable.contains_key(&wrapped_symbol!("foo")));
+            let warnings: Vec<&str> = editor
+                .logger()
+                .entries()
+                .iter()
+                .filter(|(lvl, _)| matches!(lvl, LogLevel::Warn))
+                .map(|(_, l)| l.as_str())
+                .collect();
+            assert_eq!(
+                vec!["[WARN]: Routine to wrap nonexistent is not in any object"],
+                warnings
+            );
+        }
+        Err(e) => panic!("{testdir} {e:?}"),
+    }
+
+    let mut editor = LinkerEditor::new(0x0, 0x0, 0x0, false);
+    editor.set_strict_wrap(true);
+    assert_eq!(
+        Some(LinkError::WrapRoutineNotFound(symbol!("nonexistent"))),
+        editor
+            .link(
+                read_objects_from_dir(&testdir),
+                NO_STATIC_LIBS,
+                vec![symbol!("nonexistent")],
+            )
+            .err()
+    );
+}
+
+#[test]
+fn position_independent_code() {
+    let testdir = tests_base_loc("position_independent_code");
+    let objects = read_objects_from_dir(&testdir);
+    let mut editor = LinkerEditor::new(0x0, 0x0, 0x0, false);
+    match editor.link(objects, NO_STATIC_LIBS, NO_WRAP_ROUTINES) {
+        Ok((out, info)) => {
+            assert_eq!(Ok(()), out.verify());
+            println!("{out:?}");
+            println!("{info:?}");
+            let obj_code_text = out.object_data.get(&SegmentName::TEXT).unwrap();
             assert_eq!(
                 0x24,
-                x_to_i4(obj_code_text.get_at(0x8, 0x4).unwrap()).unwrap()
+                x_to_i4(&obj_code_text.get_at(0x8, 0x4).unwrap()).unwrap()
             );
             assert_eq!(8, out.object_data.get(&SegmentName::GOT).unwrap().len());
             assert_eq!(
                 0x1C,
-                x_to_i4(obj_code_text.get_at(0xC, 0x4).unwrap()).unwrap()
+                x_to_i4(&obj_code_text.get_at(0xC, 0x4).unwrap()).unwrap()
             );
             let obj_code_got = out.object_data.get(&SegmentName::GOT).unwrap();
             assert_eq!(
                 0x3E,
-                x_to_i4(obj_code_got.get_at(0x0, 0x4).unwrap()).unwrap()
+                x_to_i4(&obj_code_got.get_at(0x0, 0x4).unwrap()).unwrap()
             );
             assert_eq!(
                 0x0,
-                x_to_i4(obj_code_text.get_at(0x10, 0x4).unwrap()).unwrap()
+                x_to_i4(&obj_code_text.get_at(0x10, 0x4).unwrap()).unwrap()
             );
             assert_eq!(
                 0x4,
-                x_to_i4(obj_code_text.get_at(0x14, 0x4).unwrap()).unwrap()
+                x_to_i4(&obj_code_text.get_at(0x14, 0x4).unwrap()).unwrap()
             );
             assert_eq!(
                 0xC,
-                x_to_i4(obj_code_text.get_at(0x18, 0x4).unwrap()).unwrap()
+                x_to_i4(&obj_code_text.get_at(0x18, 0x4).unwrap()).unwrap()
             );
             assert_eq!(2, out.relocations.len());
+            let rel_counts = info.stats().relocations;
+            assert_eq!(Some(&2), rel_counts.get(&RelType::GP4));
+            assert_eq!(6, rel_counts.values().sum::<usize>());
+        }
+        Err(e) => panic!("{testdir} {e:?}"),
+    }
+}
+
+#[test]
+fn got_entries() {
+    let testdir = tests_base_loc("position_independent_code");
+    let objects = read_objects_from_dir(&testdir);
+    let mut editor = LinkerEditor::new(0x0, 0x0, 0x0, false);
+    match editor.link(objects, NO_STATIC_LIBS, NO_WRAP_ROUTINES) {
+        Ok((_, info)) => {
+            // GP4s for foo and bar in mod_1, both defined in mod_2's .data
+            let bar_addr = info.global_symtable[&symbol!("bar")].address().unwrap();
+            assert_eq!(
+                vec![(0x0, symbol!("foo"), 0x3E), (0x4, symbol!("bar"), bar_addr)],
+                info.got_entries()
+            );
+            assert!(info
+                .ppr()
+                .contains(&format!("GOT:\n  0 => foo 3E\n  4 => bar {bar_addr:X}")));
+        }
+        Err(e) => panic!("{testdir} {e:?}"),
+    }
+}
+
+#[test]
+fn got_slot_pinned() {
+    // mod_1 pins both GP4s for foo to slot 2, the one for bar is not pinned
+    let testdir = tests_base_loc("got_slot_pinned");
+    let mut editor = LinkerEditor::new(0x0, 0x0, 0x0, false);
+    match editor.link(
+        read_objects_from_dir(&testdir),
+        NO_STATIC_LIBS,
+        NO_WRAP_ROUTINES,
+    ) {
+        Ok((out, info)) => {
+            assert_eq!(Ok(()), out.verify());
+            assert_eq!(0xC, out.segments[&SegmentName::GOT].segment_len);
+            let foo_addr = info.global_symtable[&symbol!("foo")].address().unwrap();
+            let bar_addr = info.global_symtable[&symbol!("bar")].address().unwrap();
+            assert_eq!(
+                vec![
+                    (0x8, symbol!("foo"), foo_addr),
+                    (0x0, symbol!("bar"), bar_addr)
+                ],
+                info.got_entries()
+            );
+            let text = &out.object_data[&SegmentName::TEXT];
+            for (loc, got_off) in [(0x4, 0x8), (0x8, 0x8), (0xC, 0x0)] {
+                assert_eq!(got_off, x_to_i4(&text.get_at(loc, 0x4).unwrap()).unwrap());
+            }
+        }
+        Err(e) => panic!("{testdir} {e:?}"),
+    }
+    // bar pinned to foo's slot as well
+    let mut objects = read_objects_from_dir(&testdir);
+    objects.get_mut("mod_1").unwrap().relocations[2].rel_extra = vec![2];
+    let mut editor = LinkerEditor::new(0x0, 0x0, 0x0, false);
+    match editor.link(objects, NO_STATIC_LIBS, NO_WRAP_ROUTINES) {
+        Err(e) => assert_eq!(LinkError::GotSlotConflict(2), e),
+        Ok(_) => panic!("{testdir}: expected GotSlotConflict"),
+    }
+}
+
+#[test]
+fn got_mode() {
+    // mod_1 has two GP4s, so Auto and Force give the same 8 byte GOT
+    let testdir = tests_base_loc("position_independent_code");
+    for mode in [GotMode::Auto, GotMode::Force] {
+        let mut editor = LinkerEditor::new(0x0, 0x0, 0x0, false);
+        editor.set_got_mode(mode);
+        match editor.link(
+            read_objects_from_dir(&testdir),
+            NO_STATIC_LIBS,
+            NO_WRAP_ROUTINES,
+        ) {
+            Ok((out, _)) => {
+                assert_eq!(Ok(()), out.verify());
+                assert_eq!(8, out.segments[&SegmentName::GOT].segment_len, "{mode:?}");
+            }
+            Err(e) => panic!("{testdir} {mode:?} {e:?}"),
+        }
+    }
+    let mut editor = LinkerEditor::new(0x0, 0x0, 0x0, false);
+    editor.set_got_mode(GotMode::Disable);
+    match editor.link(
+        read_objects_from_dir(&testdir),
+        NO_STATIC_LIBS,
+        NO_WRAP_ROUTINES,
+    ) {
+        Err(e) => assert_eq!(LinkError::GotRequiredButDisabled, e),
+        Ok(_) => panic!("{testdir}: expected GotRequiredButDisabled"),
+    }
+    // without the GP4s, GA4 and GR4 still need a GOT, but have no slots in it
+    for (mode, err) in [
+        (GotMode::Auto, LinkError::NoGlobalOffsetTable),
+        (GotMode::Disable, LinkError::GotRequiredButDisabled),
+    ] {
+        let mut objects = read_objects_from_dir(&testdir);
+        objects
+            .get_mut("mod_1")
+            .unwrap()
+            .relocations
+            .retain(|r| r.rel_type != RelType::GP4);
+        let mut editor = LinkerEditor::new(0x0, 0x0, 0x0, false);
+        editor.set_got_mode(mode);
+        match editor.link(objects, NO_STATIC_LIBS, NO_WRAP_ROUTINES) {
+            Err(e) => assert_eq!(err, e, "{mode:?}"),
+            Ok(_) => panic!("{testdir} {mode:?}: expected {err:?}"),
+        }
+    }
+    // nothing in link_1 needs a GOT, Force gives it an empty one
+    for (mode, has_got) in [
+        (GotMode::Auto, false),
+        (GotMode::Force, true),
+        (GotMode::Disable, false),
+    ] {
+        let mut editor = LinkerEditor::new(0x10, 0x0, 0x0, false);
+        editor.set_got_mode(mode);
+        let (out, _) = editor
+            .link(
+                read_objects_from_dir(&tests_base_loc("link_1")),
+                NO_STATIC_LIBS,
+                NO_WRAP_ROUTINES,
+            )
+            .unwrap();
+        assert_eq!(Ok(()), out.verify());
+        assert_eq!(
+            has_got.then_some(0),
+            out.segments
+                .get(&SegmentName::GOT)
+                .map(|got| got.segment_len),
+            "{mode:?}"
+        );
+    }
+}
+
+#[test]
+fn relocation_ref_out_of_range() {
+    let testdir = tests_base_loc("position_independent_code");
+    let mut objects = read_objects_from_dir(&testdir);
+    // mod_1 has 3 segments, A4 at 0x4 refers to one of them
+    let mod_1 = objects.get_mut("mod_1").unwrap();
+    assert_eq!(RelType::A4, mod_1.relocations[0].rel_type);
+    mod_1.relocations[0].rel_ref = RelRef::SegmentRef(3);
+    let mut editor = LinkerEditor::new(0x0, 0x0, 0x0, false);
+    match editor.link(objects, NO_STATIC_LIBS, NO_WRAP_ROUTINES) {
+        Err(e) => assert_eq!(LinkError::RelocationRefOutOfRange, e),
+        Ok(_) => panic!("{testdir}: expected RelocationRefOutOfRange"),
+    }
+}
+
+#[test]
+fn got_symbol() {
+    let testdir = tests_base_loc("got_symbol");
+    let objects = read_objects_from_dir(&testdir);
+    let mut editor = LinkerEditor::new(0x0, 0x0, 0x0, false);
+    match editor.link(objects, NO_STATIC_LIBS, NO_WRAP_ROUTINES) {
+        Ok((out, info)) => {
+            assert_eq!(Ok(()), out.verify());
+            // TEXT 0..8, GOT 8..C
+            assert_eq!(
+                Some(&DefnProvenance::FromGot),
+                info.symbol_provenance(&symbol!("_GLOBAL_OFFSET_TABLE_"))
+            );
+            let obj_code_text = out.object_data.get(&SegmentName::TEXT).unwrap();
+            assert_eq!(
+                0x8,
+                x_to_i4(&obj_code_text.get_at(0x0, 0x4).unwrap()).unwrap()
+            );
+        }
+        Err(e) => panic!("{testdir} {e:?}"),
+    }
+
+    let testdir = tests_base_loc("got_symbol_no_got");
+    let objects = read_objects_from_dir(&testdir);
+    let mut editor = LinkerEditor::new(0x0, 0x0, 0x0, false);
+    match editor.link(objects, NO_STATIC_LIBS, NO_WRAP_ROUTINES) {
+        Err(e) => assert_eq!(LinkError::NoGlobalOffsetTable, e),
+        Ok(_) => panic!("{testdir}: expected NoGlobalOffsetTable"),
+    }
+}
+
+#[test]
+fn got_segment_pointer() {
+    let testdir = tests_base_loc("got_segment_pointer");
+    let objects = read_objects_from_dir(&testdir);
+    let mut editor = LinkerEditor::new(0x0, 0x0, 0x0, false);
+    match editor.link(objects, NO_STATIC_LIBS, NO_WRAP_ROUTINES) {
+        Ok((out, info)) => {
+            assert_eq!(Ok(()), out.verify());
+            // TEXT 0..10, GOT 10..18, DATA 18..24 (mod_1 at 18, mod_2 at 1C)
+            assert_eq!(
+                0x10,
+                out.segments.get(&SegmentName::GOT).unwrap().segment_start
+            );
+            let data_addr = |m: &str| *info.segment_mapping[m].get(&SegmentName::DATA).unwrap();
+            assert_eq!(0x18, data_addr("mod_1"));
+            assert_eq!(0x1C, data_addr("mod_2"));
+            // GOT slots hold the .data base of each module
+            let obj_code_got = out.object_data.get(&SegmentName::GOT).unwrap();
+            assert_eq!(8, obj_code_got.len());
+            assert_eq!(
+                0x18,
+                x_to_i4(&obj_code_got.get_at(0x0, 0x4).unwrap()).unwrap()
+            );
+            assert_eq!(
+                0x1C,
+                x_to_i4(&obj_code_got.get_at(0x4, 0x4).unwrap()).unwrap()
+            );
+            // referencing locations get the GOT-relative offsets of those slots
+            let obj_code_text = out.object_data.get(&SegmentName::TEXT).unwrap();
+            assert_eq!(
+                0x0,
+                x_to_i4(&obj_code_text.get_at(0x4, 0x4).unwrap()).unwrap()
+            );
+            assert_eq!(
+                0x4,
+                x_to_i4(&obj_code_text.get_at(0x8, 0x4).unwrap()).unwrap()
+            );
+        }
+        Err(e) => panic!("{testdir} {e:?}"),
+    }
+}
+
+#[test]
+fn relocate_only_rebase() {
+    let testdir = tests_base_loc("relocate_only");
+    let objects = read_objects_from_dir(&testdir);
+    let mut editor = LinkerEditor::new(0x100, 0x0, 0x0, false);
+    let (mut out, mut info) = editor
+        .link(objects, NO_STATIC_LIBS, NO_WRAP_ROUTINES)
+        .unwrap();
+    let text_word = |out: &ObjectOut, off: usize| {
+        x_to_i4(
+            &out.object_data[&SegmentName::TEXT]
+                .get_at(off, 0x4)
+                .unwrap(),
+        )
+        .unwrap()
+    };
+    // ER4: 4 + text_start, AS4: address of foo (start of .data)
+    assert_eq!(0x104, text_word(&out, 0x0));
+    assert_eq!(0x108, text_word(&out, 0x4));
+    assert_eq!(1, out.relocations.len());
+
+    // move everything up by 0x1000 and relocate again
+    let delta = 0x1000;
+    editor.set_text_start(0x1100);
+    for seg in out.segments.values_mut() {
+        seg.segment_start += delta;
+    }
+    for addrs in info.segment_mapping.values_mut() {
+        for addr in addrs.values_mut() {
+            *addr += delta;
+        }
+    }
+    for gsym in info.global_symtable.values_mut() {
+        if let Some(addr) = gsym.defn.as_mut().unwrap().defn_addr.as_mut() {
+            *addr += delta;
+        }
+    }
+    editor.relocate_only(&mut out, &info).unwrap();
+    assert_eq!(0x1104, text_word(&out, 0x0));
+    assert_eq!(0x1108, text_word(&out, 0x4));
+    assert_eq!(1, out.relocations.len());
+}
+
+#[test]
+fn load_at_base() {
+    let testdir = tests_base_loc("position_independent_code");
+    let objects = read_objects_from_dir(&testdir);
+    let mut editor = LinkerEditor::new(0x0, 0x0, 0x0, false);
+    let (out, _) = editor
+        .link(objects, NO_STATIC_LIBS, NO_WRAP_ROUTINES)
+        .unwrap();
+    let image = load(&out, 0x4000);
+    // TEXT 0..28, GOT 28..30, DATA 30..42, BSS 42..4A
+    assert_eq!(0x4A, image.len());
+    assert_eq!(&[0xDE, 0xAD, 0xBE, 0xEF], &image[0x0..0x4]);
+    // AS4 at 0x8 was linked to 0x24, ER4 moves it to the load base
+    assert_eq!(0x4024, x_to_i4(&image[0x8..0xC]).unwrap());
+    // GA4 is base independent
+    assert_eq!(0x1C, x_to_i4(&image[0xC..0x10]).unwrap());
+}
+
+#[test]
+fn load_at_base_little_endian() {
+    let testdir = tests_base_loc("position_independent_code");
+    let objects = read_objects_from_dir(&testdir);
+    let mut editor = LinkerEditor::new(0x0, 0x0, 0x0, false);
+    editor.set_endianness(Endianness::LittleEndian);
+    let (out, _) = editor
+        .link(objects, NO_STATIC_LIBS, NO_WRAP_ROUTINES)
+        .unwrap();
+    assert_eq!(Endianness::LittleEndian, out.endianness);
+    let image = load(&out, 0x4000);
+    // same as load_at_base, words the other way round
+    assert_eq!(&[0x24, 0x40, 0x00, 0x00], &image[0x8..0xC]);
+    assert_eq!(&[0x1C, 0x00, 0x00, 0x00], &image[0xC..0x10]);
+}
+
+#[test]
+fn merge_images() {
+    // flat image: one segment per (name, start, data), an ER4 per (name, loc)
+    let mk_image = |segs: Vec<(SegmentName, i32, Vec<u8>)>, er4s: Vec<(SegmentName, i32)>| {
+        let mut out = ObjectOut::new();
+        for (segment_name, segment_start, data) in segs {
+            let mut seg = Segment::new(segment_name.clone());
+            seg.segment_start = segment_start;
+            seg.segment_len = data.len() as i32;
+            out.segments.insert(segment_name.clone(), seg);
+            let mut sd = SegmentData::new(data.len());
+            sd.write_bytes(0, &data).unwrap();
+            out.object_data.insert(segment_name, sd);
+        }
+        out.nsegs = out.segments.len() as i32;
+        for (rel_seg, rel_loc) in er4s {
+            out.relocations.push(Relocation {
+                rel_loc,
+                rel_seg,
+                rel_ref: RelRef::NoRef,
+                rel_type: RelType::ER4,
+                rel_extra: vec![],
+            });
+        }
+        out
+    };
+    let boot = || {
+        mk_image(
+            vec![(
+                SegmentName::TEXT,
+                0x0,
+                vec![0xDE, 0xAD, 0xBE, 0xEF, 0, 0, 0, 0],
+            )],
+            vec![(SegmentName::TEXT, 0x4)],
+        )
+    };
+    let payload = || {
+        mk_image(
+            vec![
+                (
+                    SegmentName::TEXT,
+                    0x0,
+                    vec![0, 0, 0, 0x8, 0xCA, 0xFE, 0xBA, 0xBE],
+                ),
+                (SegmentName::DATA, 0x8, vec![0, 0, 0, 0x4]),
+            ],
+            vec![(SegmentName::TEXT, 0x0), (SegmentName::DATA, 0x0)],
+        )
+    };
+
+    let mut image = boot();
+    image.merge(payload(), 0x10).unwrap();
+    assert_eq!(2, image.nsegs);
+    let text_seg = &image.segments[&SegmentName::TEXT];
+    assert_eq!((0x0, 0x18), (text_seg.segment_start, text_seg.segment_len));
+    assert_eq!(0x18, image.segments[&SegmentName::DATA].segment_start);
+    let rels: Vec<_> = image
+        .relocations
+        .iter()
+        .map(|r| (r.rel_seg.clone(), r.rel_loc, r.rel_type.clone()))
+        .collect();
+    assert_eq!(
+        vec![
+            (SegmentName::TEXT, 0x4, RelType::ER4),
+            (SegmentName::TEXT, 0x10, RelType::ER4),
+            (SegmentName::DATA, 0x0, RelType::ER4),
+        ],
+        rels
+    );
+    // payload addresses moved up by 0x10, the gap is zero-filled
+    let text = &image.object_data[&SegmentName::TEXT];
+    assert_eq!(&[0, 0, 0, 0, 0, 0, 0, 0], &*text.get_at(0x8, 0x8).unwrap());
+    assert_eq!(0x18, x_to_i4(&text.get_at(0x10, 0x4).unwrap()).unwrap());
+    let data = &image.object_data[&SegmentName::DATA];
+    assert_eq!(0x14, x_to_i4(&data.get_at(0x0, 0x4).unwrap()).unwrap());
+
+    let mut image = boot();
+    assert_eq!(
+        Err(LinkError::OverlappingSegments),
+        image.merge(payload(), 0x4)
+    );
+
+    // little endian images: the same words, least significant byte first
+    let le = |mut out: ObjectOut| {
+        for sd in out.object_data.values_mut() {
+            for off in (0..sd.len()).step_by(4) {
+                let v = sd.read_i32(off, Endianness::BigEndian).unwrap();
+                sd.write_i32(off, v, Endianness::LittleEndian).unwrap();
+            }
+        }
+        out.endianness = Endianness::LittleEndian;
+        out
+    };
+    let mut image = le(boot());
+    image.merge(le(payload()), 0x10).unwrap();
+    let text = &image.object_data[&SegmentName::TEXT];
+    assert_eq!(&[0x18, 0, 0, 0], &*text.get_at(0x10, 0x4).unwrap());
+    let data = &image.object_data[&SegmentName::DATA];
+    assert_eq!(&[0x14, 0, 0, 0], &*data.get_at(0x0, 0x4).unwrap());
+    let mut image = boot();
+    assert_eq!(
+        Err(LinkError::EndiannessMismatch),
+        image.merge(le(payload()), 0x10)
+    );
+}
+
+#[test]
+fn relocation_in_bss() {
+    let testdir = tests_base_loc("relocation_in_bss");
+    let objects = read_objects_from_dir(&testdir);
+    let mut editor = LinkerEditor::new(0x0, 0x0, 0x0, false);
+    match editor.link(objects, NO_STATIC_LIBS, NO_WRAP_ROUTINES) {
+        Err(e) => assert_eq!(LinkError::RelocationInBss, e),
+        Ok(_) => panic!("{testdir}: expected RelocationInBss"),
+    }
+}
+
+#[test]
+fn local_symbols() {
+    let testdir = tests_base_loc("local_symbols");
+    let objects = read_objects_from_dir(&testdir);
+    let mut editor = LinkerEditor::new(0x0, 0x0, 0x0, false);
+    match editor.link(objects, NO_STATIC_LIBS, NO_WRAP_ROUTINES) {
+        Ok((out, info)) => {
+            assert_eq!(Ok(()), out.verify());
+            assert!(!info.global_symtable.contains_key(&symbol!("helper")));
+            // each module's AS4 resolves to its own helper: 10 and 14 + 4
+            let obj_code_text = out.object_data.get(&SegmentName::TEXT).unwrap();
+            assert_eq!(
+                0x10,
+                x_to_i4(&obj_code_text.get_at(0x4, 0x4).unwrap()).unwrap()
+            );
+            assert_eq!(
+                0x18,
+                x_to_i4(&obj_code_text.get_at(0x8, 0x4).unwrap()).unwrap()
+            );
+        }
+        Err(e) => panic!("{testdir} {e:?}"),
+    }
+}
+
+#[test]
+fn local_symbols_namespaced() {
+    // mod_1 and mod_2 each have a local tmp, mod_3 a global one
+    let testdir = tests_base_loc("local_tmp_symbols");
+    let mut editor = LinkerEditor::new(0x10, 0x0, 0x0, false);
+    match editor.link(
+        read_objects_from_dir(&testdir),
+        NO_STATIC_LIBS,
+        NO_WRAP_ROUTINES,
+    ) {
+        Ok((_, info)) => {
+            let mod_1_tmp = SymbolName::Local("tmp".to_owned(), "mod_1".to_owned());
+            let mod_2_tmp = SymbolName::Local("tmp".to_owned(), "mod_2".to_owned());
+            assert_ne!(mod_1_tmp, mod_2_tmp);
+            assert_ne!(symbol!("tmp"), mod_1_tmp);
+            assert_eq!("tmp", mod_1_tmp.to_string());
+            for (name, modname) in [
+                (mod_1_tmp, "mod_1"),
+                (mod_2_tmp, "mod_2"),
+                (symbol!("tmp"), "mod_3"),
+            ] {
+                assert_eq!(
+                    Some(info.segment_mapping[modname][&SegmentName::DATA]),
+                    info.global_symtable[&name].address()
+                );
+            }
+        }
+        Err(e) => panic!("{testdir} {e:?}"),
+    }
+}
+
+#[test]
+fn case_insensitive_symbols() {
+    let testdir = tests_base_loc("case_insensitive");
+    let mut editor = LinkerEditor::new(0x10, 0x0, 0x0, false);
+    match editor.link(
+        read_objects_from_dir(&testdir),
+        NO_STATIC_LIBS,
+        NO_WRAP_ROUTINES,
+    ) {
+        Err(e) => assert_eq!(LinkError::UndefinedSymbolError, e),
+        Ok(_) => panic!("{testdir}: 'foo' should not resolve to 'Foo'"),
+    }
+    let mut editor = LinkerEditor::new(0x10, 0x0, 0x0, false);
+    editor.set_symbol_resolution(SymbolResolution::CaseInsensitive);
+    match editor.link(
+        read_objects_from_dir(&testdir),
+        NO_STATIC_LIBS,
+        NO_WRAP_ROUTINES,
+    ) {
+        Ok((out, info)) => {
+            assert_eq!(Ok(()), out.verify());
+            assert_eq!(1, info.global_symtable.len());
+            // the module symbol tables keep the original spelling
+            assert_eq!(symbol!("Foo"), info.symbol_tables["mod_1"][0].st_name);
+            // mod_2's reference to foo is Foo in mod_1
+            let obj_code_text = out.object_data.get(&SegmentName::TEXT).unwrap();
+            assert_eq!(
+                0x10,
+                x_to_i4(&obj_code_text.get_at(0x4, 0x4).unwrap()).unwrap()
+            );
+        }
+        Err(e) => panic!("{testdir} {e:?}"),
+    }
+}
+
+#[test]
+fn segment_alignment() {
+    let testdir = tests_base_loc("segment_align");
+    let objects = read_objects_from_dir(&testdir);
+    assert_eq!(0x10, objects["mod_2"].segments[1].segment_align);
+    let mut editor = LinkerEditor::new(0x0, 0x0, 0x0, false);
+    match editor.link(objects, NO_STATIC_LIBS, NO_WRAP_ROUTINES) {
+        Ok((out, info)) => {
+            assert_eq!(Ok(()), out.verify());
+            // .data starts 16-byte aligned, mod_2's part is padded from 6 to 10
+            let data_seg = out.segments.get(&SegmentName::DATA).unwrap();
+            assert_eq!(0x10, data_seg.segment_start);
+            assert_eq!(0x18, data_seg.segment_len);
+            assert_eq!(0x20, info.segment_mapping["mod_2"][&SegmentName::DATA]);
+            let obj_code_data = out.object_data.get(&SegmentName::DATA).unwrap();
+            assert_eq!(0x18, obj_code_data.len());
+            assert_eq!(&[0x0; 0xA], &*obj_code_data.get_at(0x6, 0xA).unwrap());
+            assert_eq!(0x11, obj_code_data[0x10]);
+            // AS4 to vec, the start of mod_2's .data
+            let obj_code_text = out.object_data.get(&SegmentName::TEXT).unwrap();
+            assert_eq!(
+                0x20,
+                x_to_i4(&obj_code_text.get_at(0x4, 0x4).unwrap()).unwrap()
+            );
+        }
+        Err(e) => panic!("{testdir} {e:?}"),
+    }
+}
+
+#[test]
+fn got_after_data() {
+    let testdir = tests_base_loc("position_independent_code");
+    let objects = read_objects_from_dir(&testdir);
+    let mut editor = LinkerEditor::new(0x0, 0x0, 0x0, false);
+    editor
+        .set_got_layout(GotPlacement::AfterData, 0x10)
+        .unwrap();
+    match editor.link(objects, NO_STATIC_LIBS, NO_WRAP_ROUTINES) {
+        Ok((out, _info)) => {
+            assert_eq!(Ok(()), out.verify());
+            let seg_start = |s: SegmentName| out.segments.get(&s).unwrap().segment_start;
+            // TEXT 0..28, DATA 28..3A, GOT aligned to 40..48, BSS right after GOT
+            assert_eq!(0x0, seg_start(SegmentName::TEXT));
+            assert_eq!(0x28, seg_start(SegmentName::DATA));
+            assert_eq!(0x40, seg_start(SegmentName::GOT));
+            assert_eq!(0x48, seg_start(SegmentName::BSS));
+            let obj_code_text = out.object_data.get(&SegmentName::TEXT).unwrap();
+            // GA4: distance from the reference to the GOT
+            assert_eq!(
+                0x34,
+                x_to_i4(&obj_code_text.get_at(0xC, 0x4).unwrap()).unwrap()
+            );
+            // GR4: mod_1 data + 4 relative to the GOT
+            assert_eq!(
+                -0x14,
+                x_to_i4(&obj_code_text.get_at(0x18, 0x4).unwrap()).unwrap()
+            );
         }
         Err(e) => panic!("{testdir} {e:?}"),
     }
@@ -1223,6 +3240,79 @@ fn parse_lib_stub_to_from() {
     // ensure_clean_state(&testdir);
 }
 
+#[test]
+fn stub_lib_roundtrip() {
+    let mut stub_lib0 = StubLib::new("my_lib".to_string());
+    stub_lib0.deps.push("other_lib".to_string());
+    let mut syms = BTreeMap::new();
+    syms.insert(symbol!("foo"), Left(0x40));
+    syms.insert(symbol!("bar"), Left(0x50));
+    syms.insert(symbol!("printf"), Right("other_lib".to_string()));
+    let member = StubMember::new("my_lib".to_string(), "mod_1_stub".to_string(), syms);
+    stub_lib0.members.insert(member.name.clone(), member);
+    stub_lib0.defs.insert(
+        "mod_1_stub".to_string(),
+        vec![symbol!("bar"), symbol!("foo")],
+    );
+
+    let tmp_dir = std::env::temp_dir().join(format!(
+        "linkerloader_stub_roundtrip_{}",
+        std::process::id()
+    ));
+    let _ = fs::remove_dir_all(&tmp_dir);
+    fs::create_dir_all(&tmp_dir).unwrap();
+    let basepath = tmp_dir.to_str().unwrap();
+    stub_lib0.write_to_disk(Some(basepath), None).unwrap();
+    let stub_lib1 = StubLib::parse(&format!("{basepath}/stublib"));
+    fs::remove_dir_all(&tmp_dir).unwrap();
+    assert_eq!(stub_lib0, stub_lib1.unwrap());
+}
+
+#[test]
+fn read_objects_missing_object() {
+    let base_loc = tests_base_loc("link_1");
+    let obj_names: Vec<String> = vec!["object_1".to_string(), "object_4".to_string()];
+    let missing = PathBuf::from(&base_loc).join("object_4");
+    assert_eq!(
+        Some(ParseError::FileReadError(
+            missing.to_string_lossy().into_owned()
+        )),
+        read_objects(&base_loc, obj_names).err()
+    );
+    let objects = read_objects(&base_loc, ["object_1".to_string()]).unwrap();
+    assert!(objects.contains_key("object_1"));
+}
+
+#[test]
+fn unreadable_member() {
+    let tmp_dir = std::env::temp_dir().join(format!(
+        "linkerloader_unreadable_member_{}",
+        std::process::id()
+    ));
+    let _ = fs::remove_dir_all(&tmp_dir);
+    fs::create_dir_all(&tmp_dir).unwrap();
+    // not UTF-8, so read_to_string fails on it
+    let member = tmp_dir.join("mod_1");
+    fs::write(&member, [0x4C, 0x49, 0x4E, 0x4B, 0xFF, 0xFE]).unwrap();
+    let dirname = tmp_dir.to_str().unwrap();
+    let member_path = member.to_str().unwrap().to_owned();
+    let objects = read_objects(dirname, vec!["mod_1"]);
+    let filtered = read_objects_from_dir_filtered(dirname, |_| true);
+    let lib = read_lib(dirname);
+    let missing_lib = read_lib(tmp_dir.join("nolib").to_str().unwrap());
+    fs::remove_dir_all(&tmp_dir).unwrap();
+    assert_eq!(
+        Some(ParseError::FileReadError(member_path.clone())),
+        objects.err()
+    );
+    assert_eq!(
+        Some(ParseError::FileReadError(member_path.clone())),
+        filtered.err()
+    );
+    assert_eq!(Some(LibError::FileReadError(member_path)), lib.err());
+    assert!(matches!(missing_lib, Err(LibError::FileReadError(_))));
+}
+
 #[test]
 fn static_shared_libs() {
     let testdir = tests_base_loc("static_shared_libs");
@@ -1241,15 +3331,13 @@ fn static_shared_libs() {
     // now build shared lib (linked obj and stubs)
     let staticlib1 = format!("{testdir}/staticlib1");
     let libdeps1 = vec![];
-    match librarian.build_static_shared_lib(&staticlib1, libdeps1, 0x64) {
-        Err(e) => panic!("{testdir} {e:?}"),
-        Ok(_) => {}
+    if let Err(e) = librarian.build_static_shared_lib(&staticlib1, libdeps1, 0x64) {
+        panic!("{testdir} {e:?}");
     }
     let staticlib2 = format!("{testdir}/staticlib2");
     let libdeps2 = vec![format!("{staticlib1}/stublib")];
-    match librarian.build_static_shared_lib(&staticlib2, libdeps2, 0xC8) {
-        Err(e) => panic!("{testdir} {e:?}"),
-        Ok(_) => {}
+    if let Err(e) = librarian.build_static_shared_lib(&staticlib2, libdeps2, 0xC8) {
+        panic!("{testdir} {e:?}");
     }
 
     // build project with shared libs
@@ -1261,19 +3349,866 @@ fn static_shared_libs() {
     let mut editor = LinkerEditor::new(0xFF, 0x0, 0x0, false);
     match editor.link(objects, staticlibs, NO_WRAP_ROUTINES) {
         Ok((out, info)) => {
+            assert_eq!(Ok(()), out.verify());
             println!("{out:?}");
             println!("{info:?}");
             let obj_code_text = out.object_data.get(&SegmentName::TEXT).unwrap();
             assert_eq!(
                 0xD4,
-                x_to_i4(obj_code_text.get_at(0x8, 0x4).unwrap()).unwrap()
+                x_to_i4(&obj_code_text.get_at(0x8, 0x4).unwrap()).unwrap()
             );
             assert_eq!(
                 0x70,
-                x_to_i4(obj_code_text.get_at(0xC, 0x4).unwrap()).unwrap()
+                x_to_i4(&obj_code_text.get_at(0xC, 0x4).unwrap()).unwrap()
             );
         }
         Err(e) => panic!("{testdir} {e:?}"),
     }
     ensure_clean_state_extra(&testdir, vec!["staticlib1", "staticlib2", "staticlib3"]);
 }
+
+#[test]
+fn weak_lib_symbols() {
+    let testdir = tests_base_loc("weak_symbols");
+    let libc = || read_lib(&format!("{testdir}/libc")).unwrap();
+    match libc() {
+        StaticLib::DirLib {
+            symbols,
+            weak_symbols,
+            ..
+        } => {
+            assert!(symbols["libmemcpy"].contains(&symbol!("memcpy")));
+            assert_eq!(
+                BTreeMap::from([("libmemcpy".to_owned(), [symbol!("memcpy")].into())]),
+                weak_symbols
+            );
+        }
+        _ => panic!("{testdir}/libc: expected StaticLib::DirLib"),
+    }
+
+    // libbar needs memcpy, mod_1 has it: the weak libmemcpy stays out
+    let mut editor = LinkerEditor::new(0x10, 0x0, 0x0, false);
+    match editor.link(
+        read_objects_from_dir(&format!("{testdir}/strong")),
+        vec![libc()],
+        NO_WRAP_ROUTINES,
+    ) {
+        Ok((out, info)) => {
+            assert_eq!(Ok(()), out.verify());
+            assert_eq!(1, info.stats().lib_objects);
+            assert!(info.segment_mapping.contains_key("libbar"));
+            assert!(!info.segment_mapping.contains_key("libmemcpy"));
+            let text = out.object_data.get(&SegmentName::TEXT).unwrap();
+            assert_eq!(0x14, x_to_i4(&text.get_at(0x8, 0x4).unwrap()).unwrap());
+        }
+        Err(e) => panic!("{testdir}/strong {e:?}"),
+    }
+
+    // nothing else defines memcpy, so the weak member is the fallback
+    let mut editor = LinkerEditor::new(0x10, 0x0, 0x0, false);
+    match editor.link(
+        read_objects_from_dir(&format!("{testdir}/fallback")),
+        vec![libc()],
+        NO_WRAP_ROUTINES,
+    ) {
+        Ok((out, info)) => {
+            assert_eq!(Ok(()), out.verify());
+            assert_eq!(2, info.stats().lib_objects);
+            assert!(info.segment_mapping.contains_key("libmemcpy"));
+            let gsym = info.global_symtable.get(&symbol!("memcpy")).unwrap();
+            assert_eq!(
+                Some("libmemcpy"),
+                gsym.defn.as_ref().map(|d| d.defn_mod_id.as_str())
+            );
+        }
+        Err(e) => panic!("{testdir}/fallback {e:?}"),
+    }
+}
+
+#[test]
+fn lib_search_groups() {
+    let testdir = tests_base_loc("lib_groups");
+    // liba_1 needs b_sym from libb, whose libb_1 needs a_2 back from liba
+    let libs = || {
+        vec![
+            read_lib(&format!("{testdir}/liba")).unwrap(),
+            read_lib(&format!("{testdir}/libb")).unwrap(),
+        ]
+    };
+    let mut editor = LinkerEditor::new(0x10, 0x0, 0x0, false);
+    editor.set_lib_search(LibSearch::InOrder(vec![]));
+    match editor.link(
+        read_objects(&testdir, ["mod_1"]).unwrap(),
+        libs(),
+        NO_WRAP_ROUTINES,
+    ) {
+        Err(e) => assert_eq!(LinkError::UndefinedSymbolError, e),
+        Ok(_) => panic!("{testdir}: liba is not searched again for a_2"),
+    }
+    for lib_search in [
+        LibSearch::InOrder(std::iter::once(0..2).collect()),
+        LibSearch::Grouped,
+    ] {
+        let mut editor = LinkerEditor::new(0x10, 0x0, 0x0, false);
+        editor.set_lib_search(lib_search.clone());
+        match editor.link(
+            read_objects(&testdir, ["mod_1"]).unwrap(),
+            libs(),
+            NO_WRAP_ROUTINES,
+        ) {
+            Ok((_, info)) => {
+                assert_eq!(4, info.global_symtable.len());
+                assert_eq!(3, info.stats().lib_objects);
+            }
+            Err(e) => panic!("{testdir} {lib_search:?}: {e:?}"),
+        }
+    }
+}
+
+#[test]
+fn link_assertions() {
+    let testdir = tests_base_loc("link_1");
+    let mut editor = LinkerEditor::new(0x0, 0x0, 0x4, false);
+    editor.assert_segment_max_size(SegmentName::TEXT, 0x40);
+    editor.add_assertion(
+        |info, out| info.segment_mapping.len() == 3 && out.segments.len() == 3,
+        "three objects, three segments".to_owned(),
+    );
+    match editor.link(
+        read_objects_from_dir(&testdir),
+        NO_STATIC_LIBS,
+        NO_WRAP_ROUTINES,
+    ) {
+        Ok((out, _)) => assert_eq!(0x3C, out.segments[&SegmentName::TEXT].segment_len),
+        Err(e) => panic!("{testdir} {e:?}"),
+    }
+    let mut editor = LinkerEditor::new(0x0, 0x0, 0x4, false);
+    editor.assert_segment_max_size(SegmentName::TEXT, 0x20);
+    assert_eq!(
+        Some(LinkError::AssertionFailed(
+            ".text is larger than 0x20 bytes".to_owned()
+        )),
+        editor
+            .link(
+                read_objects_from_dir(&testdir),
+                NO_STATIC_LIBS,
+                NO_WRAP_ROUTINES,
+            )
+            .err()
+    );
+    // dry runs check them too
+    let testdir = tests_base_loc("deferred_relocations");
+    let mut editor = LinkerEditor::new(0x10, 0x0, 0x0, false);
+    editor.set_undefined_policy(UndefinedPolicy::Permissive);
+    editor.assert_symbol_defined(symbol!("ext"));
+    assert_eq!(
+        Some(LinkError::AssertionFailed("ext is not defined".to_owned())),
+        editor
+            .link_dry_run(
+                read_objects_from_dir(&testdir),
+                NO_STATIC_LIBS,
+                NO_WRAP_ROUTINES,
+            )
+            .err()
+    );
+}
+
+#[test]
+fn link_progress() {
+    let testdir = tests_base_loc("link_1");
+    let seen = Rc::new(RefCell::new(vec![]));
+    let mut editor = LinkerEditor::new(0x0, 0x0, 0x4, false);
+    let recorder = Rc::clone(&seen);
+    editor.set_on_progress(move |phase, done, total| {
+        recorder.borrow_mut().push((phase, done, total))
+    });
+    if let Err(e) = editor.link(
+        read_objects_from_dir(&testdir),
+        NO_STATIC_LIBS,
+        NO_WRAP_ROUTINES,
+    ) {
+        panic!("{testdir} {e:?}");
+    }
+    assert_eq!(
+        vec![
+            (LinkPhase::Allocation, 0, 3),
+            (LinkPhase::Allocation, 1, 3),
+            (LinkPhase::Allocation, 2, 3),
+            (LinkPhase::Allocation, 3, 3),
+            // a single search unit, libraries or not
+            (LinkPhase::LibraryResolution, 0, 1),
+            (LinkPhase::LibraryResolution, 1, 1),
+            (LinkPhase::Relocation, 0, 3),
+            (LinkPhase::Relocation, 1, 3),
+            (LinkPhase::Relocation, 2, 3),
+            (LinkPhase::Relocation, 3, 3),
+        ],
+        *seen.borrow()
+    );
+    // nothing to relocate in a dry run
+    seen.borrow_mut().clear();
+    let mut editor = LinkerEditor::new(0x0, 0x0, 0x4, false);
+    let recorder = Rc::clone(&seen);
+    editor.set_on_progress(move |phase, _, _| recorder.borrow_mut().push((phase, 0, 0)));
+    assert!(editor
+        .link_dry_run(
+            read_objects_from_dir(&testdir),
+            NO_STATIC_LIBS,
+            NO_WRAP_ROUTINES,
+        )
+        .is_ok());
+    assert!(!seen
+        .borrow()
+        .iter()
+        .any(|(phase, _, _)| *phase == LinkPhase::Relocation));
+}
+
+#[test]
+fn segment_permission_conflict() {
+    // mod_1 has a read-only .data, mod_2 a writable one
+    let testdir = tests_base_loc("segment_permission_conflict");
+    let mut editor = LinkerEditor::new(0x10, 0x0, 0x0, false);
+    match editor.link(
+        read_objects_from_dir(&testdir),
+        NO_STATIC_LIBS,
+        NO_WRAP_ROUTINES,
+    ) {
+        Ok((out, _)) => assert_eq!("RPW", out.segments[&SegmentName::DATA].ppr_seg_descr()),
+        Err(e) => panic!("{testdir} {e:?}"),
+    }
+    let mut editor = LinkerEditor::new(0x10, 0x0, 0x0, false);
+    editor.set_strict_segment_permissions(true);
+    assert_eq!(
+        Some(LinkError::SegmentPermissionConflict(SegmentName::DATA)),
+        editor
+            .link(
+                read_objects_from_dir(&testdir),
+                NO_STATIC_LIBS,
+                NO_WRAP_ROUTINES,
+            )
+            .err()
+    );
+}
+
+#[test]
+fn split_segment_permissions() {
+    // mod_1's .data (msg) is read-only, mod_2's (counter) writable
+    let testdir = tests_base_loc("split_segment_permissions");
+    let mut editor = LinkerEditor::new(0x10, 0x0, 0x0, false);
+    editor.set_split_segment_permissions(true);
+    match editor.link(
+        read_objects_from_dir(&testdir),
+        NO_STATIC_LIBS,
+        NO_WRAP_ROUTINES,
+    ) {
+        Ok((out, info)) => {
+            assert_eq!(Ok(()), out.verify());
+            let rodata = &out.segments[&SegmentName::RODATA];
+            assert_eq!(
+                (0x20, 0x4, "RP"),
+                (
+                    rodata.segment_start,
+                    rodata.segment_len,
+                    rodata.ppr_seg_descr().as_str()
+                )
+            );
+            let data = &out.segments[&SegmentName::DATA];
+            assert_eq!(
+                (0x24, 0x4, "RWP"),
+                (
+                    data.segment_start,
+                    data.segment_len,
+                    data.ppr_seg_descr().as_str()
+                )
+            );
+            assert_eq!(Some(0x20), info.global_symtable[&symbol!("msg")].address());
+            assert_eq!(
+                Some(0x24),
+                info.global_symtable[&symbol!("counter")].address()
+            );
+            let text = &out.object_data[&SegmentName::TEXT];
+            assert_eq!(0x20, x_to_i4(&text.get_at(0x8, 0x4).unwrap()).unwrap());
+            assert_eq!(0x24, x_to_i4(&text.get_at(0xC, 0x4).unwrap()).unwrap());
+            // mod_1's A4 in its .data went along with it
+            let rodata = &out.object_data[&SegmentName::RODATA];
+            assert_eq!(0x10, x_to_i4(&rodata.get_at(0x0, 0x4).unwrap()).unwrap());
+        }
+        Err(e) => panic!("{testdir} {e:?}"),
+    }
+    // merged by default
+    let mut editor = LinkerEditor::new(0x10, 0x0, 0x0, false);
+    let (out, _) = editor
+        .link(
+            read_objects_from_dir(&testdir),
+            NO_STATIC_LIBS,
+            NO_WRAP_ROUTINES,
+        )
+        .unwrap();
+    assert!(!out.segments.contains_key(&SegmentName::RODATA));
+    assert_eq!(0x8, out.segments[&SegmentName::DATA].segment_len);
+}
+
+#[test]
+fn deferred_relocations() {
+    let testdir = tests_base_loc("deferred_relocations");
+    let mut editor = LinkerEditor::new(0x10, 0x0, 0x0, false);
+    match editor.link(
+        read_objects_from_dir(&testdir),
+        NO_STATIC_LIBS,
+        NO_WRAP_ROUTINES,
+    ) {
+        Err(e) => assert_eq!(LinkError::UndefinedSymbolError, e),
+        Ok(_) => panic!("{testdir}: 'ext' is undefined"),
+    }
+    let mut editor = LinkerEditor::new(0x10, 0x0, 0x0, false);
+    editor.set_undefined_policy(UndefinedPolicy::Permissive);
+    match editor.link(
+        read_objects_from_dir(&testdir),
+        NO_STATIC_LIBS,
+        NO_WRAP_ROUTINES,
+    ) {
+        Ok((out, info)) => {
+            assert_eq!(Ok(()), out.verify());
+            assert_eq!(1, info.deferred_relocations.len());
+            let (modname, r) = &info.deferred_relocations[0];
+            assert_eq!("mod_1", modname);
+            assert_eq!(RelType::AS4, r.rel_type);
+            assert_eq!(
+                "Deferred relocations:\n  mod_1: AS4 at .text+0x0 (ext)",
+                info.deferred_relocations_report()
+            );
+            // the reference to ext is left as it was
+            let obj_code_text = out.object_data.get(&SegmentName::TEXT).unwrap();
+            assert_eq!(
+                0,
+                x_to_i4(&obj_code_text.get_at(0x0, 0x4).unwrap()).unwrap()
+            );
+            let stats = info.stats();
+            assert_eq!(BTreeMap::from([(RelType::A4, 1)]), stats.relocations);
+            assert_eq!(1, stats.er4_relocations);
+            assert_eq!(1, stats.deferred_relocations);
+            assert_eq!(out.relocations.len(), stats.er4_relocations);
+        }
+        Err(e) => panic!("{testdir} {e:?}"),
+    }
+}
+
+#[test]
+fn common_only_object() {
+    let testdir = tests_base_loc("common_only");
+    let mut editor = LinkerEditor::new(0x10, 0x0, 0x0, false);
+    match editor.link(
+        read_objects_from_dir(&testdir),
+        NO_STATIC_LIBS,
+        NO_WRAP_ROUTINES,
+    ) {
+        Ok((out, info)) => {
+            assert_eq!(Ok(()), out.verify());
+            // mod_1 has no segments at all, mod_2 just .text
+            assert!(info.segment_mapping["mod_1"].is_empty());
+            assert_eq!(Some(&0x10), info.common_block_mapping.get(&symbol!("buf")));
+            // BSS follows .text, and is all common block
+            let bss = out.segments.get(&SegmentName::BSS).unwrap();
+            assert_eq!(0x14, bss.segment_start);
+            assert_eq!(0x10, bss.segment_len);
+            assert_eq!(Some(0x14), info.global_symtable[&symbol!("buf")].address());
+            assert_eq!(
+                Some(&DefnProvenance::FromCommonBlock),
+                info.symbol_provenance(&symbol!("buf"))
+            );
+            let obj_code_text = out.object_data.get(&SegmentName::TEXT).unwrap();
+            assert_eq!(
+                0x14,
+                x_to_i4(&obj_code_text.get_at(0x0, 0x4).unwrap()).unwrap()
+            );
+        }
+        Err(e) => panic!("{testdir} {e:?}"),
+    }
+}
+
+#[test]
+fn verify_output() {
+    let testdir = tests_base_loc("link_1");
+    let link = || {
+        let mut editor = LinkerEditor::new(0x10, 0x10, 0x4, false);
+        let (out, _) = editor
+            .link(
+                read_objects_from_dir(&testdir),
+                NO_STATIC_LIBS,
+                NO_WRAP_ROUTINES,
+            )
+            .unwrap();
+        out
+    };
+    assert_eq!(Ok(()), link().verify());
+
+    let mut out = link();
+    out.nsegs += 1;
+    assert_eq!(Err(LinkError::SegmentCountMismatch), out.verify());
+
+    let mut out = link();
+    out.object_data.remove(&SegmentName::TEXT);
+    assert_eq!(
+        Err(LinkError::SegmentDataMismatch(SegmentName::TEXT)),
+        out.verify()
+    );
+
+    let mut out = link();
+    out.segments
+        .get_mut(&SegmentName::DATA)
+        .unwrap()
+        .segment_len += 1;
+    assert_eq!(
+        Err(LinkError::SegmentDataMismatch(SegmentName::DATA)),
+        out.verify()
+    );
+
+    // BSS may be longer than its data, by the common blocks
+    let mut out = link();
+    out.segments.get_mut(&SegmentName::BSS).unwrap().segment_len += 8;
+    assert_eq!(Ok(()), out.verify());
+
+    let mut out = link();
+    let text_start = out.segments[&SegmentName::TEXT].segment_start;
+    out.segments
+        .get_mut(&SegmentName::DATA)
+        .unwrap()
+        .segment_start = text_start;
+    assert_eq!(Err(LinkError::OverlappingSegments), out.verify());
+
+    let mut out = link();
+    out.relocations.push(Relocation {
+        rel_loc: 0,
+        rel_seg: SegmentName::GOT,
+        rel_ref: RelRef::NoRef,
+        rel_type: RelType::ER4,
+        rel_extra: vec![],
+    });
+    assert_eq!(Err(LinkError::RelocationOutOfRange), out.verify());
+}
+
+#[test]
+fn common_block_relative_ref() {
+    let testdir = tests_base_loc("common_block_rs4");
+    let obj = parse_object(&format!("{testdir}/mod_1")).unwrap();
+    // the AS4 to buf is fine, the RS4 is not
+    assert_eq!(
+        vec!["RS4 at .text+0x0 refers to common block 'buf'".to_string()],
+        common_block_ref_warnings(&obj.relocations, &obj.symbol_table)
+    );
+    // only a warning: the link still goes through
+    let mut editor = LinkerEditor::new(0x10, 0x0, 0x0, false);
+    editor.set_warn_common_block_refs(true);
+    match editor.link(
+        read_objects_from_dir(&testdir),
+        NO_STATIC_LIBS,
+        NO_WRAP_ROUTINES,
+    ) {
+        Ok((out, info)) => {
+            assert_eq!(Ok(()), out.verify());
+            assert_eq!(2, info.stats().relocations.values().sum::<usize>());
+        }
+        Err(e) => panic!("{testdir} {e:?}"),
+    }
+}
+
+#[test]
+fn diff_images() {
+    let testdir = tests_base_loc("link_1");
+    let link = || {
+        let mut editor = LinkerEditor::new(0x10, 0x10, 0x4, false);
+        let (out, _) = editor
+            .link(
+                read_objects_from_dir(&testdir),
+                NO_STATIC_LIBS,
+                NO_WRAP_ROUTINES,
+            )
+            .unwrap();
+        out
+    };
+    let out = link();
+    let mut copy = link();
+    assert_eq!(Vec::<SegmentDiff>::new(), out.diff(&copy));
+    let text = copy.object_data.get_mut(&SegmentName::TEXT).unwrap();
+    let old = text.get_at(2, 1).unwrap()[0];
+    text.write_bytes(2, &[!old]).unwrap();
+    copy.segments
+        .get_mut(&SegmentName::BSS)
+        .unwrap()
+        .segment_len += 4;
+    let bss = &out.segments[&SegmentName::BSS];
+    assert_eq!(
+        vec![
+            SegmentDiff::Byte {
+                segment_name: SegmentName::TEXT,
+                offset: 2,
+                old: Some(old),
+                new: Some(!old),
+            },
+            SegmentDiff::Layout {
+                segment_name: SegmentName::BSS,
+                old: (bss.segment_start, bss.segment_len),
+                new: (bss.segment_start, bss.segment_len + 4),
+            },
+        ],
+        out.diff(&copy)
+    );
+}
+
+#[test]
+fn write_output() {
+    let testdir = tests_base_loc("link_1");
+    let mut editor = LinkerEditor::new(0x10, 0x10, 0x4, false);
+    let (out, _) = editor
+        .link(
+            read_objects_from_dir(&testdir),
+            NO_STATIC_LIBS,
+            NO_WRAP_ROUTINES,
+        )
+        .unwrap();
+    let dir = std::env::temp_dir().join(format!("linkerloader_output_{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let path = dir.join(out_file_name("a")).to_str().unwrap().to_owned();
+    let mut librarian = Librarian::new(false);
+    librarian
+        .write_output(&out, &path, OutputFormat::LinkObject)
+        .unwrap();
+    let obj = parse_object(&path).unwrap();
+    assert_eq!(out.segments.len(), obj.segments.len());
+    assert_eq!(out.object_data[&SegmentName::TEXT], obj.object_data[0]);
+    // output is not taken for one of the objects to link
+    assert!(read_objects_from_dir(dir.to_str().unwrap()).is_empty());
+    librarian
+        .write_output(&out, &path, OutputFormat::FlatBinary)
+        .unwrap();
+    assert_eq!(load(&out, 0), fs::read(&path).unwrap());
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[cfg(feature = "elf")]
+#[test]
+fn write_elf() {
+    let testdir = tests_base_loc("link_1");
+    let mut editor = LinkerEditor::new(0x1000, 0x1000, 0x4, false);
+    let (out, _) = editor
+        .link(
+            read_objects_from_dir(&testdir),
+            NO_STATIC_LIBS,
+            NO_WRAP_ROUTINES,
+        )
+        .unwrap();
+    let path = std::env::temp_dir().join(format!("linkerloader_elf_{}", std::process::id()));
+    out.write_elf(&path, 0x1000, Endianness::BigEndian).unwrap();
+    let elf = fs::read(&path).unwrap();
+    fs::remove_file(&path).unwrap();
+    // 32-bit, big endian
+    assert_eq!(&[0x7F, b'E', b'L', b'F', 1, 2], &elf[..6]);
+    let u16_at = |off: usize| u16::from_be_bytes([elf[off], elf[off + 1]]);
+    let u32_at = |off: usize| u32::from_be_bytes(elf[off..off + 4].try_into().unwrap());
+    assert_eq!(0x1000, u32_at(24)); // e_entry
+                                    // one program header each for .text, .data and .bss
+    assert_eq!(3, u16_at(44));
+    let text = &out.segments[&SegmentName::TEXT];
+    let phdr = 52;
+    assert_eq!(1, u32_at(phdr)); // PT_LOAD
+    assert_eq!(text.segment_start as u32, u32_at(phdr + 8)); // p_vaddr
+    assert_eq!(text.segment_len as u32, u32_at(phdr + 16)); // p_filesz
+    assert_eq!(5, u32_at(phdr + 24)); // R + X
+    let text_off = u32_at(phdr + 4) as usize;
+    assert_eq!(
+        out.object_data[&SegmentName::TEXT].as_slice(),
+        &elf[text_off..text_off + text.segment_len as usize]
+    );
+    // .bss takes no room in the file
+    let bss = phdr + 2 * 32;
+    assert_eq!(0, u32_at(bss + 16));
+    assert_eq!(
+        out.segments[&SegmentName::BSS].segment_len as u32,
+        u32_at(bss + 20)
+    );
+}
+
+#[cfg(feature = "elf")]
+#[test]
+fn write_output_elf_little_endian() {
+    let testdir = tests_base_loc("link_1");
+    let mut editor = LinkerEditor::new(0x1000, 0x1000, 0x4, false);
+    editor.set_endianness(Endianness::LittleEndian);
+    let (out, _) = editor
+        .link(
+            read_objects_from_dir(&testdir),
+            NO_STATIC_LIBS,
+            NO_WRAP_ROUTINES,
+        )
+        .unwrap();
+    let dir = std::env::temp_dir().join(format!("linkerloader_elf_le_{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("a.elf").to_str().unwrap().to_owned();
+    Librarian::new(false)
+        .write_output(&out, &path, OutputFormat::Elf)
+        .unwrap();
+    let elf = fs::read(&path).unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+    // 32-bit, little endian
+    assert_eq!(&[0x7F, b'E', b'L', b'F', 1, 1], &elf[..6]);
+    let text_start = out.segments[&SegmentName::TEXT].segment_start as u32;
+    assert_eq!(
+        text_start,
+        u32::from_le_bytes(elf[24..28].try_into().unwrap())
+    ); // e_entry
+}
+
+#[test]
+fn rename_symbols() {
+    let testdir = tests_base_loc("rename_symbols");
+    let mut editor = LinkerEditor::new(0x10, 0x0, 0x0, false);
+    editor.rename_symbols(HashMap::from([(symbol!("foo"), symbol!("bar"))]));
+    match editor.link(
+        read_objects_from_dir(&testdir),
+        NO_STATIC_LIBS,
+        NO_WRAP_ROUTINES,
+    ) {
+        Ok((out, info)) => {
+            assert_eq!(Ok(()), out.verify());
+            assert!(!info.global_symtable.contains_key(&symbol!("foo")));
+            let gsym = &info.global_symtable[&symbol!("bar")];
+            assert_eq!(Some(0x10), gsym.address());
+            assert!(gsym.refs.contains_key("mod_2"));
+            assert_eq!(symbol!("bar"), info.symbol_tables["mod_2"][1].st_name);
+            // mod_2's reference to foo is now one to bar in mod_1
+            let obj_code_text = out.object_data.get(&SegmentName::TEXT).unwrap();
+            assert_eq!(
+                0x10,
+                x_to_i4(&obj_code_text.get_at(0x4, 0x4).unwrap()).unwrap()
+            );
+        }
+        Err(e) => panic!("{testdir} {e:?}"),
+    }
+    // main is defined in mod_2 already
+    let mut editor = LinkerEditor::new(0x10, 0x0, 0x0, false);
+    editor.rename_symbols(HashMap::from([(symbol!("foo"), symbol!("main"))]));
+    match editor.link(
+        read_objects_from_dir(&testdir),
+        NO_STATIC_LIBS,
+        NO_WRAP_ROUTINES,
+    ) {
+        Err(e) => assert_eq!(
+            LinkError::RenamedSymbolNameAlreadyExists(symbol!("main")),
+            e
+        ),
+        Ok(_) => panic!("{testdir}: 'foo' renamed onto 'main'"),
+    }
+}
+
+#[test]
+fn link_script() {
+    let text = fs::read_to_string(tests_base_loc("link_script/link.ld")).unwrap();
+    let script = LinkScript::parse(&text).unwrap();
+    assert_eq!(
+        LinkScript {
+            text_start: Some(0x1000),
+            data_align: Some(0x100),
+            bss_align: Some(0x4),
+            entry: Some(symbol!("main")),
+        },
+        script
+    );
+    let testdir = tests_base_loc("rename_symbols");
+    let mut editor = LinkerEditor::from_script(&script);
+    let (out, info) = editor
+        .link(
+            read_objects_from_dir(&testdir),
+            NO_STATIC_LIBS,
+            NO_WRAP_ROUTINES,
+        )
+        .unwrap();
+    let mut editor = LinkerEditor::new(0x1000, 0x100, 0x4, false);
+    let (expected, _) = editor
+        .link(
+            read_objects_from_dir(&testdir),
+            NO_STATIC_LIBS,
+            NO_WRAP_ROUTINES,
+        )
+        .unwrap();
+    assert_eq!(expected.segments, out.segments);
+    assert_eq!(0x1100, out.segments[&SegmentName::DATA].segment_start);
+    // main is in mod_2, after mod_1's text
+    assert_eq!(Some(0x1004), info.entry_addr);
+
+    assert_eq!(
+        Err(ParseError::InvalidLinkScript(2)),
+        LinkScript::parse("text_start = 0x10;\ndata_align = 0x;")
+    );
+    assert_eq!(
+        Err(ParseError::InvalidLinkScript(1)),
+        LinkScript::parse("text_start = 0x10")
+    );
+    assert_eq!(
+        Err(ParseError::InvalidLinkScript(1)),
+        LinkScript::parse("stack_size = 0x1000;")
+    );
+    // alignments are 0 or a power of two
+    assert_eq!(
+        Err(ParseError::InvalidLinkScript(2)),
+        LinkScript::parse(
+            "data_align = 0x10;
+bss_align = -4;"
+        )
+    );
+    assert_eq!(
+        Err(ParseError::InvalidLinkScript(1)),
+        LinkScript::parse("data_align = 0x64;")
+    );
+}
+
+#[test]
+fn shared_dependencies() {
+    let testdir = tests_base_loc("shared_dependencies");
+    let libs = vec![
+        read_lib(&format!("{testdir}/libc/stublib")).unwrap(),
+        read_lib(&format!("{testdir}/libm/stublib")).unwrap(),
+    ];
+    let mut editor = LinkerEditor::new(0x10, 0x0, 0x0, false);
+    match editor.link(
+        read_objects(&testdir, ["mod_1"]).unwrap(),
+        libs,
+        NO_WRAP_ROUTINES,
+    ) {
+        Ok((out, info)) => {
+            assert_eq!(Ok(()), out.verify());
+            assert_eq!(
+                vec!["libc".to_string(), "libm".to_string()],
+                info.shared_dependencies()
+            );
+            // the names go in .lib, after .data (there is no .bss)
+            let lib = &out.segments[&SegmentName::LIB];
+            assert_eq!(0x1C, lib.segment_start);
+            assert_eq!(
+                b"libc\0libm\0",
+                out.object_data[&SegmentName::LIB].as_slice()
+            );
+            assert_eq!(
+                Some(&DefnProvenance::FromLibSegment),
+                info.symbol_provenance(&symbol!("_SHARED_LIBRARIES"))
+            );
+            assert_eq!(
+                Some(0x1C),
+                info.global_symtable[&symbol!("_SHARED_LIBRARIES")].address()
+            );
+        }
+        Err(e) => panic!("{testdir} {e:?}"),
+    }
+    // no stubs, no .lib
+    let mut editor = LinkerEditor::new(0x10, 0x0, 0x0, false);
+    let (out, info) = editor
+        .link(
+            read_objects_from_dir(&tests_base_loc("rename_symbols")),
+            NO_STATIC_LIBS,
+            NO_WRAP_ROUTINES,
+        )
+        .unwrap();
+    assert!(info.shared_dependencies().is_empty());
+    assert!(!out.segments.contains_key(&SegmentName::LIB));
+}
+
+#[test]
+fn relocation_displacement_overflow() {
+    for rel_type in ["r4", "rs4", "gr4"] {
+        let testdir = tests_base_loc(&format!("relocation_displacement_overflow/{rel_type}"));
+        let mut editor = LinkerEditor::new(0x10, 0x0, 0x0, false);
+        // GR4 is relative to the GOT, but needs no slots in it
+        editor.set_got_mode(GotMode::Force);
+        match editor.link(
+            read_objects_from_dir(&testdir),
+            NO_STATIC_LIBS,
+            NO_WRAP_ROUTINES,
+        ) {
+            Err(e) => assert_eq!(
+                LinkError::RelocationDisplacementOverflow {
+                    seg: SegmentName::TEXT,
+                    loc: 0x4
+                },
+                e
+            ),
+            Ok(_) => panic!("{testdir}: the addend puts the target out of range"),
+        }
+    }
+}
+
+#[test]
+fn relocation_address_overflow() {
+    for rel_type in ["as4", "er4"] {
+        let testdir = tests_base_loc(&format!("relocation_address_overflow/{rel_type}"));
+        let mut editor = LinkerEditor::new(0x10, 0x0, 0x0, false);
+        match editor.link(
+            read_objects_from_dir(&testdir),
+            NO_STATIC_LIBS,
+            NO_WRAP_ROUTINES,
+        ) {
+            Err(e) => assert_eq!(LinkError::AddressOverflowError, e),
+            Ok(_) => panic!("{testdir}: the addend puts the address out of range"),
+        }
+    }
+}
+
+#[test]
+fn relocation_target_segment_missing() {
+    let testdir = tests_base_loc("relocation_target_segment_missing");
+    let mut objects = read_objects_from_dir(&testdir);
+    // drop .data, leaving its relocation dangling
+    let obj = objects.get_mut("mod_1").unwrap();
+    obj.segments.pop();
+    obj.object_data.pop();
+    obj.nsegs = 1;
+    let mut editor = LinkerEditor::new(0x10, 0x0, 0x0, false);
+    match editor.link(objects, NO_STATIC_LIBS, NO_WRAP_ROUTINES) {
+        Err(e) => assert_eq!(
+            LinkError::RelocationTargetSegmentMissing(SegmentName::DATA),
+            e
+        ),
+        Ok(_) => panic!("{testdir}: unexpected Ok"),
+    }
+}
+
+#[test]
+fn pad_segment() {
+    let testdir = tests_base_loc("link_1");
+    let mut editor = LinkerEditor::new(0x10, 0x0, 0x0, false);
+    editor.pad_segment(SegmentName::TEXT, 0x100, 0xFF);
+    match editor.link(
+        read_objects_from_dir(&testdir),
+        NO_STATIC_LIBS,
+        NO_WRAP_ROUTINES,
+    ) {
+        Ok((out, info)) => {
+            assert_eq!(Ok(()), out.verify());
+            let text = &out.segments[&SegmentName::TEXT];
+            assert_eq!(0x100, text.segment_len);
+            // the modules' 0x3C bytes, then the fill
+            let text_data = &out.object_data[&SegmentName::TEXT];
+            assert_eq!(
+                Some(&[0xFF; 0x100 - 0x3C][..]),
+                text_data.get_at(0x3C, 0x100 - 0x3C).as_deref()
+            );
+            let data_start = out.segments[&SegmentName::DATA].segment_start;
+            assert!(data_start >= 0x10 + 0x100, "{data_start:X}");
+            for addrs in info.segment_mapping.values() {
+                assert!(addrs[&SegmentName::DATA] >= data_start);
+            }
+        }
+        Err(e) => panic!("{testdir} {e:?}"),
+    }
+    // TEXT is 0x3C bytes already
+    let mut editor = LinkerEditor::new(0x10, 0x0, 0x0, false);
+    editor.pad_segment(SegmentName::TEXT, 0x20, 0x0);
+    assert_eq!(
+        Err(LinkError::SegmentExceedsPadding(SegmentName::TEXT)),
+        editor
+            .link(
+                read_objects_from_dir(&testdir),
+                NO_STATIC_LIBS,
+                NO_WRAP_ROUTINES,
+            )
+            .map(|_| ())
+    );
+}
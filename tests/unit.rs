@@ -1,6 +1,20 @@
 mod tests {
-    use linkerloader::types::object::MAGIC_NUMBER;
+    use std::collections::BTreeMap;
+
+    use linkerloader::common::Alignment;
+    use linkerloader::linker::editor::{GotPlacement, LinkerEditor, LinkerInfo};
+    use linkerloader::logger::Logger;
+    use linkerloader::types::errors::{LinkError, ParseError};
+    use linkerloader::types::object::{parse_object_file, MAGIC_NUMBER};
+    use linkerloader::types::out::ObjectOut;
+    use linkerloader::types::relocation::{RelRef, RelType, Relocation};
+    use linkerloader::types::segment::{
+        parse_segment_data, parse_segment_data_into, Endianness, FileSegmentData, Segment,
+        SegmentBytes, SegmentData, SegmentName,
+    };
+    use linkerloader::types::symbol_table::SymbolName;
     use linkerloader::utils::{find_seg_start, mk_addr_4, mk_i_4, x_to_i4};
+    use linkerloader::{symbol, wrapped_symbol};
 
     #[test]
     fn test_magic_number() {
@@ -20,6 +34,38 @@ mod tests {
         assert_eq!(find_seg_start(0x64, 0x10), 0x70);
     }
 
+    #[test]
+    fn test_alignment() {
+        assert_eq!(Ok(Alignment::None), Alignment::try_from(0));
+        assert_eq!(Ok(Alignment::Bytes(0x10)), Alignment::try_from(0x10));
+        for n in [-0x10, -1, 0x3, 0x64] {
+            assert_eq!(Err(LinkError::InvalidAlignment(n)), Alignment::try_from(n));
+        }
+        assert_eq!(0x15B, Alignment::None.align(0x15B));
+        assert_eq!(0x15C, Alignment::Bytes(0x4).align(0x15B));
+        assert_eq!(0x80, Alignment::Bytes(0x10).align(0x80));
+        assert_eq!(0x0, Alignment::Bytes(0x10).align(0x0));
+        assert!(Alignment::None.is_valid());
+        assert!(Alignment::Bytes(0x1).is_valid());
+        assert!(!Alignment::Bytes(0x0).is_valid());
+        assert!(!Alignment::Bytes(0x6).is_valid());
+        let mut editor = LinkerEditor::new(0x0, 0x0, 0x0, true);
+        assert_eq!(
+            Ok(()),
+            editor.set_data_start_boundary(Alignment::Bytes(0x8))
+        );
+        assert_eq!(
+            Err(LinkError::InvalidAlignment(0)),
+            editor.set_bss_start_boundary(Alignment::Bytes(0x0))
+        );
+        assert_eq!(
+            Err(LinkError::InvalidAlignment(-8)),
+            editor.set_got_layout(GotPlacement::AfterText, -8)
+        );
+        assert_eq!("none", Alignment::None.to_string());
+        assert_eq!("100", Alignment::Bytes(0x100).to_string());
+    }
+
     #[test]
     fn test_mk_addr_4() {
         assert!(mk_addr_4(0xFFFFFFFF + 0x1).is_none());
@@ -47,13 +93,13 @@ mod tests {
 
     #[test]
     fn test_x_to_i4() {
-        assert!(x_to_i4(&vec![255, 255, 255]).is_none());
-        assert!(x_to_i4(&vec![255, 255]).is_none());
-        assert!(x_to_i4(&vec![255]).is_none());
-        assert!(x_to_i4(&vec![]).is_none());
+        assert!(x_to_i4(&[255, 255, 255]).is_none());
+        assert!(x_to_i4(&[255, 255]).is_none());
+        assert!(x_to_i4(&[255]).is_none());
+        assert!(x_to_i4(&[]).is_none());
 
-        assert_eq!(-1, x_to_i4(&vec![255, 255, 255, 255]).unwrap());
-        assert_eq!(0, x_to_i4(&vec![0, 0, 0, 0]).unwrap());
+        assert_eq!(-1, x_to_i4(&[255, 255, 255, 255]).unwrap());
+        assert_eq!(0, x_to_i4(&[0, 0, 0, 0]).unwrap());
     }
 
     #[test]
@@ -62,4 +108,458 @@ mod tests {
             assert_eq!(i, x_to_i4(&mk_i_4(i)).unwrap());
         }
     }
+
+    #[test]
+    fn test_segment_name_from_str() {
+        assert_eq!(Ok(SegmentName::GOT), ".got".parse::<SegmentName>());
+        for name in SegmentName::order() {
+            assert_eq!(Ok(name.clone()), name.to_string().parse::<SegmentName>());
+        }
+        assert_eq!(
+            Err(ParseError::InvalidSegmentName),
+            ".comment".parse::<SegmentName>()
+        );
+    }
+
+    #[test]
+    fn test_logger_no_color() {
+        let mut logger = Logger::new_test_logger(false);
+        logger.color = false;
+        logger.info("linking mod_1");
+        logger.debug("done");
+        let lines: Vec<&str> = logger.entries().iter().map(|(_, l)| l.as_str()).collect();
+        assert_eq!(vec!["[INFO]: linking mod_1", "[DEBUG]: done"], lines);
+        assert!(lines.iter().all(|l| !l.contains('\x1b')));
+    }
+
+    #[test]
+    fn test_segment_data_i32_big_endian() {
+        let mut sd = SegmentData::new(8);
+        assert_eq!(Some(()), sd.write_i32(2, 0x11223344, Endianness::BigEndian));
+        assert_eq!(
+            Some(&[0x11, 0x22, 0x33, 0x44][..]),
+            sd.get_at(2, 4).as_deref()
+        );
+        assert_eq!(Some(0x11223344), sd.read_i32(2, Endianness::BigEndian));
+        assert_eq!(Some(()), sd.write_i32(4, -2, Endianness::BigEndian));
+        assert_eq!(Some(-2), sd.read_i32(4, Endianness::BigEndian));
+        assert!(sd.read_i32(5, Endianness::BigEndian).is_none());
+        assert!(sd.write_i32(5, 1, Endianness::BigEndian).is_none());
+        assert!(sd.read_i32(usize::MAX, Endianness::BigEndian).is_none());
+        assert_eq!(8, sd.len());
+    }
+
+    #[test]
+    fn test_segment_data_i32_little_endian() {
+        let mut sd = SegmentData::new(8);
+        assert_eq!(
+            Some(()),
+            sd.write_i32(2, 0x11223344, Endianness::LittleEndian)
+        );
+        assert_eq!(
+            Some(&[0x44, 0x33, 0x22, 0x11][..]),
+            sd.get_at(2, 4).as_deref()
+        );
+        assert_eq!(Some(0x11223344), sd.read_i32(2, Endianness::LittleEndian));
+        assert_eq!(Some(0x44332211), sd.read_i32(2, Endianness::BigEndian));
+        assert_eq!(Some(()), sd.write_u16(0, 0xABCD, Endianness::LittleEndian));
+        assert_eq!(Some(&[0xCD, 0xAB][..]), sd.get_at(0, 2).as_deref());
+        assert!(sd.read_i32(6, Endianness::LittleEndian).is_none());
+        assert!(sd.write_u16(7, 1, Endianness::LittleEndian).is_none());
+        assert_eq!(8, sd.len());
+    }
+
+    #[test]
+    fn test_segment_data_hexdump() {
+        let mut sd = SegmentData::new(20);
+        sd.write_bytes(0, &[0xDE, 0xAD, 0xBE, 0xEF]).unwrap();
+        sd.write_bytes(4, b"Hello, linker").unwrap();
+        assert_eq!(
+            "00000010: DEAD BEEF 4865 6C6C 6F2C 206C 696E 6B65  ....Hello, linke\n\
+             00000020: 7200 0000                                r...",
+            sd.hexdump(0x10)
+        );
+        assert_eq!("", SegmentData::new(0).hexdump(0x10));
+    }
+
+    #[test]
+    fn test_segment_data_fill() {
+        let mut sd = SegmentData::new(8);
+        assert_eq!(Ok(()), sd.fill(2, 4, 0x90));
+        assert_eq!(&[0, 0, 0x90, 0x90, 0x90, 0x90, 0, 0], &sd[..]);
+        assert_eq!(Ok(()), sd.fill(8, 0, 0xFF));
+        assert_eq!(
+            Err(LinkError::SegmentDataOutOfRange { start: 6, len: 4 }),
+            sd.fill(6, 4, 0xFF)
+        );
+        assert_eq!(
+            Err(LinkError::SegmentDataOutOfRange {
+                start: usize::MAX,
+                len: 1
+            }),
+            sd.fill(usize::MAX, 1, 0xFF)
+        );
+        assert_eq!(&[0, 0, 0x90, 0x90, 0x90, 0x90, 0, 0], &sd[..]);
+    }
+
+    #[test]
+    fn test_segment_data_splice() {
+        let mut sd = SegmentData::new(4);
+        sd.write_bytes(0, &[1, 2, 3, 4]).unwrap();
+        // grows
+        assert_eq!(Ok(()), sd.splice(1, 1, &[0xA, 0xB, 0xC]));
+        assert_eq!(&[1, 0xA, 0xB, 0xC, 3, 4], &sd[..]);
+        // shrinks
+        assert_eq!(Ok(()), sd.splice(0, 4, &[0xF]));
+        assert_eq!(&[0xF, 3, 4], &sd[..]);
+        // appends at the end
+        assert_eq!(Ok(()), sd.splice(3, 0, &[5, 6]));
+        assert_eq!(&[0xF, 3, 4, 5, 6], &sd[..]);
+        assert_eq!(
+            Err(LinkError::SegmentDataOutOfRange { start: 4, len: 2 }),
+            sd.splice(4, 2, &[])
+        );
+        assert_eq!(5, sd.len());
+    }
+
+    #[test]
+    fn test_segment_data_crc32() {
+        let mut sd = SegmentData::new(9);
+        sd.write_bytes(0, b"123456789").unwrap();
+        assert_eq!(0xCBF43926, sd.crc32());
+        assert_eq!(0, SegmentData::new(0).crc32());
+        assert_eq!(0x2144DF1C, SegmentData::new(4).crc32());
+    }
+
+    #[test]
+    fn test_file_segment_data_large() {
+        let path =
+            std::env::temp_dir().join(format!("linkerloader_segment_{}", std::process::id()));
+        // 512MB, only the touched windows are ever read
+        let len = 512 * 1024 * 1024;
+        let mut sd = FileSegmentData::create(&path, len).unwrap();
+        assert_eq!(len, sd.len());
+        assert_eq!(Some(&[0, 0, 0, 0][..]), sd.get_at(len - 4, 4).as_deref());
+        assert_eq!(Ok(()), sd.update(len - 4, 4, vec![0xDE, 0xAD, 0xBE, 0xEF]));
+        assert_eq!(
+            Some(&[0xAD, 0xBE, 0xEF][..]),
+            sd.get_at(len - 3, 3).as_deref()
+        );
+        assert_eq!(Some(()), sd.write_i32(0x1000, -2, Endianness::BigEndian));
+        assert_eq!(Some(-2), sd.read_i32(0x1000, Endianness::BigEndian));
+        assert!(sd.get_at(len - 3, 4).is_none());
+        assert!(sd.write_i32(len - 2, 1, Endianness::BigEndian).is_none());
+        assert_eq!(
+            Err(LinkError::SegmentDataOutOfRange {
+                start: len - 2,
+                len: 4
+            }),
+            sd.update(len - 2, 4, vec![0; 4])
+        );
+        drop(sd);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    // sd holds DE AD BE EF 00 01
+    fn check_segment_bytes_update(sd: &mut impl SegmentBytes) {
+        assert_eq!(Ok(()), sd.update(1, 1, vec![0x1, 0x2, 0x3]));
+        assert_eq!(8, sd.len());
+        assert_eq!(
+            Some(&[0xDE, 0x1, 0x2, 0x3, 0xBE, 0xEF, 0x0, 0x1][..]),
+            sd.get_at(0, 8).as_deref()
+        );
+        assert_eq!(Ok(()), sd.update(0, 4, vec![0x9]));
+        assert_eq!(
+            Some(&[0x9, 0xBE, 0xEF, 0x0, 0x1][..]),
+            sd.get_at(0, 5).as_deref()
+        );
+        assert!(sd.get_at(0, 6).is_none());
+        assert_eq!(
+            Err(LinkError::SegmentDataOutOfRange { start: 4, len: 2 }),
+            sd.update(4, 2, vec![])
+        );
+    }
+
+    #[test]
+    fn test_parse_segment_data_tokens() {
+        let bytes = [0xDE, 0xAD, 0xBE, 0xEF];
+        for s in ["DE AD BE EF", "DEADBEEF", "  DEADBEEF ", "de ad be ef"] {
+            assert_eq!(
+                &bytes,
+                &*parse_segment_data(4, s).unwrap().get_at(0, 4).unwrap()
+            );
+        }
+        assert_eq!(
+            &[0xA, 0x1],
+            &*parse_segment_data(2, "A 1").unwrap().get_at(0, 2).unwrap()
+        );
+        // only a line of one token is contiguous
+        for s in ["DEAD BE EF", "00DE AD", "ABC", "ABC DE"] {
+            assert_eq!(
+                Err(ParseError::InvalidObjectData),
+                parse_segment_data(2, s),
+                "{s}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_segment_bytes_update() {
+        let hex = "DE AD BE EF 00 01";
+        check_segment_bytes_update(&mut parse_segment_data(6, hex).unwrap());
+        let path =
+            std::env::temp_dir().join(format!("linkerloader_segment_upd_{}", std::process::id()));
+        let mut sd = FileSegmentData::create(&path, 6).unwrap();
+        assert_eq!(Ok(()), parse_segment_data_into(&mut sd, hex));
+        check_segment_bytes_update(&mut sd);
+        assert_eq!(
+            Err(ParseError::SegmentDataLengthMismatch),
+            parse_segment_data_into(&mut sd, "DEADBEEF")
+        );
+        // the tail is moved a window at a time
+        let len = 200 * 1024;
+        let mut sd = FileSegmentData::create(&path, len).unwrap();
+        sd.write_i32(len - 4, 0x11223344, Endianness::BigEndian)
+            .unwrap();
+        assert_eq!(Ok(()), sd.update(0, 0, vec![0xFF]));
+        assert_eq!(
+            Some(0x11223344),
+            sd.read_i32(len - 3, Endianness::BigEndian)
+        );
+        assert_eq!(Some(&[0xFF, 0x0][..]), sd.get_at(0, 2).as_deref());
+        assert_eq!(Ok(()), sd.update(0, 3, vec![]));
+        assert_eq!(len - 2, sd.len());
+        assert_eq!(
+            Some(0x11223344),
+            sd.read_i32(len - 6, Endianness::BigEndian)
+        );
+        drop(sd);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_file_segment_data_from_hex() {
+        let path =
+            std::env::temp_dir().join(format!("linkerloader_segment_hex_{}", std::process::id()));
+        let hex: &[u8] = b"DE AD BE EF 00 01\nnot data";
+        let sd = FileSegmentData::from_hex(&path, 6, hex).unwrap();
+        assert_eq!(
+            Some(&[0xBE, 0xEF, 0x00, 0x01][..]),
+            sd.get_at(2, 4).as_deref()
+        );
+        assert_eq!(
+            Some(ParseError::SegmentDataLengthMismatch),
+            FileSegmentData::from_hex(&path, 8, hex).err()
+        );
+        assert_eq!(
+            Some(ParseError::InvalidObjectData),
+            FileSegmentData::from_hex(&path, 2, &b"DE XX"[..]).err()
+        );
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    // Module "m" linked with .text at 0x10, .data at 0x20 and an 8 byte GOT
+    // at 0x40. foo is at .data+4, abs at 0x12345678.
+    fn reloc_setup() -> (LinkerEditor, LinkerInfo, ObjectOut) {
+        let obj = parse_object_file(String::from(
+            "LINK\n\
+             2 2 0\n\
+             .text 0 8 RP\n\
+             .data 8 4 RWP\n\
+             foo 4 2 D L\n\
+             abs 12345678 0 D L\n\
+             00 00 00 04 00 00 00 08\n\
+             00 00 00 00\n",
+        ))
+        .unwrap();
+        let mut out = ObjectOut::new();
+        for (i, (name, start)) in [(SegmentName::TEXT, 0x10), (SegmentName::DATA, 0x20)]
+            .into_iter()
+            .enumerate()
+        {
+            let mut seg = obj.segments[i].clone();
+            seg.segment_start = start;
+            out.segments.insert(name.clone(), seg);
+            out.object_data.insert(name, obj.object_data[i].clone());
+        }
+        let mut got = Segment::new(SegmentName::GOT);
+        got.segment_start = 0x40;
+        got.segment_len = 8;
+        out.segments.insert(SegmentName::GOT, got);
+        out.object_data
+            .insert(SegmentName::GOT, SegmentData::new(8));
+        let mut info = LinkerInfo::new();
+        info.segment_mapping.insert(
+            String::from("m"),
+            BTreeMap::from([(SegmentName::TEXT, 0x10), (SegmentName::DATA, 0x20)]),
+        );
+        let mut editor = LinkerEditor::new(0x10, 0x0, 0x0, true);
+        editor.session_objects.insert(String::from("m"), obj);
+        (editor, info, out)
+    }
+
+    fn text_reloc(rel_loc: i32, rel_ref: RelRef, rel_type: RelType) -> Relocation {
+        Relocation {
+            rel_loc,
+            rel_seg: SegmentName::TEXT,
+            rel_ref,
+            rel_type,
+            rel_extra: vec![],
+        }
+    }
+
+    fn word_at(out: &ObjectOut, segment_name: &SegmentName, off: usize) -> i32 {
+        out.object_data[segment_name]
+            .read_i32(off, Endianness::BigEndian)
+            .unwrap()
+    }
+
+    fn er4_at(rel_loc: i32) -> Option<Relocation> {
+        Some(text_reloc(rel_loc, RelRef::NoRef, RelType::ER4))
+    }
+
+    #[test]
+    fn test_apply_a4() {
+        let (mut editor, info, mut out) = reloc_setup();
+        // .data + 4 (already at loc)
+        let r = text_reloc(0x0, RelRef::SegmentRef(1), RelType::A4);
+        assert_eq!(Ok(er4_at(0x0)), editor.apply_a4(&mut out, &info, "m", &r));
+        assert_eq!(0x24, word_at(&out, &SegmentName::TEXT, 0x0));
+    }
+
+    #[test]
+    fn test_apply_r4() {
+        let (mut editor, info, mut out) = reloc_setup();
+        let r = text_reloc(0x4, RelRef::SegmentRef(1), RelType::R4);
+        assert_eq!(Ok(None), editor.apply_r4(&mut out, &info, "m", &r));
+        // 0x10 + 0x4 + 4 - 0x20 + 0x8
+        assert_eq!(0x0, word_at(&out, &SegmentName::TEXT, 0x4));
+    }
+
+    #[test]
+    fn test_apply_as4_rs4() {
+        let (mut editor, info, mut out) = reloc_setup();
+        let r = text_reloc(0x0, RelRef::SymbolRef(0), RelType::AS4);
+        assert_eq!(Ok(er4_at(0x0)), editor.apply_as4(&mut out, &info, "m", &r));
+        // foo + 4
+        assert_eq!(0x28, word_at(&out, &SegmentName::TEXT, 0x0));
+        let r = text_reloc(0x4, RelRef::SymbolRef(0), RelType::RS4);
+        assert_eq!(Ok(None), editor.apply_rs4(&mut out, &info, "m", &r));
+        // 0x10 + 4 - foo + 0x8
+        assert_eq!(-0x8, word_at(&out, &SegmentName::TEXT, 0x4));
+    }
+
+    #[test]
+    fn test_apply_u2_l2() {
+        let (mut editor, info, mut out) = reloc_setup();
+        let r = text_reloc(0x0, RelRef::SymbolRef(1), RelType::U2);
+        assert_eq!(Ok(None), editor.apply_u2_l2(&mut out, &info, "m", &r));
+        let r = text_reloc(0x2, RelRef::SymbolRef(1), RelType::L2);
+        assert_eq!(Ok(None), editor.apply_u2_l2(&mut out, &info, "m", &r));
+        assert_eq!(0x12345678, word_at(&out, &SegmentName::TEXT, 0x0));
+    }
+
+    #[test]
+    fn test_apply_got_relocations() {
+        let (mut editor, info, mut out) = reloc_setup();
+        // distance from loc to the GOT
+        let r = text_reloc(0x4, RelRef::NoRef, RelType::GA4);
+        assert_eq!(Ok(None), editor.apply_ga4(&mut out, &info, "m", &r));
+        assert_eq!(0x2C, word_at(&out, &SegmentName::TEXT, 0x4));
+        // foo, then .data, go in the next GOT slots
+        let mut got_offset = 0;
+        let r = text_reloc(0x0, RelRef::SymbolRef(0), RelType::GP4);
+        assert_eq!(
+            Ok(None),
+            editor.apply_gp4(&mut out, &info, "m", &r, &mut got_offset)
+        );
+        let r = text_reloc(0x4, RelRef::SegmentRef(1), RelType::GPS4);
+        assert_eq!(
+            Ok(None),
+            editor.apply_gps4(&mut out, &info, "m", &r, &mut got_offset)
+        );
+        assert_eq!(8, got_offset);
+        assert_eq!(0x0, word_at(&out, &SegmentName::TEXT, 0x0));
+        assert_eq!(0x4, word_at(&out, &SegmentName::TEXT, 0x4));
+        assert_eq!(0x24, word_at(&out, &SegmentName::GOT, 0x0));
+        assert_eq!(0x20, word_at(&out, &SegmentName::GOT, 0x4));
+        // .data + 4, relative to the GOT
+        let (mut editor, info, mut out) = reloc_setup();
+        let r = text_reloc(0x0, RelRef::SegmentRef(1), RelType::GR4);
+        assert_eq!(Ok(None), editor.apply_gr4(&mut out, &info, "m", &r));
+        assert_eq!(-0x1C, word_at(&out, &SegmentName::TEXT, 0x0));
+    }
+
+    #[test]
+    fn test_apply_er4() {
+        let (mut editor, info, mut out) = reloc_setup();
+        let r = text_reloc(0x4, RelRef::NoRef, RelType::ER4);
+        assert_eq!(Ok(None), editor.apply_er4(&mut out, &info, "m", &r));
+        // plus text_start
+        assert_eq!(0x18, word_at(&out, &SegmentName::TEXT, 0x4));
+        let r = Relocation {
+            rel_seg: SegmentName::BSS,
+            ..r
+        };
+        assert_eq!(
+            Err(LinkError::RelocationTargetSegmentMissing(SegmentName::BSS)),
+            editor.apply_er4(&mut out, &info, "m", &r)
+        );
+    }
+
+    #[test]
+    fn test_symbol_name_same_base() {
+        // foo and wrap_foo are different symbols around the same base name
+        assert_ne!(symbol!("foo"), wrapped_symbol!("foo"));
+        assert!(symbol!("foo").same_base(&wrapped_symbol!("foo")));
+        assert!(wrapped_symbol!("foo").same_base(&symbol!("foo")));
+        assert!(symbol!("foo").same_base(&symbol!("foo")));
+        assert!(!symbol!("foo").same_base(&symbol!("bar")));
+        assert_eq!("foo", wrapped_symbol!("foo").base());
+        // an object's own wrap_foo is spelled like the wrapped foo, but is
+        // neither equal to it nor around the same base name
+        let spelled = symbol!("wrap_foo");
+        assert_eq!(spelled.to_string(), wrapped_symbol!("foo").to_string());
+        assert_ne!(spelled, wrapped_symbol!("foo"));
+        assert!(!spelled.same_base(&wrapped_symbol!("foo")));
+    }
+
+    #[test]
+    fn test_segment_ordinal() {
+        let obj = parse_object_file(String::from(
+            "LINK\n\
+             3 1 1\n\
+             .text 0 8 RP\n\
+             .data 8 4 RWP\n\
+             .bss C 4 RW\n\
+             foo 0 2 D\n\
+             0 2 1 A4\n\
+             00 00 00 00 00 00 00 00\n\
+             00 00 00 00\n\
+             00 00 00 00\n",
+        ))
+        .unwrap();
+        for (ordinal, name) in [
+            (1, SegmentName::TEXT),
+            (2, SegmentName::DATA),
+            (3, SegmentName::BSS),
+        ] {
+            assert_eq!(Some(ordinal), obj.segment_ordinal(&name));
+            assert_eq!(
+                Some(&name),
+                obj.segment_by_ordinal(ordinal).map(|s| &s.segment_name)
+            );
+        }
+        assert_eq!(None, obj.segment_ordinal(&SegmentName::GOT));
+        assert_eq!(None, obj.segment_by_ordinal(0));
+        assert_eq!(None, obj.segment_by_ordinal(4));
+        // symbols and relocations number segments the same way
+        let foo = &obj.symbol_table[0];
+        assert_eq!(
+            Some(&SegmentName::DATA),
+            obj.segment_by_ordinal(foo.st_seg).map(|s| &s.segment_name)
+        );
+        assert_eq!(Some(2), obj.segment_ordinal(&obj.relocations[0].rel_seg));
+        assert!(obj.ppr(true).contains("\n0 2 1 A4\n"));
+    }
 }